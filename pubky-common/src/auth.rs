@@ -1,6 +1,10 @@
 //! Client-server Authentication using signed timesteps
 
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::BTreeSet,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
 
 use serde::{Deserialize, Serialize};
 
@@ -11,13 +15,13 @@ use crate::{
     timestamp::Timestamp,
 };
 
-// 30 seconds
-const TIME_INTERVAL: u64 = 30 * 1_000_000;
-
 const CURRENT_VERSION: u8 = 0;
 // 45 seconds in the past or the future
 const TIMESTAMP_WINDOW: i64 = 45 * 1_000_000;
 
+/// Default maximum number of token IDs kept by a [MemoryReplayCache].
+pub const DEFAULT_REPLAY_CACHE_CAPACITY: usize = 1_000_000;
+
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct AuthToken {
     /// Signature over the token.
@@ -29,7 +33,7 @@ pub struct AuthToken {
     ///
     /// Version 0:
     /// - Signer is implicitly the same as the root keypair for
-    ///     the [AuthToken::pubky], without any delegation.
+    ///   the [AuthToken::pubky], without any delegation.
     /// - Capabilities are only meant for resoucres on the homeserver.
     version: u8,
     /// Timestamp
@@ -65,6 +69,11 @@ impl AuthToken {
     }
 
     pub fn verify(bytes: &[u8]) -> Result<Self, Error> {
+        Self::verify_at(bytes, Timestamp::now())
+    }
+
+    /// Verify the token as if the current time was `now`.
+    fn verify_at(bytes: &[u8], now: Timestamp) -> Result<Self, Error> {
         if bytes[75] > CURRENT_VERSION {
             return Err(Error::UnknownVersion);
         }
@@ -73,8 +82,6 @@ impl AuthToken {
 
         match token.version {
             0 => {
-                let now = Timestamp::now();
-
                 // Chcek timestamp;
                 let diff = token.timestamp.as_u64() as i64 - now.as_u64() as i64;
                 if diff > TIMESTAMP_WINDOW {
@@ -89,6 +96,10 @@ impl AuthToken {
                     .verify(AuthToken::signable(token.version, bytes), &token.signature)
                     .map_err(|_| Error::InvalidSignature)?;
 
+                for capability in token.capabilities() {
+                    capability.validate()?;
+                }
+
                 Ok(token)
             }
             _ => unreachable!(),
@@ -126,44 +137,101 @@ impl AuthToken {
     }
 }
 
-#[derive(Debug, Clone, Default)]
-/// Keeps track of used AuthToken until they expire.
-pub struct AuthVerifier {
-    seen: Arc<Mutex<Vec<Box<[u8]>>>>,
+/// Storage of the IDs of used [AuthToken]s, to reject replays.
+///
+/// IDs start with the big-endian bytes of the token's [Timestamp],
+/// so they are ordered by time.
+///
+/// Implementations shared between multiple verifiers (for example backed by a database)
+/// protect all of them against replays of the same token.
+pub trait ReplayCache: Debug + Send + Sync {
+    /// Insert a token `id`, returning `false` if it was already present.
+    ///
+    /// IDs of tokens with a timestamp older than `expired_before` can't be replayed anymore,
+    /// and are evicted to make room once the cache is full, returning
+    /// [Error::ReplayCacheFull] only if it is still full.
+    fn insert(&self, id: &[u8], expired_before: &Timestamp) -> Result<bool, Error>;
 }
 
-impl AuthVerifier {
-    pub fn verify(&self, bytes: &[u8]) -> Result<AuthToken, Error> {
-        self.gc();
+/// In memory [ReplayCache], holding at most `capacity` token IDs.
+#[derive(Debug)]
+pub struct MemoryReplayCache {
+    seen: Mutex<BTreeSet<Box<[u8]>>>,
+    capacity: usize,
+}
+
+impl MemoryReplayCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            seen: Mutex::new(BTreeSet::new()),
+            capacity,
+        }
+    }
+}
 
-        let token = AuthToken::verify(bytes)?;
+impl Default for MemoryReplayCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_REPLAY_CACHE_CAPACITY)
+    }
+}
 
+impl ReplayCache for MemoryReplayCache {
+    fn insert(&self, id: &[u8], expired_before: &Timestamp) -> Result<bool, Error> {
         let mut seen = self.seen.lock().unwrap();
 
-        let id = AuthToken::id(token.version, bytes);
+        if seen.contains(id) {
+            return Ok(false);
+        }
+        if seen.len() >= self.capacity {
+            let threshold: Box<[u8]> = expired_before.to_bytes().into();
+            *seen = seen.split_off(&threshold);
 
-        match seen.binary_search_by(|element| element.cmp(&id)) {
-            Ok(_) => Err(Error::AlreadyUsed),
-            Err(index) => {
-                seen.insert(index, id);
-                Ok(token)
+            if seen.len() >= self.capacity {
+                return Err(Error::ReplayCacheFull);
             }
         }
+
+        Ok(seen.insert(id.into()))
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Keeps track of used AuthToken until they expire.
+pub struct AuthVerifier {
+    seen: Arc<dyn ReplayCache>,
+}
+
+impl Default for AuthVerifier {
+    fn default() -> Self {
+        Self::new(MemoryReplayCache::default())
+    }
+}
+
+impl AuthVerifier {
+    /// Create a verifier that tracks used tokens in a custom [ReplayCache].
+    pub fn new(cache: impl ReplayCache + 'static) -> Self {
+        Self {
+            seen: Arc::new(cache),
+        }
+    }
+
+    pub fn verify(&self, bytes: &[u8]) -> Result<AuthToken, Error> {
+        self.verify_at(bytes, Timestamp::now())
     }
 
     // === Private Methods ===
 
-    /// Remove all tokens older than two time intervals in the past.
-    fn gc(&self) {
-        let threshold = ((Timestamp::now().as_u64() / TIME_INTERVAL) - 2).to_be_bytes();
+    fn verify_at(&self, bytes: &[u8], now: Timestamp) -> Result<AuthToken, Error> {
+        let token = AuthToken::verify_at(bytes, now)?;
 
-        let mut inner = self.seen.lock().unwrap();
+        let id = AuthToken::id(token.version, bytes);
 
-        match inner.binary_search_by(|element| element[0..8].cmp(&threshold)) {
-            Ok(index) | Err(index) => {
-                inner.drain(0..index);
-            }
+        // Tokens older than the window are rejected as expired anyway.
+        if !self.seen.insert(&id, &(now - TIMESTAMP_WINDOW as u64))? {
+            return Err(Error::AlreadyUsed);
         }
+
+        Ok(token)
     }
 }
 
@@ -181,6 +249,12 @@ pub enum Error {
     Postcard(#[from] postcard::Error),
     #[error("AuthToken already used")]
     AlreadyUsed,
+    #[error("Too many AuthTokens used recently")]
+    ReplayCacheFull,
+    #[error("Replay cache error: {0}")]
+    ReplayCache(String),
+    #[error(transparent)]
+    Capability(#[from] crate::capabilities::Error),
 }
 
 #[cfg(test)]
@@ -228,6 +302,28 @@ mod tests {
         assert_eq!(token.capabilities, capabilities.into());
     }
 
+    #[test]
+    fn invalid_capability() {
+        let signer = Keypair::random();
+        let capabilities = vec![Capability {
+            scope: "/pub/../".to_string(),
+            actions: vec![crate::capabilities::Action::Read],
+        }];
+
+        let verifier = AuthVerifier::default();
+
+        let token = AuthToken::sign(&signer, capabilities);
+
+        let result = verifier.verify(&token.serialize());
+
+        assert_eq!(
+            result,
+            Err(Error::Capability(crate::capabilities::Error::InvalidPath(
+                crate::path::Error::DotSegment
+            )))
+        );
+    }
+
     #[test]
     fn expired() {
         let signer = Keypair::random();
@@ -276,4 +372,106 @@ mod tests {
 
         assert_eq!(verifier.verify(serialized), Err(Error::AlreadyUsed));
     }
+
+    /// Sign a token as if it was created at `timestamp`.
+    fn sign_at(signer: &Keypair, timestamp: Timestamp) -> Vec<u8> {
+        let mut token = AuthToken {
+            signature: Signature::from_bytes(&[0; 64]),
+            namespace: *PUBKY_AUTH,
+            version: 0,
+            timestamp,
+            pubky: signer.public_key(),
+            capabilities: vec![Capability::root()].into(),
+        };
+
+        let serialized = token.serialize();
+        token.signature = signer.sign(&serialized[65..]);
+
+        token.serialize()
+    }
+
+    #[test]
+    fn clock_skew() {
+        let signer = Keypair::random();
+        let verifier = AuthVerifier::default();
+
+        let now = Timestamp::now();
+        let window = TIMESTAMP_WINDOW as u64;
+
+        for (timestamp, expected) in [
+            (now + window, Ok(())),
+            (now + window + 1, Err(Error::TooFarInTheFuture)),
+            (now - window, Ok(())),
+            (now - window - 1, Err(Error::Expired)),
+        ] {
+            let token = sign_at(&signer, timestamp);
+
+            assert_eq!(
+                verifier.verify_at(&token, now).map(|_| ()),
+                expected,
+                "{timestamp} verified at {now}"
+            );
+        }
+    }
+
+    #[test]
+    fn replay_until_expired() {
+        let signer = Keypair::random();
+        let verifier = AuthVerifier::default();
+
+        let timestamp = Timestamp::now();
+        let window = TIMESTAMP_WINDOW as u64;
+
+        let token = sign_at(&signer, timestamp);
+        verifier.verify_at(&token, timestamp).unwrap();
+
+        // Still remembered at the end of the window.
+        assert_eq!(
+            verifier.verify_at(&token, timestamp + window),
+            Err(Error::AlreadyUsed)
+        );
+        // Forgotten only once it is expired.
+        assert_eq!(
+            verifier.verify_at(&token, timestamp + window + 1),
+            Err(Error::Expired)
+        );
+
+        // A token from the future is remembered until it expires too.
+        let token = sign_at(&signer, timestamp + window);
+        verifier.verify_at(&token, timestamp).unwrap();
+
+        assert_eq!(
+            verifier.verify_at(&token, timestamp + 2 * window),
+            Err(Error::AlreadyUsed)
+        );
+
+        // Clones share the same cache.
+        assert_eq!(
+            verifier.clone().verify_at(&token, timestamp),
+            Err(Error::AlreadyUsed)
+        );
+    }
+
+    #[test]
+    fn replay_cache_capacity() {
+        let signer = Keypair::random();
+        let verifier = AuthVerifier::new(MemoryReplayCache::new(1));
+
+        let timestamp = Timestamp::now();
+        let window = TIMESTAMP_WINDOW as u64;
+
+        verifier
+            .verify_at(&sign_at(&signer, timestamp), timestamp)
+            .unwrap();
+
+        let token = sign_at(&signer, timestamp + 1);
+        assert_eq!(
+            verifier.verify_at(&token, timestamp + 1),
+            Err(Error::ReplayCacheFull)
+        );
+
+        // Expired tokens make room for new ones.
+        let token = sign_at(&signer, timestamp + window + 1);
+        verifier.verify_at(&token, timestamp + window + 1).unwrap();
+    }
 }
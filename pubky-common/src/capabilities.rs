@@ -2,6 +2,8 @@ use std::fmt::Display;
 
 use serde::{Deserialize, Serialize};
 
+use crate::path::{self, Path};
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Capability {
     pub scope: String,
@@ -16,6 +18,25 @@ impl Capability {
             actions: vec![Action::Read, Action::Write],
         }
     }
+
+    /// Whether this capability grants the `action` on the `path`.
+    ///
+    /// The scope is a prefix of the paths it covers, so a directory scope (ending with `/`)
+    /// covers every path under it, while `/pub/foo` also covers `/pub/foo.txt` and `/pub/foo/`,
+    /// as it always did for existing sessions.
+    pub fn allows(&self, path: &Path, action: &Action) -> bool {
+        self.actions.contains(action) && path.as_str().starts_with(&path::normalize(&self.scope))
+    }
+
+    /// Validate the scope as a [Path].
+    ///
+    /// Parsing a [Capability] doesn't validate its scope, so that sessions created
+    /// before paths were validated keep working; newly signed tokens are validated instead.
+    pub fn validate(&self) -> Result<(), Error> {
+        Path::parse(&self.scope).map_err(Error::InvalidPath)?;
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -128,6 +149,8 @@ impl<'de> Deserialize<'de> for Capability {
 pub enum Error {
     #[error("Capability: Invalid scope: does not start with `/`")]
     InvalidScope,
+    #[error("Capability: Invalid scope: {0}")]
+    InvalidPath(path::Error),
     #[error("Capability: Invalid format should be <scope>:<abilities>")]
     InvalidFormat,
     #[error("Capability: Invalid Action")]
@@ -234,4 +257,35 @@ mod tests {
 
         assert_eq!(Capability::try_from(expected_string), Ok(cap))
     }
+
+    #[test]
+    fn allows() {
+        let path = |path: &str| Path::parse(path).unwrap();
+
+        let directory = Capability::try_from("/pub/foo/:r").unwrap();
+        assert!(directory.allows(&path("/pub/foo/bar.txt"), &Action::Read));
+        assert!(!directory.allows(&path("/pub/foo/bar.txt"), &Action::Write));
+        assert!(!directory.allows(&path("/pub/foo.txt"), &Action::Read));
+
+        let prefix = Capability::try_from("/pub/foo:rw").unwrap();
+        assert!(prefix.allows(&path("/pub/foo"), &Action::Write));
+        assert!(prefix.allows(&path("/pub/foo/bar.txt"), &Action::Write));
+        assert!(prefix.allows(&path("/pub/foo.txt"), &Action::Write));
+        assert!(!prefix.allows(&path("/pub/bar.txt"), &Action::Write));
+
+        assert!(Capability::root().allows(&path("/pub/bar.txt"), &Action::Write));
+    }
+
+    #[test]
+    fn lenient_scope() {
+        // Invalid scopes of existing sessions still parse, and match as prefixes.
+        let cap = Capability::try_from("/pub/foo/.:r").unwrap();
+        assert!(cap.allows(&Path::parse("/pub/foo/.bar").unwrap(), &Action::Read));
+
+        assert_eq!(
+            cap.validate(),
+            Err(Error::InvalidPath(path::Error::DotSegment))
+        );
+        assert_eq!(Capability::root().validate(), Ok(()));
+    }
 }
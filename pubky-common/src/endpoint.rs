@@ -0,0 +1,200 @@
+//! Homeserver parameters announced as SvcParams in the SVCB record of its pkarr packet,
+//! letting clients adapt to the features of a homeserver before making any request.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use pkarr::dns::{rdata::SVCB, SimpleDnsError};
+use serde::{Deserialize, Serialize};
+
+/// Version of the homeserver API implemented by this crate.
+pub const PROTOCOL_VERSION: &str = "0";
+
+/// SvcParamKey of the supported [PROTOCOL_VERSION]s, encoded like `alpn`.
+///
+/// Keys `65280-65534` are reserved for private use, see
+/// [RFC 9460](https://www.rfc-editor.org/rfc/rfc9460#section-14.3.2).
+pub const VERSIONS_KEY: u16 = 65280;
+/// SvcParamKey of the maximum size of an entry in bytes, as a big endian `u64`.
+pub const MAX_UPLOAD_SIZE_KEY: u16 = 65281;
+/// SvcParamKey of the optional features supported by the homeserver, encoded like `alpn`.
+pub const FEATURES_KEY: u16 = 65282;
+
+/// Feature of homeservers serving each user at `<pubky>.<host>`, with paths
+/// relative to the user's root, instead of at `<host>/<pubky>/`.
+pub const SUBDOMAINS_FEATURE: &str = "subdomains";
+
+/// Parameters of a homeserver endpoint.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EndpointParams {
+    /// Supported application protocols, for example `h2` or `http/1.1`.
+    pub alpn: Vec<String>,
+    /// IPv4 addresses of the endpoint, for clients skipping a DNS lookup.
+    pub ipv4_hints: Vec<Ipv4Addr>,
+    /// IPv6 addresses of the endpoint, for clients skipping a DNS lookup.
+    pub ipv6_hints: Vec<Ipv6Addr>,
+    /// Supported versions of the homeserver API.
+    pub versions: Vec<String>,
+    /// Maximum size of an entry in bytes, users may have higher limits.
+    pub max_upload_size: Option<u64>,
+    /// Optional features supported by the homeserver, for example `uploads` or `events`.
+    pub features: Vec<String>,
+}
+
+impl EndpointParams {
+    /// Parse the parameters of an SVCB record, ignoring malformed values.
+    pub fn from_svcb(svcb: &SVCB) -> Self {
+        Self {
+            alpn: svcb
+                .get_param(SVCB::ALPN)
+                .map(decode_strings)
+                .unwrap_or_default(),
+            ipv4_hints: svcb
+                .get_param(SVCB::IPV4HINT)
+                .map(|value| {
+                    value
+                        .chunks_exact(4)
+                        .map(|ip| Ipv4Addr::from([ip[0], ip[1], ip[2], ip[3]]))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            ipv6_hints: svcb
+                .get_param(SVCB::IPV6HINT)
+                .map(|value| {
+                    value
+                        .chunks_exact(16)
+                        .map(|ip| {
+                            let mut octets = [0; 16];
+                            octets.copy_from_slice(ip);
+                            Ipv6Addr::from(octets)
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            versions: svcb
+                .get_param(VERSIONS_KEY)
+                .map(decode_strings)
+                .unwrap_or_default(),
+            max_upload_size: svcb
+                .get_param(MAX_UPLOAD_SIZE_KEY)
+                .and_then(|value| value.try_into().ok())
+                .map(u64::from_be_bytes),
+            features: svcb
+                .get_param(FEATURES_KEY)
+                .map(decode_strings)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Set these parameters on an SVCB record, skipping empty ones.
+    pub fn write_to(&self, svcb: &mut SVCB) -> Result<(), SimpleDnsError> {
+        if !self.alpn.is_empty() {
+            svcb.set_param(SVCB::ALPN, encode_strings(&self.alpn)?)?;
+        }
+        if !self.ipv4_hints.is_empty() {
+            svcb.set_ipv4hint(self.ipv4_hints.iter().map(|ip| u32::from(*ip)))?;
+        }
+        if !self.ipv6_hints.is_empty() {
+            svcb.set_ipv6hint(self.ipv6_hints.iter().map(|ip| u128::from(*ip)))?;
+        }
+        if !self.versions.is_empty() {
+            svcb.set_param(VERSIONS_KEY, encode_strings(&self.versions)?)?;
+        }
+        if let Some(max_upload_size) = self.max_upload_size {
+            svcb.set_param(MAX_UPLOAD_SIZE_KEY, max_upload_size.to_be_bytes().to_vec())?;
+        }
+        if !self.features.is_empty() {
+            svcb.set_param(FEATURES_KEY, encode_strings(&self.features)?)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the homeserver supports this version of its API,
+    /// or didn't announce any supported versions.
+    pub fn supports_version(&self, version: &str) -> bool {
+        self.versions.is_empty() || self.versions.iter().any(|v| v == version)
+    }
+
+    /// Returns true if the homeserver announced this feature.
+    pub fn supports_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+
+/// Encode a list of length prefixed strings, like the `alpn` SvcParamValue.
+fn encode_strings(strings: &[String]) -> Result<Vec<u8>, SimpleDnsError> {
+    let mut value = Vec::new();
+
+    for string in strings {
+        let length = u8::try_from(string.len()).map_err(|_| SimpleDnsError::InvalidDnsPacket)?;
+
+        value.push(length);
+        value.extend_from_slice(string.as_bytes());
+    }
+
+    Ok(value)
+}
+
+fn decode_strings(mut value: &[u8]) -> Vec<String> {
+    let mut strings = Vec::new();
+
+    while let Some((length, rest)) = value.split_first() {
+        let Some((string, rest)) = rest.split_at_checked(*length as usize) else {
+            break;
+        };
+
+        strings.push(String::from_utf8_lossy(string).to_string());
+        value = rest;
+    }
+
+    strings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn svcb_params_roundtrip() {
+        let params = EndpointParams {
+            alpn: vec!["h2".into(), "http/1.1".into()],
+            ipv4_hints: vec![Ipv4Addr::new(192, 0, 2, 1)],
+            ipv6_hints: vec![Ipv6Addr::LOCALHOST],
+            versions: vec![PROTOCOL_VERSION.into()],
+            max_upload_size: Some(100 * 1024 * 1024),
+            features: vec!["uploads".into(), "events".into()],
+        };
+
+        let mut svcb = SVCB::new(1, "example.com".try_into().unwrap());
+        params.write_to(&mut svcb).unwrap();
+
+        assert_eq!(svcb.get_param(SVCB::ALPN), Some(&b"\x02h2\x08http/1.1"[..]));
+        assert_eq!(EndpointParams::from_svcb(&svcb), params);
+
+        assert!(params.supports_version(PROTOCOL_VERSION));
+        assert!(!params.supports_version("1"));
+        assert!(params.supports_feature("events"));
+        assert!(!params.supports_feature("foo"));
+    }
+
+    #[test]
+    fn empty_svcb_params() {
+        let svcb = SVCB::new(1, "example.com".try_into().unwrap());
+
+        assert_eq!(EndpointParams::from_svcb(&svcb), EndpointParams::default());
+        assert!(EndpointParams::default().supports_version("1"));
+    }
+
+    #[test]
+    fn malformed_svcb_params() {
+        let mut svcb = SVCB::new(1, "example.com".try_into().unwrap());
+        svcb.set_param(MAX_UPLOAD_SIZE_KEY, vec![1, 2, 3]).unwrap();
+        svcb.set_param(FEATURES_KEY, b"\x07uploads\x09ev".to_vec())
+            .unwrap();
+
+        let params = EndpointParams::from_svcb(&svcb);
+
+        assert_eq!(params.max_upload_size, None);
+        assert_eq!(params.features, vec!["uploads".to_string()]);
+    }
+}
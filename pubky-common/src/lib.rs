@@ -1,9 +1,14 @@
 pub mod auth;
 pub mod capabilities;
 pub mod crypto;
+pub mod endpoint;
+pub mod limits;
+pub mod list;
 pub mod namespaces;
+pub mod path;
 pub mod recovery_file;
 pub mod session;
+pub mod upload;
 
 pub mod timestamp {
     pub use pubky_timestamp::*;
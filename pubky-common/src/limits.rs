@@ -0,0 +1,37 @@
+//! Size limits enforced by homeservers, reported in the JSON body
+//! of `413 Payload Too Large` responses.
+
+use serde::{Deserialize, Serialize};
+
+/// A limit that a request exceeded.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Limit {
+    /// Maximum size of a single entry.
+    EntrySize,
+    /// Maximum total size of all entries of a user.
+    StorageQuota,
+    /// Maximum size of a request body, for example a signup or a signin request.
+    BodySize,
+}
+
+/// JSON body of a `413 Payload Too Large` response.
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub struct PayloadTooLarge {
+    /// The exceeded limit.
+    pub limit: Limit,
+    /// The value of the exceeded limit in bytes.
+    pub max: u64,
+}
+
+impl std::fmt::Display for PayloadTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let limit = match self.limit {
+            Limit::EntrySize => "Entry size",
+            Limit::StorageQuota => "Storage quota",
+            Limit::BodySize => "Request body size",
+        };
+
+        write!(f, "{limit} limit of {} bytes exceeded", self.max)
+    }
+}
@@ -0,0 +1,44 @@
+//! Structured directory listings and stats returned by the homeserver
+//! when requested with `Accept: application/json` or `?stat`.
+
+use serde::{Deserialize, Serialize};
+
+/// A file or a directory in a [ListResponse].
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub struct ListEntry {
+    /// The `pubky://` url of this file or directory.
+    ///
+    /// Directories (only returned in shallow listings) end with `/`.
+    pub url: String,
+    /// Whether or not this is a directory.
+    pub is_directory: bool,
+    /// Length of the file's content in bytes.
+    pub content_length: Option<u64>,
+    /// Content type of the file, if known.
+    pub content_type: Option<String>,
+    /// Last modification time of the file, formatted as an HTTP date.
+    pub last_modified: Option<String>,
+    /// Quoted ETag of the file, same as the `ETag` header of a `GET` request.
+    pub etag: Option<String>,
+}
+
+/// A page of a directory listing.
+#[derive(Clone, Default, Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub struct ListResponse {
+    pub entries: Vec<ListEntry>,
+    /// Cursor to pass in the next list request to get the next page,
+    /// `None` if this is the last page.
+    pub next_cursor: Option<String>,
+}
+
+/// Aggregated stats of a directory, returned by `GET <directory>/?stat`.
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub struct DirectoryStat {
+    /// Total length in bytes of all files under this directory, recursively.
+    pub content_length: u64,
+    /// Number of files under this directory, recursively.
+    pub entries_count: u64,
+    /// Last time a file under this directory was written or removed,
+    /// formatted as an HTTP date.
+    pub last_modified: String,
+}
@@ -0,0 +1,228 @@
+//! Validated and normalized paths of entries in a Pubky homeserver.
+
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+
+/// Maximum length of a [Path] in bytes, after normalization.
+pub const MAX_PATH_LENGTH: usize = 1024;
+/// Maximum length of a single segment of a [Path] in bytes.
+pub const MAX_SEGMENT_LENGTH: usize = 255;
+/// Maximum number of segments in a [Path].
+pub const MAX_SEGMENTS: usize = 32;
+
+/// A path of an entry (or a directory) relative to a user's root,
+/// for example `pub/example.com/file.txt`.
+///
+/// - Leading `/` is optional and is removed.
+/// - A trailing `/` marks a directory, which can be listed but not written to.
+/// - Segments can't be empty, `.` or `..`.
+/// - Control characters and `\` are forbidden.
+/// - Unicode is normalized to NFC, so visually identical paths are equal.
+/// - Paths are case-sensitive, but the root segment (`pub/`) has to be lowercase.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Path(String);
+
+impl Path {
+    /// Validate and normalize a path.
+    pub fn parse(path: &str) -> Result<Self, Error> {
+        let path = normalize(path);
+
+        if path.len() > MAX_PATH_LENGTH {
+            return Err(Error::TooLong);
+        }
+
+        if let Some(char) = path.chars().find(|c| c.is_control() || *c == '\\') {
+            return Err(Error::ForbiddenCharacter(char));
+        }
+
+        let segments = path
+            .strip_suffix('/')
+            .unwrap_or(&path)
+            .split('/')
+            .collect::<Vec<_>>();
+
+        if segments.len() > MAX_SEGMENTS {
+            return Err(Error::TooManySegments);
+        }
+
+        // The root path has a single empty segment.
+        if !path.is_empty() {
+            for segment in &segments {
+                match *segment {
+                    "" => return Err(Error::EmptySegment),
+                    "." | ".." => return Err(Error::DotSegment),
+                    s if s.len() > MAX_SEGMENT_LENGTH => return Err(Error::SegmentTooLong),
+                    _ => {}
+                }
+            }
+
+            if path.contains('/') && segments[0].to_lowercase() != segments[0] {
+                return Err(Error::UppercaseRoot);
+            }
+        }
+
+        Ok(Self(path))
+    }
+
+    /// Returns the normalized path without a leading `/`.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether this path is a directory (ends with `/`, or is the root).
+    pub fn is_directory(&self) -> bool {
+        self.0.is_empty() || self.0.ends_with('/')
+    }
+
+    /// Returns an iterator over the segments of this path.
+    pub fn segments(&self) -> impl Iterator<Item = &str> {
+        self.0
+            .strip_suffix('/')
+            .unwrap_or(&self.0)
+            .split('/')
+            .filter(|s| !s.is_empty())
+    }
+
+    /// Whether this path is within `scope`.
+    ///
+    /// A directory scope contains every path under it,
+    /// while a file scope only matches itself.
+    pub fn is_within(&self, scope: &Path) -> bool {
+        if scope.is_directory() {
+            self.0.starts_with(&scope.0)
+        } else {
+            self.0 == scope.0
+        }
+    }
+}
+
+/// Remove the leading `/` of `path` and normalize it to NFC, without validating it.
+pub(crate) fn normalize(path: &str) -> String {
+    path.trim_start_matches('/').nfc().collect()
+}
+
+impl Display for Path {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl TryFrom<&str> for Path {
+    type Error = Error;
+
+    fn try_from(value: &str) -> Result<Self, Error> {
+        Path::parse(value)
+    }
+}
+
+impl TryFrom<String> for Path {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Error> {
+        Path::parse(&value)
+    }
+}
+
+impl Serialize for Path {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Path {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let string: String = Deserialize::deserialize(deserializer)?;
+
+        string.try_into().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum Error {
+    #[error("Path: longer than {MAX_PATH_LENGTH} bytes")]
+    TooLong,
+    #[error("Path: more than {MAX_SEGMENTS} segments")]
+    TooManySegments,
+    #[error("Path: segment longer than {MAX_SEGMENT_LENGTH} bytes")]
+    SegmentTooLong,
+    #[error("Path: empty segment")]
+    EmptySegment,
+    #[error("Path: `.` and `..` segments are not allowed")]
+    DotSegment,
+    #[error("Path: forbidden character {0:?}")]
+    ForbiddenCharacter(char),
+    #[error("Path: root directory should be lowercase")]
+    UppercaseRoot,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize() {
+        assert_eq!(Path::parse("/pub/foo.txt").unwrap().as_str(), "pub/foo.txt");
+        assert_eq!(Path::parse("pub/foo/").unwrap().as_str(), "pub/foo/");
+        assert_eq!(Path::parse("/").unwrap().as_str(), "");
+        assert!(Path::parse("pub/foo/").unwrap().is_directory());
+        assert!(!Path::parse("pub/foo").unwrap().is_directory());
+
+        // "é" as `e` + combining acute accent, normalized to a single code point.
+        assert_eq!(
+            Path::parse("pub/cafe\u{301}").unwrap(),
+            Path::parse("pub/caf\u{e9}").unwrap()
+        );
+
+        // Case-sensitive except for the root.
+        assert_ne!(Path::parse("pub/Foo"), Path::parse("pub/foo"));
+        assert_eq!(Path::parse("PUB/foo"), Err(Error::UppercaseRoot));
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(Path::parse("pub//foo"), Err(Error::EmptySegment));
+        assert_eq!(Path::parse("pub/../foo"), Err(Error::DotSegment));
+        assert_eq!(Path::parse("pub/./foo"), Err(Error::DotSegment));
+        assert_eq!(
+            Path::parse("pub/foo\x7f"),
+            Err(Error::ForbiddenCharacter('\x7f'))
+        );
+        assert_eq!(
+            Path::parse("pub\\foo"),
+            Err(Error::ForbiddenCharacter('\\'))
+        );
+        assert_eq!(
+            Path::parse(&format!("pub/{}", "a".repeat(MAX_SEGMENT_LENGTH + 1))),
+            Err(Error::SegmentTooLong)
+        );
+        assert_eq!(
+            Path::parse(&"a/".repeat(MAX_SEGMENTS + 1)),
+            Err(Error::TooManySegments)
+        );
+        assert_eq!(
+            Path::parse(&"a".repeat(MAX_PATH_LENGTH + 1)),
+            Err(Error::TooLong)
+        );
+    }
+
+    #[test]
+    fn is_within() {
+        let path = Path::parse("pub/foo.bar/file").unwrap();
+
+        assert!(path.is_within(&Path::parse("/").unwrap()));
+        assert!(path.is_within(&Path::parse("/pub/foo.bar/").unwrap()));
+        assert!(path.is_within(&Path::parse("/pub/foo.bar/file").unwrap()));
+        assert!(!path.is_within(&Path::parse("/pub/foo.bar").unwrap()));
+        assert!(!Path::parse("pub/foo.bar/file2")
+            .unwrap()
+            .is_within(&Path::parse("/pub/foo.bar/file").unwrap()));
+    }
+}
@@ -0,0 +1,13 @@
+//! Resumable uploads, staged in parts on the homeserver before being
+//! written as a single entry.
+
+use serde::{Deserialize, Serialize};
+
+/// Status of an upload session, returned by `GET <path>?upload=<id>`.
+#[derive(Clone, Default, Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub struct UploadStatus {
+    /// Numbers of the parts received so far, sorted.
+    pub parts: Vec<u32>,
+    /// Number of bytes received in consecutive parts starting from part `0`.
+    pub offset: u64,
+}
@@ -0,0 +1,79 @@
+//! Negotiated (gzip, brotli or zstd) compression of responses.
+
+use axum::{
+    body::Body,
+    http::{header, Extensions, HeaderMap, HeaderValue, Response, StatusCode, Version},
+};
+use tower_http::compression::{
+    predicate::{Predicate, SizeAbove},
+    CompressionLayer,
+};
+
+/// Compress successful responses with a compressible `Content-Type`,
+/// according to the request's `Accept-Encoding`.
+pub fn layer() -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new().compress_when(SizeAbove::default().and(
+        |status: StatusCode, _: Version, headers: &HeaderMap, _: &Extensions| {
+            status.is_success()
+                && headers
+                    .get(header::CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .is_some_and(is_compressible)
+        },
+    ))
+}
+
+/// An encoded response is not byte-for-byte identical to the stored entry,
+/// so its ETag can only be weak.
+pub async fn weaken_etag(mut response: Response<Body>) -> Response<Body> {
+    if !response.headers().contains_key(header::CONTENT_ENCODING) {
+        return response;
+    }
+
+    if let Some(etag) = response.headers().get(header::ETAG) {
+        if !etag.as_bytes().starts_with(b"W/") {
+            let mut weak = b"W/".to_vec();
+            weak.extend_from_slice(etag.as_bytes());
+
+            let weak = HeaderValue::from_bytes(&weak).expect("valid etag is a valid header value");
+
+            response.headers_mut().insert(header::ETAG, weak);
+        }
+    }
+
+    response
+}
+
+/// Whether a content type is text-like and worth compressing,
+/// unlike images, videos or archives which are already compressed.
+fn is_compressible(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/json" | "application/javascript" | "application/xml" | "application/wasm"
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compressible() {
+        assert!(is_compressible("application/json"));
+        assert!(is_compressible("text/plain; charset=utf-8"));
+        assert!(is_compressible("application/ld+json"));
+        assert!(!is_compressible("image/png"));
+        assert!(!is_compressible("application/octet-stream"));
+        assert!(!is_compressible(""));
+    }
+}
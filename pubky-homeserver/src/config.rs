@@ -1,16 +1,28 @@
 //! Configuration for the server
 
 use anyhow::{anyhow, Context, Result};
-use pkarr::Keypair;
+use pkarr::{Keypair, PublicKey};
 use serde::{Deserialize, Serialize};
 use std::{
-    fmt::Debug,
+    collections::HashMap,
+    fmt::{Debug, Display},
+    net::{IpAddr, SocketAddr},
     path::{Path, PathBuf},
+    str::FromStr,
     time::Duration,
 };
-use tracing::info;
+use tracing::{info, warn};
 
-use pubky_common::timestamp::Timestamp;
+use pubky_common::{auth::DEFAULT_REPLAY_CACHE_CAPACITY, timestamp::Timestamp};
+
+// === Overrides ===
+/// Prefix of environment variables overriding config keys, see [ConfigBuilder::env].
+pub const ENV_PREFIX: &str = "PUBKY_";
+const REDACTED: &str = "<redacted>";
+
+// === Secret key ===
+/// Name of the file in the storage directory persisting a generated secret key.
+const PERSISTED_SECRET_KEY_FILE: &str = "secret_key";
 
 // === Database ===
 const DEFAULT_STORAGE_DIR: &str = "pubky";
@@ -19,19 +31,226 @@ pub const DEFAULT_MAP_SIZE: usize = 10995116277760; // 10TB (not = disk-space us
 // === Server ==
 pub const DEFAULT_LIST_LIMIT: u16 = 100;
 pub const DEFAULT_MAX_LIST_LIMIT: u16 = 1000;
+pub const DEFAULT_UPLOAD_SESSION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+pub const DEFAULT_TLS_RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+// === Pkarr ===
+pub const DEFAULT_PKARR_TTL: u32 = 60 * 60;
+pub const DEFAULT_PKARR_REPUBLISH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// === Limits ===
+pub const DEFAULT_MAX_ENTRY_SIZE: u64 = 100 * 1024 * 1024; // 100MB
+pub const DEFAULT_MAX_AUTH_BODY_SIZE: u64 = 16 * 1024; // 16KB
+/// Signature (64 bytes) + timestamp (8 bytes) + encoded DNS packet (1000 bytes) + some slack.
+pub const DEFAULT_MAX_PKARR_BODY_SIZE: u64 = 1104;
 
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
 struct ConfigToml {
     testnet: Option<bool>,
     port: Option<u16>,
+    listen: Option<Vec<ListenAddress>>,
+    public_port: Option<u16>,
+    metrics_listen: Option<SocketAddr>,
     bootstrap: Option<Vec<String>>,
     domain: Option<String>,
+    subdomains: Option<bool>,
+    cors_allowed_origins: Option<Vec<String>>,
     storage: Option<PathBuf>,
     secret_key: Option<String>,
+    secret_key_file: Option<PathBuf>,
+    previous_secret_key_files: Option<Vec<PathBuf>>,
     dht_request_timeout: Option<Duration>,
     default_list_limit: Option<u16>,
     max_list_limit: Option<u16>,
+    upload_session_ttl: Option<Duration>,
+    shutdown_timeout: Option<Duration>,
+    pkarr_ttl: Option<u32>,
+    pkarr_republish_interval: Option<Duration>,
+    public_ips: Option<Vec<IpAddr>>,
+    alpn: Option<Vec<String>>,
+    max_entry_size: Option<u64>,
+    max_auth_body_size: Option<u64>,
+    max_pkarr_body_size: Option<u64>,
+    replay_cache_capacity: Option<usize>,
+    storage_quota: Option<u64>,
+    user_limits: Option<HashMap<String, UserLimits>>,
+    rate_limits: Option<RateLimits>,
+    trusted_proxies: Option<Vec<IpAddr>>,
     db_map_size: Option<usize>,
+    tls: Option<TlsToml>,
+}
+
+/// The `[tls]` table, resolved into [Tls].
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+struct TlsToml {
+    cert: PathBuf,
+    key: PathBuf,
+    reload_interval: Option<Duration>,
+}
+
+/// How the homeserver serves HTTPS itself, instead of behind a reverse proxy:
+/// a certificate chain and its private key, from PEM files reloaded when they change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tls {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// How often to check the files for changes.
+    pub reload_interval: Duration,
+}
+
+impl From<TlsToml> for Tls {
+    fn from(value: TlsToml) -> Self {
+        Tls {
+            cert: value.cert,
+            key: value.key,
+            reload_interval: value.reload_interval.unwrap_or(DEFAULT_TLS_RELOAD_INTERVAL),
+        }
+    }
+}
+
+impl From<&Tls> for TlsToml {
+    fn from(value: &Tls) -> Self {
+        TlsToml {
+            cert: value.cert.clone(),
+            key: value.key.clone(),
+            reload_interval: Some(value.reload_interval),
+        }
+    }
+}
+
+/// Limits overriding the server wide limits for a specific user.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct UserLimits {
+    /// Overrides [Config::max_entry_size].
+    pub max_entry_size: Option<u64>,
+    /// Overrides [Config::storage_quota].
+    pub storage_quota: Option<u64>,
+}
+
+/// Token bucket rate limits, for each kind of request.
+///
+/// Omitted budgets use their default limits.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct RateLimits {
+    /// Signup, signin and session management.
+    pub auth: BudgetLimits,
+    /// `PUT`, `POST` and `DELETE` requests, including pkarr relay publishing.
+    pub write: BudgetLimits,
+    /// `GET` and `HEAD` requests, except for the `/events/` feed.
+    pub read: BudgetLimits,
+    /// The `/events/` feed.
+    pub events: BudgetLimits,
+}
+
+impl RateLimits {
+    /// No rate limits at all.
+    pub fn disabled() -> Self {
+        Self {
+            auth: BudgetLimits::default(),
+            write: BudgetLimits::default(),
+            read: BudgetLimits::default(),
+            events: BudgetLimits::default(),
+        }
+    }
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            auth: BudgetLimits {
+                per_ip: Some(Quota::new(10, 30)),
+                per_pubky: Some(Quota::new(10, 30)),
+            },
+            write: BudgetLimits {
+                per_ip: Some(Quota::new(100, 600)),
+                per_pubky: Some(Quota::new(100, 600)),
+            },
+            read: BudgetLimits {
+                per_ip: Some(Quota::new(1000, 6000)),
+                per_pubky: None,
+            },
+            events: BudgetLimits {
+                per_ip: Some(Quota::new(60, 120)),
+                per_pubky: None,
+            },
+        }
+    }
+}
+
+/// Limits of a budget, a request is rejected if it exceeds either of them.
+///
+/// Omitted limits are disabled.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct BudgetLimits {
+    /// Limit per client IP address.
+    pub per_ip: Option<Quota>,
+    /// Limit per pubky in the request path (`/:pubky/...`).
+    pub per_pubky: Option<Quota>,
+}
+
+/// A token bucket holding up to `burst` requests, refilled by `per_minute` requests every minute.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+impl Quota {
+    pub fn new(burst: u32, per_minute: u32) -> Self {
+        Self { burst, per_minute }
+    }
+}
+
+/// An address to accept connections on, parsed from either a socket address
+/// like `127.0.0.1:6287` or `[::]:6287`, or a Unix domain socket path like `unix:/run/pubky.sock`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddress {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err(anyhow!("missing Unix socket path in {s:?}"));
+            }
+
+            return Ok(ListenAddress::Unix(path.into()));
+        }
+
+        s.parse()
+            .map(ListenAddress::Tcp)
+            .map_err(|_| anyhow!("invalid address {s:?}"))
+    }
+}
+
+impl TryFrom<String> for ListenAddress {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<ListenAddress> for String {
+    fn from(value: ListenAddress) -> Self {
+        value.to_string()
+    }
+}
+
+impl Display for ListenAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenAddress::Tcp(address) => write!(f, "{address}"),
+            ListenAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
 }
 
 /// Server configuration
@@ -39,8 +258,22 @@ struct ConfigToml {
 pub struct Config {
     /// Whether or not this server is running in a testnet.
     testnet: bool,
-    /// The configured port for this server.
+    /// The configured port for this server, on all IPv4 interfaces unless [Config::listen] is set.
     port: u16,
+    /// Addresses to listen on, overriding [Config::port].
+    ///
+    /// Defaults to none, listening on `0.0.0.0:<port>`.
+    listen: Vec<ListenAddress>,
+    /// Port announced in the pkarr packet, for example the port of a proxy
+    /// forwarding to this server.
+    ///
+    /// Defaults to the port of the first TCP listener for `localhost` or when serving TLS,
+    /// and to the conventional ports of a reverse proxy otherwise.
+    public_port: Option<u16>,
+    /// Address to serve Prometheus metrics on, at `/metrics`.
+    ///
+    /// Defaults to `None`, not serving metrics at all.
+    metrics_listen: Option<SocketAddr>,
     /// Bootstrapping DHT nodes.
     ///
     /// Helpful to run the server locally or in testnet.
@@ -48,14 +281,30 @@ pub struct Config {
     /// A public domain for this server
     /// necessary for web browsers running in https environment.
     domain: Option<String>,
+    /// Serve users at `<pubky>.<domain>` (or `<pubky>.localhost`) in addition to `/<pubky>/` paths,
+    /// giving each user its own browser origin.
+    ///
+    /// Defaults to `false`
+    subdomains: bool,
+    /// Origins of web apps allowed to make credentialed cross-origin requests, sending
+    /// the session cookies of their users, or `*` for any origin.
+    ///
+    /// Other origins can still make requests without credentials, like reading public data.
+    ///
+    /// Defaults to any origin, or to none if [Config::subdomains] is enabled.
+    cors_allowed_origins: Option<Vec<String>>,
     /// Path to the storage directory.
     ///
     /// Defaults to a directory in the OS data directory
     storage: PathBuf,
     /// Server keypair.
     ///
-    /// Defaults to a random keypair.
+    /// Defaults to a random keypair, persisted in the storage directory on start.
     keypair: Keypair,
+    /// Where [Config::keypair] comes from.
+    secret_key_source: SecretKeySource,
+    /// Previous keypairs of the server, redirecting to the current one, see [Config::previous_keypairs].
+    previous_keypairs: Vec<(PathBuf, Keypair)>,
     dht_request_timeout: Option<Duration>,
     /// The default limit of a list api if no `limit` query parameter is provided.
     ///
@@ -65,21 +314,115 @@ pub struct Config {
     ///
     /// Defaults to `1000`
     max_list_limit: u16,
+    /// How long an upload session is kept without receiving any part,
+    /// before it is considered abandoned and removed.
+    ///
+    /// Defaults to 24 hours
+    upload_session_ttl: Duration,
+    /// How long to wait for in-flight requests, including uploads, to complete on shutdown,
+    /// before aborting them.
+    ///
+    /// Defaults to 30 seconds
+    shutdown_timeout: Duration,
+    /// Serve HTTPS directly.
+    ///
+    /// Defaults to `None`, serving plain HTTP, for example behind a reverse proxy.
+    tls: Option<Tls>,
+
+    // === Pkarr ===
+    /// TTL in seconds of the records in the server's pkarr packet.
+    ///
+    /// Defaults to 1 hour
+    pkarr_ttl: u32,
+    /// How often to republish the server's pkarr packet, to keep it alive in the DHT.
+    ///
+    /// Defaults to 1 hour
+    pkarr_republish_interval: Duration,
+    /// Public IP addresses of this server, announced as A/AAAA records
+    /// and as hints in the server's SVCB record.
+    ///
+    /// Defaults to none
+    public_ips: Vec<IpAddr>,
+    /// Application protocols announced in the server's SVCB record, for example `h2`.
+    ///
+    /// Defaults to none
+    alpn: Vec<String>,
+
+    // === Limits ===
+    /// Maximum size of a single entry in bytes.
+    ///
+    /// Defaults to 100MB
+    max_entry_size: u64,
+    /// Maximum size of signup and signin request bodies in bytes.
+    ///
+    /// Defaults to 16KB
+    max_auth_body_size: u64,
+    /// Maximum size of a signed packet published through the pkarr relay endpoint.
+    ///
+    /// Defaults to 1104 bytes
+    max_pkarr_body_size: u64,
+    /// Maximum number of recently used auth tokens remembered to reject replays,
+    /// limiting signups and signins to that many per 90 seconds.
+    ///
+    /// Defaults to 1,000,000
+    replay_cache_capacity: usize,
+    /// Maximum total size of all entries of a user in bytes.
+    ///
+    /// Defaults to no quota.
+    storage_quota: Option<u64>,
+    /// Per user overrides of [Config::max_entry_size] and [Config::storage_quota].
+    user_limits: HashMap<PublicKey, UserLimits>,
+    /// Rate limits per client IP and per pubky.
+    ///
+    /// Defaults to [RateLimits::default], disabled in [Config::test].
+    rate_limits: RateLimits,
+    /// Addresses of reverse proxies whose `X-Forwarded-For` or `Forwarded` headers
+    /// are trusted for the client IP address of rate limits.
+    ///
+    /// Defaults to none, using the address of the connection.
+    trusted_proxies: Vec<IpAddr>,
 
     // === Database params ===
     db_map_size: usize,
 }
 
 impl Config {
-    fn try_from_str(value: &str) -> Result<Self> {
-        let config_toml: ConfigToml = toml::from_str(value)?;
+    #[cfg(test)]
+    pub(crate) fn try_from_str(value: &str) -> Result<Self> {
+        ConfigBuilder::default().toml(value, "config")?.build()
+    }
 
-        let keypair = if let Some(secret_key) = config_toml.secret_key {
-            let secret_key = deserialize_secret_key(secret_key)?;
-            Keypair::from_secret_key(&secret_key)
-        } else {
-            Keypair::random()
-        };
+    fn try_from_config_toml(config_toml: ConfigToml) -> Result<Self> {
+        let (keypair, secret_key_source) =
+            match (config_toml.secret_key, config_toml.secret_key_file) {
+                (Some(secret_key), None) => (
+                    parse_secret_key(&secret_key)
+                        .map_err(|error| anyhow!("secret_key: {error}"))?,
+                    SecretKeySource::Config,
+                ),
+                (None, Some(path)) => (
+                    read_secret_key_file(&path)
+                        .map_err(|error| anyhow!("secret_key_file: {error}"))?,
+                    SecretKeySource::File(path),
+                ),
+                (None, None) => (Keypair::random(), SecretKeySource::Generated),
+                (Some(_), Some(_)) => {
+                    return Err(anyhow!(
+                        "secret_key: set either `secret_key` or `secret_key_file`, not both"
+                    ))
+                }
+            };
+
+        let previous_keypairs = config_toml
+            .previous_secret_key_files
+            .unwrap_or_default()
+            .into_iter()
+            .map(|path| {
+                read_secret_key_file(&path)
+                    .map(|keypair| (path, keypair))
+                    .map_err(|error| anyhow!("previous_secret_key_files: {error}"))
+            })
+            .collect::<Result<_>>()?;
 
         let storage = {
             let dir = if let Some(storage) = config_toml.storage {
@@ -94,19 +437,76 @@ impl Config {
             dir.join("homeserver")
         };
 
+        let user_limits = config_toml
+            .user_limits
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(public_key, limits)| {
+                PublicKey::try_from(public_key.as_str())
+                    .map(|public_key| (public_key, limits))
+                    .map_err(|_| anyhow!("user_limits: invalid public key {public_key}"))
+            })
+            .collect::<Result<_>>()?;
+
+        let cors_allowed_origins = config_toml
+            .cors_allowed_origins
+            .map(|origins| {
+                origins
+                    .iter()
+                    .map(|origin| parse_origin(origin))
+                    .collect::<Result<_>>()
+            })
+            .transpose()?;
+
+        if config_toml.port.is_some() && config_toml.listen.is_some() {
+            return Err(anyhow!("listen: set either `port` or `listen`, not both"));
+        }
+
         let config = Config {
             testnet: config_toml.testnet.unwrap_or(false),
             port: config_toml.port.unwrap_or(0),
+            listen: config_toml.listen.unwrap_or_default(),
+            public_port: config_toml.public_port,
+            metrics_listen: config_toml.metrics_listen,
             bootstrap: config_toml.bootstrap,
             domain: config_toml.domain,
+            subdomains: config_toml.subdomains.unwrap_or_default(),
+            cors_allowed_origins,
             keypair,
+            secret_key_source,
+            previous_keypairs,
             storage,
             dht_request_timeout: config_toml.dht_request_timeout,
             default_list_limit: config_toml.default_list_limit.unwrap_or(DEFAULT_LIST_LIMIT),
-            max_list_limit: config_toml
-                .default_list_limit
-                .unwrap_or(DEFAULT_MAX_LIST_LIMIT),
+            max_list_limit: config_toml.max_list_limit.unwrap_or(DEFAULT_MAX_LIST_LIMIT),
+            upload_session_ttl: config_toml
+                .upload_session_ttl
+                .unwrap_or(DEFAULT_UPLOAD_SESSION_TTL),
+            shutdown_timeout: config_toml
+                .shutdown_timeout
+                .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT),
+            pkarr_ttl: config_toml.pkarr_ttl.unwrap_or(DEFAULT_PKARR_TTL),
+            pkarr_republish_interval: config_toml
+                .pkarr_republish_interval
+                .unwrap_or(DEFAULT_PKARR_REPUBLISH_INTERVAL),
+            public_ips: config_toml.public_ips.unwrap_or_default(),
+            alpn: config_toml.alpn.unwrap_or_default(),
+            max_entry_size: config_toml.max_entry_size.unwrap_or(DEFAULT_MAX_ENTRY_SIZE),
+            max_auth_body_size: config_toml
+                .max_auth_body_size
+                .unwrap_or(DEFAULT_MAX_AUTH_BODY_SIZE),
+            max_pkarr_body_size: config_toml
+                .max_pkarr_body_size
+                .unwrap_or(DEFAULT_MAX_PKARR_BODY_SIZE),
+            replay_cache_capacity: config_toml
+                .replay_cache_capacity
+                .unwrap_or(DEFAULT_REPLAY_CACHE_CAPACITY),
+            storage_quota: config_toml.storage_quota,
+            user_limits,
+            rate_limits: config_toml.rate_limits.unwrap_or_default(),
+            trusted_proxies: config_toml.trusted_proxies.unwrap_or_default(),
             db_map_size: config_toml.db_map_size.unwrap_or(DEFAULT_MAP_SIZE),
+            tls: config_toml.tls.map(Tls::from),
         };
 
         if config.testnet {
@@ -123,11 +523,102 @@ impl Config {
 
     /// Load the config from a file.
     pub async fn load(path: impl AsRef<Path>) -> Result<Config> {
-        let s = tokio::fs::read_to_string(path.as_ref())
-            .await
-            .with_context(|| format!("failed to read {}", path.as_ref().to_string_lossy()))?;
+        Config::builder().file(path).await?.build()
+    }
+
+    /// Returns a builder to layer the config file, `PUBKY_*` environment variables
+    /// and command line overrides.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// The effective config as TOML, with the secret key redacted.
+    pub fn to_toml_redacted(&self) -> Result<String> {
+        let config_toml = ConfigToml {
+            testnet: Some(self.testnet),
+            port: self.listen.is_empty().then_some(self.port),
+            listen: (!self.listen.is_empty()).then(|| self.listen.clone()),
+            public_port: self.public_port,
+            metrics_listen: self.metrics_listen,
+            bootstrap: self.bootstrap.clone(),
+            domain: self.domain.clone(),
+            subdomains: Some(self.subdomains),
+            cors_allowed_origins: self.cors_allowed_origins.clone(),
+            storage: self.storage.parent().map(Path::to_path_buf),
+            secret_key: (self.secret_key_source == SecretKeySource::Config)
+                .then(|| REDACTED.to_string()),
+            secret_key_file: match &self.secret_key_source {
+                SecretKeySource::File(path) => Some(path.clone()),
+                _ => None,
+            },
+            previous_secret_key_files: (!self.previous_keypairs.is_empty()).then(|| {
+                self.previous_keypairs
+                    .iter()
+                    .map(|(path, _)| path.clone())
+                    .collect()
+            }),
+            dht_request_timeout: self.dht_request_timeout,
+            default_list_limit: Some(self.default_list_limit),
+            max_list_limit: Some(self.max_list_limit),
+            upload_session_ttl: Some(self.upload_session_ttl),
+            shutdown_timeout: Some(self.shutdown_timeout),
+            pkarr_ttl: Some(self.pkarr_ttl),
+            pkarr_republish_interval: Some(self.pkarr_republish_interval),
+            public_ips: Some(self.public_ips.clone()),
+            alpn: Some(self.alpn.clone()),
+            max_entry_size: Some(self.max_entry_size),
+            max_auth_body_size: Some(self.max_auth_body_size),
+            max_pkarr_body_size: Some(self.max_pkarr_body_size),
+            replay_cache_capacity: Some(self.replay_cache_capacity),
+            storage_quota: self.storage_quota,
+            user_limits: (!self.user_limits.is_empty()).then(|| {
+                self.user_limits
+                    .iter()
+                    .map(|(public_key, limits)| (public_key.to_string(), limits.clone()))
+                    .collect()
+            }),
+            rate_limits: Some(self.rate_limits.clone()),
+            trusted_proxies: Some(self.trusted_proxies.clone()),
+            db_map_size: Some(self.db_map_size),
+            tls: self.tls.as_ref().map(TlsToml::from),
+        };
+
+        let header = match self.secret_key_source {
+            SecretKeySource::Generated => format!(
+                "# Secret key: persisted in {}, generated on first start\n",
+                self.persisted_secret_key_file().display()
+            ),
+            _ => format!("# Public key: {}\n", self.keypair.public_key()),
+        };
 
-        Config::try_from_str(&s)
+        Ok(header + &toml::to_string(&config_toml)?)
+    }
+
+    fn persisted_secret_key_file(&self) -> PathBuf {
+        self.storage.join(PERSISTED_SECRET_KEY_FILE)
+    }
+
+    /// If no secret key is configured, use the one persisted in the storage directory,
+    /// or persist the generated one, so the server keeps its identity across restarts.
+    pub(crate) fn load_or_persist_secret_key(&mut self) -> Result<()> {
+        if self.secret_key_source != SecretKeySource::Generated {
+            return Ok(());
+        }
+
+        let path = self.persisted_secret_key_file();
+
+        if path.exists() {
+            self.keypair = read_secret_key_file(&path)?;
+        } else {
+            std::fs::create_dir_all(&self.storage)?;
+            write_secret_key_file(&path, &self.keypair)?;
+
+            info!("Generated a secret key, persisted in {}", path.display());
+        }
+
+        self.secret_key_source = SecretKeySource::File(path);
+
+        Ok(())
     }
 
     /// Testnet configurations
@@ -140,6 +631,7 @@ impl Config {
             dht_request_timeout: None,
             db_map_size: DEFAULT_MAP_SIZE,
             keypair: Keypair::from_secret_key(&[0; 32]),
+            secret_key_source: SecretKeySource::Config,
             ..Self::test(&testnet)
         }
     }
@@ -156,6 +648,7 @@ impl Config {
             bootstrap,
             storage,
             db_map_size: 10485760,
+            rate_limits: RateLimits::disabled(),
             ..Default::default()
         }
     }
@@ -164,6 +657,26 @@ impl Config {
         self.port
     }
 
+    /// Addresses to listen on, see [Config::listen].
+    pub fn listen(&self) -> Vec<ListenAddress> {
+        if self.listen.is_empty() {
+            return vec![ListenAddress::Tcp(SocketAddr::from((
+                [0, 0, 0, 0],
+                self.port,
+            )))];
+        }
+
+        self.listen.clone()
+    }
+
+    pub fn public_port(&self) -> Option<u16> {
+        self.public_port
+    }
+
+    pub fn metrics_listen(&self) -> Option<SocketAddr> {
+        self.metrics_listen
+    }
+
     pub fn bootstsrap(&self) -> Option<Vec<String>> {
         self.bootstrap.to_owned()
     }
@@ -172,10 +685,25 @@ impl Config {
         &self.domain
     }
 
+    pub fn subdomains(&self) -> bool {
+        self.subdomains
+    }
+
+    /// The configured [Config::cors_allowed_origins], if any.
+    pub fn cors_allowed_origins(&self) -> Option<&[String]> {
+        self.cors_allowed_origins.as_deref()
+    }
+
     pub fn keypair(&self) -> &Keypair {
         &self.keypair
     }
 
+    /// Keypairs the server used before a key rotation, publishing pkarr packets
+    /// redirecting to [Config::keypair], for clients that still resolve them.
+    pub fn previous_keypairs(&self) -> impl Iterator<Item = &Keypair> {
+        self.previous_keypairs.iter().map(|(_, keypair)| keypair)
+    }
+
     pub fn default_list_limit(&self) -> u16 {
         self.default_list_limit
     }
@@ -184,6 +712,75 @@ impl Config {
         self.max_list_limit
     }
 
+    pub fn upload_session_ttl(&self) -> Duration {
+        self.upload_session_ttl
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        self.shutdown_timeout
+    }
+
+    pub fn tls(&self) -> Option<&Tls> {
+        self.tls.as_ref()
+    }
+
+    pub fn pkarr_ttl(&self) -> u32 {
+        self.pkarr_ttl
+    }
+
+    pub fn pkarr_republish_interval(&self) -> Duration {
+        self.pkarr_republish_interval
+    }
+
+    pub fn public_ips(&self) -> &[IpAddr] {
+        &self.public_ips
+    }
+
+    pub fn alpn(&self) -> &[String] {
+        &self.alpn
+    }
+
+    /// Server wide maximum size of a single entry, ignoring [UserLimits].
+    pub fn default_max_entry_size(&self) -> u64 {
+        self.max_entry_size
+    }
+
+    /// Maximum size of a single entry written by `public_key`.
+    pub fn max_entry_size(&self, public_key: &PublicKey) -> u64 {
+        self.user_limits
+            .get(public_key)
+            .and_then(|limits| limits.max_entry_size)
+            .unwrap_or(self.max_entry_size)
+    }
+
+    /// Maximum total size of all entries of `public_key`, if any.
+    pub fn storage_quota(&self, public_key: &PublicKey) -> Option<u64> {
+        self.user_limits
+            .get(public_key)
+            .and_then(|limits| limits.storage_quota)
+            .or(self.storage_quota)
+    }
+
+    pub fn max_auth_body_size(&self) -> u64 {
+        self.max_auth_body_size
+    }
+
+    pub fn max_pkarr_body_size(&self) -> u64 {
+        self.max_pkarr_body_size
+    }
+
+    pub fn replay_cache_capacity(&self) -> usize {
+        self.replay_cache_capacity
+    }
+
+    pub fn rate_limits(&self) -> &RateLimits {
+        &self.rate_limits
+    }
+
+    pub fn trusted_proxies(&self) -> &[IpAddr] {
+        &self.trusted_proxies
+    }
+
     /// Get the path to the storage directory
     pub fn storage(&self) -> &PathBuf {
         &self.storage
@@ -203,34 +800,311 @@ impl Default for Config {
         Self {
             testnet: false,
             port: 0,
+            listen: Vec::new(),
+            public_port: None,
+            metrics_listen: None,
             bootstrap: None,
             domain: None,
+            subdomains: false,
+            cors_allowed_origins: None,
             storage: storage(None)
                 .expect("operating environment provides no directory for application data"),
             keypair: Keypair::random(),
+            secret_key_source: SecretKeySource::Random,
+            previous_keypairs: Vec::new(),
             dht_request_timeout: None,
             default_list_limit: DEFAULT_LIST_LIMIT,
             max_list_limit: DEFAULT_MAX_LIST_LIMIT,
+            upload_session_ttl: DEFAULT_UPLOAD_SESSION_TTL,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            tls: None,
+            pkarr_ttl: DEFAULT_PKARR_TTL,
+            pkarr_republish_interval: DEFAULT_PKARR_REPUBLISH_INTERVAL,
+            public_ips: Vec::new(),
+            alpn: Vec::new(),
+            max_entry_size: DEFAULT_MAX_ENTRY_SIZE,
+            max_auth_body_size: DEFAULT_MAX_AUTH_BODY_SIZE,
+            max_pkarr_body_size: DEFAULT_MAX_PKARR_BODY_SIZE,
+            replay_cache_capacity: DEFAULT_REPLAY_CACHE_CAPACITY,
+            storage_quota: None,
+            user_limits: HashMap::new(),
+            rate_limits: RateLimits::default(),
+            trusted_proxies: Vec::new(),
             db_map_size: DEFAULT_MAP_SIZE,
         }
     }
 }
 
-fn deserialize_secret_key(s: String) -> anyhow::Result<[u8; 32]> {
-    let bytes =
-        hex::decode(s).map_err(|_| anyhow!("secret_key in config.toml should hex encoded"))?;
+/// Layers of configuration, each overriding the keys set by the previous ones.
+///
+/// From lowest to highest precedence: defaults, [ConfigBuilder::file],
+/// [ConfigBuilder::env] then [ConfigBuilder::set].
+#[derive(Debug, Default)]
+pub struct ConfigBuilder {
+    table: toml::Table,
+    /// The key each layer set, and where it came from, to report invalid values.
+    origins: Vec<(Vec<String>, String)>,
+}
+
+impl ConfigBuilder {
+    /// Read a TOML config file.
+    pub async fn file(self, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+
+        let s = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("failed to read {}", path.to_string_lossy()))?;
+
+        self.toml(&s, &path.to_string_lossy())
+            .with_context(|| format!("failed to parse {}", path.to_string_lossy()))
+    }
+
+    fn toml(mut self, s: &str, origin: &str) -> Result<Self> {
+        let table: toml::Table = s.parse()?;
+
+        for (key, value) in table {
+            self.insert(vec![key], value, origin);
+        }
+
+        Ok(self)
+    }
+
+    /// Override keys with `PUBKY_<KEY>` environment variables, like `PUBKY_PORT=6287`,
+    /// with `__` separating the keys of nested tables, like `PUBKY_TLS__CERT=/etc/pubky/cert.pem`.
+    ///
+    /// Values are parsed as TOML values, or as strings if they are not valid TOML,
+    /// so `PUBKY_DOMAIN=example.com` needs no quotes. Other variables are ignored,
+    /// and so are `PUBKY_*` variables that are not config keys, see [ConfigBuilder::build].
+    pub fn env(mut self, vars: impl IntoIterator<Item = (String, String)>) -> Self {
+        for (name, value) in vars {
+            if let Some(key) = name.strip_prefix(ENV_PREFIX) {
+                let path = key.split("__").map(str::to_lowercase).collect();
+                self.insert(path, parse_value(&value), &name);
+            }
+        }
+
+        self
+    }
+
+    /// Override a key with a `key=value` assignment, like `port=6287`, with `.` separating
+    /// the keys of nested tables, like `tls.cert=/etc/pubky/cert.pem`.
+    ///
+    /// Values are parsed like in [ConfigBuilder::env].
+    pub fn set(mut self, assignment: &str) -> Result<Self> {
+        let (key, value) = assignment
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected `key=value`, got {assignment:?}"))?;
+        let key = key.trim();
+
+        let path = key.split('.').map(String::from).collect();
+        self.insert(path, parse_value(value.trim()), &format!("--set {key}"));
+
+        Ok(self)
+    }
+
+    fn insert(&mut self, path: Vec<String>, value: toml::Value, origin: &str) {
+        let Some((last, parents)) = path.split_last() else {
+            return;
+        };
+
+        let mut table = &mut self.table;
+
+        for key in parents {
+            let entry = table
+                .entry(key.clone())
+                .or_insert_with(|| toml::Value::Table(Default::default()));
+
+            if !entry.is_table() {
+                *entry = toml::Value::Table(Default::default());
+            }
+
+            table = match entry {
+                toml::Value::Table(table) => table,
+                _ => unreachable!(),
+            };
+        }
+
+        table.insert(last.clone(), value);
+
+        self.origins.retain(|(key, _)| !key.starts_with(&path));
+        self.origins.push((path, origin.to_string()));
+    }
+
+    /// Resolve the layers into a [Config].
+    ///
+    /// Unknown keys are logged and ignored, so unrelated `PUBKY_*` environment variables
+    /// don't prevent the server from starting.
+    pub fn build(self) -> Result<Config> {
+        let (config_toml, ignored) = self.deserialize()?;
+
+        for key in ignored {
+            warn!("Ignoring unknown config key {key}");
+        }
+
+        Config::try_from_config_toml(config_toml)
+    }
+
+    /// Deserialize the layers, returning the unknown keys with their origin.
+    fn deserialize(mut self) -> Result<(ConfigToml, Vec<String>)> {
+        let table = std::mem::take(&mut self.table);
+
+        let mut ignored = vec![];
+        let mut callback = |path: serde_ignored::Path| ignored.push(map_keys(&path));
+
+        let config_toml = serde_path_to_error::deserialize(serde_ignored::Deserializer::new(
+            table,
+            &mut callback,
+        ))
+        .map_err(|error| {
+            let path = error
+                .path()
+                .iter()
+                .filter_map(|segment| match segment {
+                    serde_path_to_error::Segment::Map { key } => Some(key.clone()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+
+            let origin = self.origin(&path);
+
+            match error.path().to_string().as_str() {
+                "." => anyhow!("{}{origin}", error.inner().message()),
+                key => anyhow!("{key}: {}{origin}", error.inner().message()),
+            }
+        })?;
+
+        let ignored = ignored
+            .iter()
+            .map(|path| format!("{}{}", path.join("."), self.origin(path)))
+            .collect();
+
+        Ok((config_toml, ignored))
+    }
+
+    /// ` (from <origin>)` of the layer that set the key at `path`, if any.
+    fn origin(&self, path: &[String]) -> String {
+        self.origins
+            .iter()
+            .filter(|(key, _)| path.starts_with(key))
+            .max_by_key(|(key, _)| key.len())
+            .map(|(_, origin)| format!(" (from {origin})"))
+            .unwrap_or_default()
+    }
+}
+
+/// The keys of the tables leading to an ignored value.
+fn map_keys(path: &serde_ignored::Path) -> Vec<String> {
+    use serde_ignored::Path;
+
+    match path {
+        Path::Root => vec![],
+        Path::Map { parent, key } => {
+            let mut keys = map_keys(parent);
+            keys.push(key.clone());
+            keys
+        }
+        Path::Seq { parent, .. }
+        | Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => map_keys(parent),
+    }
+}
+
+/// Parse an override as a TOML value, or as a string if it is not one, like a path or a domain.
+fn parse_value(value: &str) -> toml::Value {
+    format!("value = {value}")
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+/// Where the server's [Keypair] comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SecretKeySource {
+    /// `secret_key` in the config.
+    Config,
+    /// `secret_key_file`, or the file persisting a generated key.
+    File(PathBuf),
+    /// Generated, to be persisted on start, see [Config::load_or_persist_secret_key].
+    Generated,
+    /// Random and never persisted, for configs built in code, like [Config::test].
+    Random,
+}
+
+/// Normalize an origin, like `https://example.com`, as browsers send it in the `Origin` header.
+fn parse_origin(origin: &str) -> Result<String> {
+    if origin == "*" {
+        return Ok(origin.to_string());
+    }
+
+    let serialized = url::Url::parse(origin)
+        .map(|url| url.origin().ascii_serialization())
+        .unwrap_or_default();
+
+    if serialized == "null" || serialized.is_empty() {
+        return Err(anyhow!("cors_allowed_origins: invalid origin {origin:?}"));
+    }
+
+    Ok(serialized)
+}
+
+fn parse_secret_key(s: &str) -> Result<Keypair> {
+    let bytes = hex::decode(s.trim()).map_err(|_| anyhow!("should be hex encoded"))?;
 
-    if bytes.len() != 32 {
-        return Err(anyhow!(format!(
-            "secret_key in config.toml should be 32 bytes in hex (64 characters), got: {}",
+    let secret_key: [u8; 32] = bytes.as_slice().try_into().map_err(|_| {
+        anyhow!(
+            "should be 32 bytes in hex (64 characters), got: {}",
             bytes.len()
-        )));
+        )
+    })?;
+
+    Ok(Keypair::from_secret_key(&secret_key))
+}
+
+/// Read a secret key in hex from a file, refusing files accessible by other users.
+pub fn read_secret_key_file(path: &Path) -> Result<Keypair> {
+    let metadata = std::fs::metadata(path)
+        .map_err(|error| anyhow!("failed to read {}: {error}", path.display()))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = metadata.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            return Err(anyhow!(
+                "{} is accessible by other users (mode {mode:o}), restrict it with `chmod 600`",
+                path.display()
+            ));
+        }
     }
+    #[cfg(not(unix))]
+    let _ = metadata;
 
-    let mut arr = [0u8; 32];
-    arr.copy_from_slice(&bytes);
+    let s = std::fs::read_to_string(path)
+        .map_err(|error| anyhow!("failed to read {}: {error}", path.display()))?;
 
-    Ok(arr)
+    parse_secret_key(&s).map_err(|error| anyhow!("{}: {error}", path.display()))
+}
+
+/// Write the secret key of `keypair` in hex to a new file, only accessible by the current user.
+pub fn write_secret_key_file(path: &Path, keypair: &Keypair) -> Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options
+        .open(path)
+        .map_err(|error| anyhow!("failed to create {}: {error}", path.display()))?;
+
+    writeln!(file, "{}", hex::encode(keypair.secret_key()))?;
+
+    Ok(())
 }
 
 fn storage(storage: Option<String>) -> Result<PathBuf> {
@@ -260,11 +1134,338 @@ mod tests {
             config,
             Config {
                 keypair: config.keypair.clone(),
+                secret_key_source: SecretKeySource::Generated,
                 ..Default::default()
             }
         )
     }
 
+    #[test]
+    fn parse_user_limits() {
+        let public_key = Keypair::random().public_key();
+
+        let config = Config::try_from_str(&format!(
+            r#"
+            max_entry_size = 1000
+            storage_quota = 5000
+
+            [user_limits.{public_key}]
+            storage_quota = 10000
+            "#
+        ))
+        .unwrap();
+
+        assert_eq!(config.max_entry_size(&public_key), 1000);
+        assert_eq!(config.storage_quota(&public_key), Some(10000));
+
+        let other = Keypair::random().public_key();
+        assert_eq!(config.storage_quota(&other), Some(5000));
+
+        let error = Config::try_from_str("[user_limits.foo]\nstorage_quota = 1").unwrap_err();
+        assert_eq!(error.to_string(), "user_limits: invalid public key foo");
+    }
+
+    #[test]
+    fn parse_listen() {
+        let config = Config::try_from_str(
+            r#"
+            listen = ["127.0.0.1:6287", "[::1]:6287", "unix:/run/pubky/homeserver.sock"]
+            public_port = 443
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.listen(),
+            vec![
+                ListenAddress::Tcp(([127, 0, 0, 1], 6287).into()),
+                ListenAddress::Tcp((std::net::Ipv6Addr::LOCALHOST, 6287).into()),
+                ListenAddress::Unix("/run/pubky/homeserver.sock".into()),
+            ]
+        );
+        assert_eq!(config.public_port(), Some(443));
+
+        for address in config.listen() {
+            assert_eq!(
+                address.to_string().parse::<ListenAddress>().unwrap(),
+                address
+            );
+        }
+
+        let config = Config::try_from_str("port = 6287").unwrap();
+        assert_eq!(
+            config.listen(),
+            vec![ListenAddress::Tcp(([0, 0, 0, 0], 6287).into())]
+        );
+
+        let error = Config::try_from_str(r#"listen = ["localhost"]"#).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            r#"listen[0]: invalid address "localhost" (from config)"#
+        );
+
+        let error = Config::try_from_str("port = 6287\nlisten = [\"127.0.0.1:6287\"]").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "listen: set either `port` or `listen`, not both"
+        );
+    }
+
+    #[test]
+    fn parse_tls() {
+        let config = Config::try_from_str(
+            r#"
+            [tls]
+            cert = "/etc/pubky/fullchain.pem"
+            key = "/etc/pubky/privkey.pem"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.tls(),
+            Some(&Tls {
+                cert: "/etc/pubky/fullchain.pem".into(),
+                key: "/etc/pubky/privkey.pem".into(),
+                reload_interval: DEFAULT_TLS_RELOAD_INTERVAL,
+            })
+        );
+
+        let error = Config::try_from_str("[tls]\ncert = \"cert.pem\"").unwrap_err();
+        assert_eq!(error.to_string(), "tls: missing field `key` (from config)");
+    }
+
+    #[test]
+    fn overrides() {
+        let config = Config::builder()
+            .toml(
+                r#"
+                port = 6287
+                domain = "localhost"
+
+                [tls]
+                cert = "/etc/pubky/fullchain.pem"
+                key = "/etc/pubky/privkey.pem"
+                "#,
+                "config.toml",
+            )
+            .unwrap()
+            .env([
+                ("PUBKY_PORT".to_string(), "6288".to_string()),
+                ("PUBKY_DOMAIN".to_string(), "example.com".to_string()),
+                (
+                    "PUBKY_TLS__RELOAD_INTERVAL".to_string(),
+                    "{ secs = 5, nanos = 0 }".to_string(),
+                ),
+                ("HOME".to_string(), "/root".to_string()),
+            ])
+            .set("port=6289")
+            .unwrap()
+            .set("rate_limits.auth.per_ip = { burst = 1, per_minute = 2 }")
+            .unwrap()
+            .build()
+            .unwrap();
+
+        assert_eq!(config.port(), 6289);
+        assert_eq!(config.domain(), &Some("example.com".to_string()));
+        assert_eq!(
+            config.tls(),
+            Some(&Tls {
+                cert: "/etc/pubky/fullchain.pem".into(),
+                key: "/etc/pubky/privkey.pem".into(),
+                reload_interval: Duration::from_secs(5),
+            })
+        );
+        assert_eq!(config.rate_limits().auth.per_ip, Some(Quota::new(1, 2)));
+        assert_eq!(config.rate_limits().auth.per_pubky, None);
+    }
+
+    #[test]
+    fn override_errors() {
+        let error = Config::builder()
+            .toml("port = 6287", "config.toml")
+            .unwrap()
+            .env([("PUBKY_PORT".to_string(), "abc".to_string())])
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            r#"port: invalid type: string "abc", expected u16 (from PUBKY_PORT)"#
+        );
+
+        let error = Config::builder()
+            .toml(
+                "[tls]\ncert = \"cert.pem\"\nkey = \"key.pem\"",
+                "config.toml",
+            )
+            .unwrap()
+            .set("tls.reload_interval=60")
+            .unwrap()
+            .build()
+            .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("tls.reload_interval: invalid type: integer `60`"),
+            "{error}"
+        );
+        assert!(
+            error
+                .to_string()
+                .ends_with("(from --set tls.reload_interval)"),
+            "{error}"
+        );
+
+        let error = Config::builder().set("port").unwrap_err();
+        assert_eq!(error.to_string(), r#"expected `key=value`, got "port""#);
+    }
+
+    #[test]
+    fn ignore_unknown_keys() {
+        let (config_toml, ignored) = Config::builder()
+            .toml(
+                "port = 6287\nprot = 6288\n[tls]\ncert = \"cert.pem\"\nkey = \"key.pem\"\nkye = 1",
+                "config.toml",
+            )
+            .unwrap()
+            .env([
+                (
+                    "PUBKY_HOMESERVER_URL".to_string(),
+                    "https://example.com".to_string(),
+                ),
+                ("PUBKY_DOMAIN".to_string(), "example.com".to_string()),
+            ])
+            .deserialize()
+            .unwrap();
+
+        assert_eq!(config_toml.port, Some(6287));
+        assert_eq!(config_toml.domain, Some("example.com".to_string()));
+        assert_eq!(
+            ignored,
+            [
+                "homeserver_url (from PUBKY_HOMESERVER_URL)",
+                "prot (from config.toml)",
+                "tls.kye (from config.toml)",
+            ]
+        );
+    }
+
+    #[test]
+    fn print_config() {
+        let secret_key = hex::encode(Keypair::random().secret_key());
+
+        let config = Config::try_from_str(&format!(
+            r#"
+            secret_key = "{secret_key}"
+            listen = ["127.0.0.1:6287", "unix:/run/pubky/homeserver.sock"]
+            storage = "/var/lib/pubky"
+            max_list_limit = 500
+            upload_session_ttl = {{ secs = 600, nanos = 0 }}
+
+            [user_limits.{}]
+            storage_quota = 10000
+
+            [tls]
+            cert = "/etc/pubky/fullchain.pem"
+            key = "/etc/pubky/privkey.pem"
+            "#,
+            Keypair::random().public_key(),
+        ))
+        .unwrap();
+
+        let printed = config.to_toml_redacted().unwrap();
+
+        assert!(!printed.contains(&secret_key));
+        assert!(printed.contains(&config.keypair().public_key().to_string()));
+
+        let reloaded = Config::try_from_str(&printed.replace(REDACTED, &secret_key)).unwrap();
+        assert_eq!(reloaded, config);
+    }
+
+    #[test]
+    fn secret_key_file() {
+        let dir = std::env::temp_dir().join(Timestamp::now().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let keypair = Keypair::random();
+        let path = dir.join("secret_key");
+        write_secret_key_file(&path, &keypair).unwrap();
+
+        let previous = Keypair::random();
+        let previous_path = dir.join("previous_secret_key");
+        write_secret_key_file(&previous_path, &previous).unwrap();
+
+        let config = Config::try_from_str(&format!(
+            "secret_key_file = {path:?}\nprevious_secret_key_files = [{previous_path:?}]"
+        ))
+        .unwrap();
+        assert_eq!(config.keypair(), &keypair);
+        assert_eq!(
+            config.previous_keypairs().collect::<Vec<_>>(),
+            vec![&previous]
+        );
+
+        // Never overwritten.
+        assert!(write_secret_key_file(&path, &Keypair::random()).is_err());
+
+        let error = Config::try_from_str(&format!(
+            "secret_key_file = {path:?}\nsecret_key = \"{}\"",
+            hex::encode(keypair.secret_key())
+        ))
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "secret_key: set either `secret_key` or `secret_key_file`, not both"
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+            let error = Config::try_from_str(&format!("secret_key_file = {path:?}")).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!(
+                    "secret_key_file: {} is accessible by other users (mode 644), restrict it with `chmod 600`",
+                    path.display()
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn persist_generated_secret_key() {
+        let storage = std::env::temp_dir().join(Timestamp::now().to_string());
+        let toml = format!("storage = {storage:?}");
+
+        let mut first = Config::try_from_str(&toml).unwrap();
+        first.load_or_persist_secret_key().unwrap();
+
+        let mut second = Config::try_from_str(&toml).unwrap();
+        assert_ne!(second.keypair(), first.keypair());
+
+        second.load_or_persist_secret_key().unwrap();
+        assert_eq!(second.keypair(), first.keypair());
+
+        // Configured keys are not persisted.
+        let mut config = Config::try_from_str(&format!(
+            "{toml}\nsecret_key = \"{}\"",
+            hex::encode([1; 32])
+        ))
+        .unwrap();
+        config.load_or_persist_secret_key().unwrap();
+        assert_eq!(config.keypair(), &Keypair::from_secret_key(&[1; 32]));
+
+        // Neither are the random keys of configs built in code.
+        let mut config = Config::test(&Testnet::new(0));
+        let keypair = config.keypair().clone();
+        config.load_or_persist_secret_key().unwrap();
+        assert_eq!(config.keypair(), &keypair);
+        assert!(!config.persisted_secret_key_file().exists());
+    }
+
     #[test]
     fn config_test() {
         let testnet = Testnet::new(3);
@@ -276,6 +1477,7 @@ mod tests {
                 testnet: true,
                 bootstrap: testnet.bootstrap.into(),
                 db_map_size: 10485760,
+                rate_limits: RateLimits::disabled(),
 
                 storage: config.storage.clone(),
                 keypair: config.keypair.clone(),
@@ -293,6 +1495,8 @@ mod tests {
             Config {
                 testnet: true,
                 port: 15411,
+                rate_limits: RateLimits::disabled(),
+                secret_key_source: SecretKeySource::Config,
 
                 bootstrap: config.bootstrap.clone(),
                 storage: config.storage.clone(),
@@ -0,0 +1,125 @@
+//! Validation of the content types stored with entries, and the headers
+//! preventing browsers from running them as active content on the homeserver's origin.
+
+use axum::{
+    body::Body,
+    http::{header, HeaderValue, Response},
+};
+
+/// Parse a `Content-Type` like `text/plain; charset=utf-8`, returning it with
+/// a lowercase type and subtype, or `None` if it isn't a valid media type.
+pub fn parse(value: &str) -> Option<String> {
+    let mut parts = value.split(';');
+
+    let essence = parts.next()?.trim().to_ascii_lowercase();
+    let (kind, subtype) = essence.split_once('/')?;
+    if !is_token(kind) || !is_token(subtype) {
+        return None;
+    }
+
+    let mut content_type = essence;
+
+    for parameter in parts.filter(|parameter| !parameter.trim().is_empty()) {
+        let (name, value) = parameter.trim().split_once('=')?;
+        if !is_token(name) || !(is_token(value) || is_quoted_string(value)) {
+            return None;
+        }
+
+        content_type.push_str("; ");
+        content_type.push_str(&name.to_ascii_lowercase());
+        content_type.push('=');
+        content_type.push_str(value);
+    }
+
+    Some(content_type)
+}
+
+/// Whether browsers may run scripts from a content type served inline,
+/// so it has to be served as an attachment instead.
+pub fn is_active(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    essence.ends_with("+xml")
+        || essence.contains("javascript")
+        || essence.contains("ecmascript")
+        || matches!(
+            essence.as_str(),
+            "text/html"
+                | "text/xml"
+                | "text/xsl"
+                | "application/xml"
+                | "application/pdf"
+                | "text/vnd.wap.wml"
+                | "multipart/x-mixed-replace"
+        )
+}
+
+/// Forbid browsers from guessing the content type of any response.
+pub async fn nosniff(mut response: Response<Body>) -> Response<Body> {
+    response.headers_mut().insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+
+    response
+}
+
+/// RFC 7230 token characters.
+fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+fn is_quoted_string(value: &str) -> bool {
+    value.len() >= 2
+        && value.starts_with('"')
+        && value.ends_with('"')
+        && value[1..value.len() - 1]
+            .bytes()
+            .all(|byte| byte == b'\t' || (byte >= b' ' && byte != 0x7f))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_content_type() {
+        assert_eq!(
+            parse("application/json").as_deref(),
+            Some("application/json")
+        );
+        assert_eq!(parse("text/plain;").as_deref(), Some("text/plain"));
+        assert_eq!(
+            parse("Text/Plain;Charset=utf-8").as_deref(),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(
+            parse("multipart/form-data; boundary=\"a b\"").as_deref(),
+            Some("multipart/form-data; boundary=\"a b\"")
+        );
+
+        for invalid in ["", "text", "text/", "/plain", "text/pl ain", "a/b; c"] {
+            assert_eq!(parse(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn active_content() {
+        assert!(is_active("text/html; charset=utf-8"));
+        assert!(is_active("image/svg+xml"));
+        assert!(is_active("application/xhtml+xml"));
+        assert!(is_active("text/javascript"));
+        assert!(!is_active("text/plain"));
+        assert!(!is_active("application/json"));
+        assert!(!is_active("image/png"));
+        assert!(!is_active(""));
+    }
+}
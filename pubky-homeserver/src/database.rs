@@ -1,9 +1,11 @@
 use std::{fs, path::PathBuf};
 
 use heed::{Env, EnvOpenOptions};
+use tracing::info;
 
 mod migrations;
 pub mod tables;
+pub mod uploads;
 
 use crate::config::Config;
 
@@ -22,8 +24,6 @@ impl DB {
     pub fn open(config: Config) -> anyhow::Result<Self> {
         let buffers_dir = config.storage().clone().join("buffers");
 
-        // Cleanup buffers.
-        let _ = fs::remove_dir(&buffers_dir);
         fs::create_dir_all(&buffers_dir)?;
 
         let env = unsafe {
@@ -43,8 +43,54 @@ impl DB {
             max_chunk_size: max_chunk_size(),
         };
 
+        // Buffers of entries that were being written when the server crashed.
+        let count = db.remove_entry_buffers()?;
+        if count > 0 {
+            info!(?count, "Removed orphaned entry buffers");
+        }
+
         Ok(db)
     }
+
+    /// Remove the buffers of [EntryWriter](tables::entries::EntryWriter)s that were never
+    /// committed nor dropped, keeping upload sessions.
+    ///
+    /// Only runs in [DB::open], before any entry can be written, since it would remove
+    /// the buffers of writers still running, for example in aborted requests' blocking tasks.
+    ///
+    /// Returns the number of removed buffers.
+    fn remove_entry_buffers(&self) -> anyhow::Result<usize> {
+        let mut count = 0;
+
+        for dir_entry in fs::read_dir(&self.buffers_dir)? {
+            let dir_entry = dir_entry?;
+
+            if dir_entry.file_type()?.is_file() {
+                fs::remove_file(dir_entry.path())?;
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+}
+
+/// Remove the buffer file at `path`, ignoring errors.
+///
+/// Writers are dropped on the runtime's worker threads when requests are aborted,
+/// which shouldn't block on the filesystem, so the file is removed in a blocking task
+/// if there is a runtime.
+pub(crate) fn remove_buffer(path: PathBuf) {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => {
+            handle.spawn_blocking(move || {
+                let _ = fs::remove_file(path);
+            });
+        }
+        Err(_) => {
+            let _ = fs::remove_file(path);
+        }
+    }
 }
 
 /// calculate optimal chunk size:
@@ -61,3 +107,33 @@ fn max_chunk_size() -> usize {
     //      - chunk index: 4 bytes
     ((page_size - 16) / 2) - (8 + 2) - 12
 }
+
+#[cfg(test)]
+mod tests {
+    use pkarr::mainline::Testnet;
+
+    use super::*;
+
+    #[test]
+    fn remove_orphaned_buffers() -> anyhow::Result<()> {
+        let config = Config::test(&Testnet::new(0));
+
+        let buffers_dir = config.storage().join("buffers");
+        let upload_dir = buffers_dir.join("uploads").join("0000000000000");
+        fs::create_dir_all(&upload_dir)?;
+
+        // Left behind by a crash.
+        fs::write(buffers_dir.join("0000000000001"), b"foo")?;
+        fs::write(upload_dir.join("0"), b"bar")?;
+
+        let db = DB::open(config)?;
+
+        assert!(!buffers_dir.join("0000000000001").exists());
+        // Upload sessions are resumable.
+        assert!(upload_dir.join("0").exists());
+
+        assert_eq!(db.remove_entry_buffers()?, 0);
+
+        Ok(())
+    }
+}
@@ -1,6 +1,9 @@
 use heed::Env;
 
 mod m0;
+mod m1;
+mod m2;
+mod m3;
 
 use super::tables::Tables;
 
@@ -8,6 +11,9 @@ pub fn run(env: &Env) -> anyhow::Result<Tables> {
     let mut wtxn = env.write_txn()?;
 
     m0::run(env, &mut wtxn)?;
+    m1::run(env, &mut wtxn)?;
+    m2::run(env, &mut wtxn)?;
+    m3::run(env, &mut wtxn)?;
 
     let tables = Tables::new(env, &mut wtxn)?;
 
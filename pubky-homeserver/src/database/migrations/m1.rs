@@ -0,0 +1,10 @@
+use heed::{Env, RwTxn};
+
+use crate::database::tables::blob_refs;
+
+pub fn run(env: &Env, wtxn: &mut RwTxn) -> anyhow::Result<()> {
+    let _: blob_refs::BlobRefsTable =
+        env.create_database(wtxn, Some(blob_refs::BLOB_REFS_TABLE))?;
+
+    Ok(())
+}
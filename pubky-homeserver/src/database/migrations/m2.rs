@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+
+use heed::{Env, RwTxn};
+
+use crate::database::tables::{
+    directories::{self, ancestors, DirectoryStats},
+    entries::{self, Entry},
+};
+
+/// Create the directories table, and backfill it from existing entries.
+pub fn run(env: &Env, wtxn: &mut RwTxn) -> anyhow::Result<()> {
+    let existing: Option<directories::DirectoriesTable> =
+        env.open_database(wtxn, Some(directories::DIRECTORIES_TABLE))?;
+
+    if existing.is_some() {
+        return Ok(());
+    }
+
+    let table: directories::DirectoriesTable =
+        env.create_database(wtxn, Some(directories::DIRECTORIES_TABLE))?;
+
+    let Some(entries_table): Option<entries::EntriesTable> =
+        env.open_database(wtxn, Some(entries::ENTRIES_TABLE))?
+    else {
+        return Ok(());
+    };
+
+    let mut stats: HashMap<String, DirectoryStats> = HashMap::new();
+
+    for result in entries_table.iter(wtxn)? {
+        let (key, bytes) = result?;
+        let entry = Entry::deserialize(bytes)?;
+
+        for directory in ancestors(key) {
+            stats
+                .entry(directory.to_string())
+                .or_default()
+                .add(&entry, entry.timestamp());
+        }
+    }
+
+    for (directory, stats) in stats {
+        table.put(wtxn, &directory, &stats.serialize())?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,10 @@
+use heed::{Env, RwTxn};
+
+use crate::database::tables::auth_tokens;
+
+pub fn run(env: &Env, wtxn: &mut RwTxn) -> anyhow::Result<()> {
+    let _: auth_tokens::AuthTokensTable =
+        env.create_database(wtxn, Some(auth_tokens::AUTH_TOKENS_TABLE))?;
+
+    Ok(())
+}
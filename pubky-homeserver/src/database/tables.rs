@@ -1,4 +1,7 @@
+pub mod auth_tokens;
+pub mod blob_refs;
 pub mod blobs;
+pub mod directories;
 pub mod entries;
 pub mod events;
 pub mod sessions;
@@ -6,7 +9,10 @@ pub mod users;
 
 use heed::{Env, RwTxn};
 
+use auth_tokens::{AuthTokensTable, AUTH_TOKENS_TABLE};
+use blob_refs::{BlobRefsTable, BLOB_REFS_TABLE};
 use blobs::{BlobsTable, BLOBS_TABLE};
+use directories::{DirectoriesTable, DIRECTORIES_TABLE};
 use entries::{EntriesTable, ENTRIES_TABLE};
 
 use self::{
@@ -15,15 +21,18 @@ use self::{
     users::{UsersTable, USERS_TABLE},
 };
 
-pub const TABLES_COUNT: u32 = 5;
+pub const TABLES_COUNT: u32 = 8;
 
 #[derive(Debug, Clone)]
 pub struct Tables {
     pub users: UsersTable,
     pub sessions: SessionsTable,
     pub blobs: BlobsTable,
+    pub blob_refs: BlobRefsTable,
     pub entries: EntriesTable,
     pub events: EventsTable,
+    pub directories: DirectoriesTable,
+    pub auth_tokens: AuthTokensTable,
 }
 
 impl Tables {
@@ -38,12 +47,21 @@ impl Tables {
             blobs: env
                 .open_database(wtxn, Some(BLOBS_TABLE))?
                 .expect("Blobs table already created"),
+            blob_refs: env
+                .open_database(wtxn, Some(BLOB_REFS_TABLE))?
+                .expect("Blob refs table already created"),
             entries: env
                 .open_database(wtxn, Some(ENTRIES_TABLE))?
                 .expect("Entries table already created"),
             events: env
                 .open_database(wtxn, Some(EVENTS_TABLE))?
                 .expect("Events table already created"),
+            directories: env
+                .open_database(wtxn, Some(DIRECTORIES_TABLE))?
+                .expect("Directories table already created"),
+            auth_tokens: env
+                .open_database(wtxn, Some(AUTH_TOKENS_TABLE))?
+                .expect("Auth tokens table already created"),
         })
     }
 }
@@ -0,0 +1,121 @@
+//! IDs of used [AuthToken](pubky_common::auth::AuthToken)s, persisted so replays are rejected
+//! across restarts and by every homeserver process sharing the same storage.
+
+use std::ops::Bound;
+
+use heed::{
+    types::{Bytes, Unit},
+    Database,
+};
+use pubky_common::{
+    auth::{Error, ReplayCache},
+    timestamp::Timestamp,
+};
+
+use crate::database::DB;
+
+/// Token ID (timestamp + public key) => ().
+pub type AuthTokensTable = Database<Bytes, Unit>;
+
+pub const AUTH_TOKENS_TABLE: &str = "auth_tokens";
+
+impl ReplayCache for DB {
+    fn insert(&self, id: &[u8], expired_before: &Timestamp) -> Result<bool, Error> {
+        let mut wtxn = self.env.write_txn().map_err(cache_error)?;
+
+        if self
+            .tables
+            .auth_tokens
+            .get(&wtxn, id)
+            .map_err(cache_error)?
+            .is_some()
+        {
+            return Ok(false);
+        }
+
+        let capacity = self.config.replay_cache_capacity() as u64;
+
+        if self.tables.auth_tokens.len(&wtxn).map_err(cache_error)? >= capacity {
+            let threshold = expired_before.to_bytes();
+            let range = (Bound::Unbounded, Bound::Excluded(threshold.as_slice()));
+
+            self.tables
+                .auth_tokens
+                .delete_range(&mut wtxn, &range)
+                .map_err(cache_error)?;
+
+            if self.tables.auth_tokens.len(&wtxn).map_err(cache_error)? >= capacity {
+                return Err(Error::ReplayCacheFull);
+            }
+        }
+
+        self.tables
+            .auth_tokens
+            .put(&mut wtxn, id, &())
+            .map_err(cache_error)?;
+
+        wtxn.commit().map_err(cache_error)?;
+
+        Ok(true)
+    }
+}
+
+fn cache_error(error: heed::Error) -> Error {
+    Error::ReplayCache(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use pkarr::{mainline::Testnet, Keypair};
+    use pubky_common::auth::{AuthToken, AuthVerifier};
+    use pubky_common::capabilities::Capability;
+
+    use crate::config::Config;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn persisted_replay_cache() {
+        let config = Config::test(&Testnet::new(0));
+
+        let token = AuthToken::sign(&Keypair::random(), vec![Capability::root()]).serialize();
+
+        {
+            let db = DB::open(config.clone()).unwrap();
+            AuthVerifier::new(db).verify(&token).unwrap();
+        }
+
+        // Reopening the database, as if the homeserver restarted.
+        let db = DB::open(config).unwrap();
+        assert_eq!(
+            AuthVerifier::new(db.clone()).verify(&token).unwrap_err(),
+            Error::AlreadyUsed
+        );
+    }
+
+    #[tokio::test]
+    async fn evict_expired_when_full() {
+        let config = Config::try_from_str(&format!(
+            r#"
+            storage = {:?}
+            replay_cache_capacity = 1
+            db_map_size = 10485760
+            "#,
+            Config::test(&Testnet::new(0)).storage(),
+        ))
+        .unwrap();
+
+        let db = DB::open(config).unwrap();
+        let now = Timestamp::now();
+
+        assert!(db.insert(&now.to_bytes(), &(now - 1)).unwrap());
+        assert_eq!(
+            db.insert(&(now + 1).to_bytes(), &(now - 1)),
+            Err(Error::ReplayCacheFull)
+        );
+
+        // Once the first token expired, it makes room for new ones.
+        assert!(db.insert(&(now + 1).to_bytes(), &(now + 1)).unwrap());
+        assert!(!db.insert(&(now + 1).to_bytes(), &(now + 1)).unwrap());
+    }
+}
@@ -0,0 +1,74 @@
+//! Reference counts of blobs shared by more than one [Entry][super::entries::Entry].
+//!
+//! Blobs are keyed by the timestamp of the entry that wrote them, so copying an
+//! entry re-points the new entry at the same blob instead of duplicating chunks.
+
+use heed::{
+    byteorder::BigEndian,
+    types::{Bytes, U64},
+    Database, RwTxn,
+};
+
+use pubky_common::timestamp::Timestamp;
+
+use crate::database::DB;
+
+/// Blob timestamp => Number of entries referencing it.
+///
+/// Missing keys mean the blob is referenced by exactly one entry.
+pub type BlobRefsTable = Database<Bytes, U64<BigEndian>>;
+
+pub const BLOB_REFS_TABLE: &str = "blob_refs";
+
+impl DB {
+    /// Increment the reference count of the blob written at `timestamp`.
+    pub(crate) fn retain_blob(
+        &self,
+        wtxn: &mut RwTxn,
+        timestamp: &Timestamp,
+    ) -> anyhow::Result<()> {
+        let key = timestamp.to_bytes();
+
+        let count = self.tables.blob_refs.get(wtxn, &key)?.unwrap_or(1);
+
+        self.tables.blob_refs.put(wtxn, &key, &(count + 1))?;
+
+        Ok(())
+    }
+
+    /// Decrement the reference count of the blob written at `timestamp`,
+    /// deleting its chunks if no other entry references it.
+    ///
+    /// Returns `true` if the chunks were deleted.
+    pub(crate) fn release_blob(
+        &self,
+        wtxn: &mut RwTxn,
+        timestamp: &Timestamp,
+    ) -> anyhow::Result<bool> {
+        let key = timestamp.to_bytes();
+
+        match self.tables.blob_refs.get(wtxn, &key)? {
+            Some(count) if count > 2 => {
+                self.tables.blob_refs.put(wtxn, &key, &(count - 1))?;
+            }
+            Some(_) => {
+                self.tables.blob_refs.delete(wtxn, &key)?;
+            }
+            None => {
+                let mut deleted_chunks = false;
+
+                let mut iter = self.tables.blobs.prefix_iter_mut(wtxn, &key)?;
+
+                while iter.next().is_some() {
+                    unsafe {
+                        deleted_chunks = iter.del_current()?;
+                    }
+                }
+
+                return Ok(deleted_chunks);
+            }
+        }
+
+        Ok(false)
+    }
+}
@@ -0,0 +1,259 @@
+//! Aggregated stats of directories, updated whenever an entry is written or removed.
+
+use heed::{
+    types::{Bytes, Str},
+    Database, RoTxn, RwTxn,
+};
+use pkarr::PublicKey;
+use postcard::{from_bytes, to_allocvec};
+use serde::{Deserialize, Serialize};
+
+use pubky_common::{list::DirectoryStat, timestamp::Timestamp};
+
+use crate::database::DB;
+
+use super::entries::Entry;
+
+/// Directory path (`<public_key>/<path>/`) => [DirectoryStats].
+pub type DirectoriesTable = Database<Str, Bytes>;
+
+pub const DIRECTORIES_TABLE: &str = "directories";
+
+#[derive(Clone, Default, Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub struct DirectoryStats {
+    /// Encoding version
+    version: usize,
+    /// Total length of the content of all entries under this directory.
+    content_length: u64,
+    /// Number of entries under this directory, recursively.
+    entries_count: u64,
+    /// Last time an entry under this directory was written or removed.
+    last_modified: Timestamp,
+}
+
+impl DirectoryStats {
+    // === Getters ===
+
+    pub fn content_length(&self) -> u64 {
+        self.content_length
+    }
+
+    pub fn entries_count(&self) -> u64 {
+        self.entries_count
+    }
+
+    pub fn last_modified(&self) -> &Timestamp {
+        &self.last_modified
+    }
+
+    // === Public Method ===
+
+    /// Account for an `entry` added under this directory at `modified_at`.
+    pub fn add(&mut self, entry: &Entry, modified_at: &Timestamp) -> &mut Self {
+        self.content_length += entry.content_length() as u64;
+        self.entries_count += 1;
+        self.last_modified = self.last_modified.max(*modified_at);
+        self
+    }
+
+    /// Account for an `entry` removed from this directory at `modified_at`.
+    pub fn remove(&mut self, entry: &Entry, modified_at: &Timestamp) -> &mut Self {
+        self.content_length = self
+            .content_length
+            .saturating_sub(entry.content_length() as u64);
+        self.entries_count = self.entries_count.saturating_sub(1);
+        self.last_modified = self.last_modified.max(*modified_at);
+        self
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        to_allocvec(self).expect("DirectoryStats::serialize")
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> core::result::Result<Self, postcard::Error> {
+        if bytes[0] > 0 {
+            panic!("Unknown DirectoryStats version");
+        }
+
+        from_bytes(bytes)
+    }
+}
+
+impl From<&DirectoryStats> for DirectoryStat {
+    fn from(stats: &DirectoryStats) -> Self {
+        DirectoryStat {
+            content_length: stats.content_length,
+            entries_count: stats.entries_count,
+            last_modified: stats.last_modified.format_http_date(),
+        }
+    }
+}
+
+impl DB {
+    /// Get the stats of the directory at `path` (ending with `/`).
+    pub fn get_directory_stats(
+        &self,
+        txn: &RoTxn,
+        public_key: &PublicKey,
+        path: &str,
+    ) -> anyhow::Result<Option<DirectoryStats>> {
+        let key = format!("{public_key}/{path}");
+
+        if let Some(bytes) = self.tables.directories.get(txn, &key)? {
+            return Ok(Some(DirectoryStats::deserialize(bytes)?));
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the total content length of all entries of `public_key`,
+    /// as if the entry at `path` was replaced with `content_length` bytes.
+    pub fn storage_usage_after_write(
+        &self,
+        public_key: &PublicKey,
+        path: &str,
+        content_length: u64,
+    ) -> anyhow::Result<u64> {
+        let rtxn = self.env.read_txn()?;
+
+        let usage = self
+            .get_directory_stats(&rtxn, public_key, "")?
+            .map(|stats| stats.content_length)
+            .unwrap_or_default();
+
+        let replaced = self
+            .get_entry(&rtxn, public_key, path)?
+            .map(|entry| entry.content_length() as u64)
+            .unwrap_or_default();
+
+        rtxn.commit()?;
+
+        Ok(usage.saturating_sub(replaced) + content_length)
+    }
+
+    /// Returns the total content length of the entry at `path`, or of every
+    /// entry under it if it is a directory (ends with `/`).
+    pub fn content_length(&self, public_key: &PublicKey, path: &str) -> anyhow::Result<u64> {
+        let rtxn = self.env.read_txn()?;
+
+        let content_length = if path.ends_with('/') {
+            self.get_directory_stats(&rtxn, public_key, path)?
+                .map(|stats| stats.content_length)
+        } else {
+            self.get_entry(&rtxn, public_key, path)?
+                .map(|entry| entry.content_length() as u64)
+        };
+
+        rtxn.commit()?;
+
+        Ok(content_length.unwrap_or_default())
+    }
+
+    /// Update the stats of every ancestor directory of the entry at `key`
+    /// (`<public_key>/<path>`), after `removed` was replaced by `added`.
+    pub(crate) fn update_directories(
+        &self,
+        wtxn: &mut RwTxn,
+        key: &str,
+        removed: Option<&Entry>,
+        added: Option<&Entry>,
+        modified_at: &Timestamp,
+    ) -> anyhow::Result<()> {
+        for directory in ancestors(key) {
+            let mut stats = match self.tables.directories.get(wtxn, directory)? {
+                Some(bytes) => DirectoryStats::deserialize(bytes)?,
+                None => DirectoryStats::default(),
+            };
+
+            if let Some(removed) = removed {
+                stats.remove(removed, modified_at);
+            }
+            if let Some(added) = added {
+                stats.add(added, modified_at);
+            }
+
+            if stats.entries_count == 0 {
+                self.tables.directories.delete(wtxn, directory)?;
+            } else {
+                self.tables
+                    .directories
+                    .put(wtxn, directory, &stats.serialize())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the ancestor directories of an entry's `key`,
+/// from `<public_key>/` to its parent directory.
+pub(crate) fn ancestors(key: &str) -> impl Iterator<Item = &str> {
+    key.match_indices('/').map(|(index, _)| &key[..=index])
+}
+
+#[cfg(test)]
+mod tests {
+    use pkarr::{mainline::Testnet, Keypair};
+
+    use crate::config::Config;
+
+    use super::*;
+
+    #[test]
+    fn ancestors_of_key() {
+        assert_eq!(
+            ancestors("pk/pub/a/b.txt").collect::<Vec<_>>(),
+            vec!["pk/", "pk/pub/", "pk/pub/a/"]
+        );
+    }
+
+    #[tokio::test]
+    async fn directory_stats() -> anyhow::Result<()> {
+        let mut db = DB::open(Config::test(&Testnet::new(0))).unwrap();
+
+        let public_key = Keypair::random().public_key();
+
+        db.write_entry(&public_key, "pub/a/1.txt")?
+            .update(&[0; 10])?
+            .commit()?;
+        db.write_entry(&public_key, "pub/a/b/2.txt")?
+            .update(&[0; 5])?
+            .commit()?;
+        // Overwrite
+        db.write_entry(&public_key, "pub/a/1.txt")?
+            .update(&[0; 3])?
+            .commit()?;
+
+        {
+            let rtxn = db.env.read_txn()?;
+
+            let stats = db
+                .get_directory_stats(&rtxn, &public_key, "pub/a/")?
+                .unwrap();
+            assert_eq!(stats.content_length(), 8);
+            assert_eq!(stats.entries_count(), 2);
+
+            let stats = db
+                .get_directory_stats(&rtxn, &public_key, "pub/a/b/")?
+                .unwrap();
+            assert_eq!(stats.content_length(), 5);
+            assert_eq!(stats.entries_count(), 1);
+        }
+
+        db.delete_entry(&public_key, "pub/a/b/2.txt")?;
+
+        {
+            let rtxn = db.env.read_txn()?;
+
+            assert!(db
+                .get_directory_stats(&rtxn, &public_key, "pub/a/b/")?
+                .is_none());
+
+            let stats = db.get_directory_stats(&rtxn, &public_key, "")?.unwrap();
+            assert_eq!(stats.content_length(), 3);
+            assert_eq!(stats.entries_count(), 1);
+        }
+
+        Ok(())
+    }
+}
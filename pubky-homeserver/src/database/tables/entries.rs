@@ -4,21 +4,23 @@ use serde::{Deserialize, Serialize};
 use std::{
     fs::File,
     io::{Read, Write},
+    ops::Bound,
     path::PathBuf,
 };
 use tracing::instrument;
 
 use heed::{
     types::{Bytes, Str},
-    Database, RoTxn,
+    Database, RoTxn, RwTxn,
 };
 
 use pubky_common::{
     crypto::{Hash, Hasher},
+    list::{ListEntry, ListResponse},
     timestamp::Timestamp,
 };
 
-use crate::database::DB;
+use crate::database::{remove_buffer, DB};
 
 use super::events::Event;
 
@@ -27,12 +29,17 @@ pub type EntriesTable = Database<Str, Bytes>;
 
 pub const ENTRIES_TABLE: &str = "entries";
 
+/// Maximum number of entries deleted or copied in a single write transaction
+/// by [DB::delete_entries] and [DB::copy_entries], to avoid blocking other writers
+/// for too long, or exceeding the size limits of a transaction.
+const WRITE_BATCH_SIZE: usize = 100;
+
 impl DB {
     pub fn write_entry(
         &mut self,
         public_key: &PublicKey,
         path: &str,
-    ) -> anyhow::Result<EntryWriter> {
+    ) -> anyhow::Result<EntryWriter<'_>> {
         EntryWriter::new(self, public_key, path)
     }
 
@@ -41,49 +48,213 @@ impl DB {
 
         let key = format!("{public_key}/{path}");
 
-        let deleted = if let Some(bytes) = self.tables.entries.get(&wtxn, &key)? {
-            let entry = Entry::deserialize(bytes)?;
+        let deleted = self.delete_entry_key(&mut wtxn, &key)?;
 
-            let mut deleted_chunks = false;
+        wtxn.commit()?;
 
-            {
-                let mut iter = self
-                    .tables
-                    .blobs
-                    .prefix_iter_mut(&mut wtxn, &entry.timestamp.to_bytes())?;
+        Ok(deleted)
+    }
 
-                while iter.next().is_some() {
-                    unsafe {
-                        deleted_chunks = iter.del_current()?;
-                    }
+    /// Delete every entry under the directory `path` (ending with `/`),
+    /// in batches of [WRITE_BATCH_SIZE] entries per write transaction.
+    ///
+    /// Returns the number of deleted entries.
+    pub fn delete_entries(&mut self, public_key: &PublicKey, path: &str) -> anyhow::Result<usize> {
+        let prefix = format!("{public_key}/{path}");
+
+        let mut count = 0;
+
+        loop {
+            let mut wtxn = self.env.write_txn()?;
+
+            let keys = self
+                .tables
+                .entries
+                .prefix_iter(&wtxn, &prefix)?
+                .take(WRITE_BATCH_SIZE)
+                .map(|result| result.map(|(key, _)| key.to_string()))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            for key in &keys {
+                if self.delete_entry_key(&mut wtxn, key)? {
+                    count += 1;
                 }
             }
 
-            let deleted_entry = self.tables.entries.delete(&mut wtxn, &key)?;
+            wtxn.commit()?;
 
-            // create DELETE event
-            if path.starts_with("pub/") {
-                let url = format!("pubky://{key}");
+            if keys.len() < WRITE_BATCH_SIZE {
+                break;
+            }
+        }
 
-                let event = Event::delete(&url);
-                let value = event.serialize();
+        Ok(count)
+    }
 
-                let key = Timestamp::now().to_string();
+    /// Delete the entry at `key` (`<public_key>/<path>`), release its blob,
+    /// and write a DELETE [Event] if it is public.
+    fn delete_entry_key(&self, wtxn: &mut RwTxn, key: &str) -> anyhow::Result<bool> {
+        let Some(bytes) = self.tables.entries.get(wtxn, key)? else {
+            return Ok(false);
+        };
+
+        let entry = Entry::deserialize(bytes)?;
+
+        self.release_blob(wtxn, &entry.timestamp)?;
+
+        let deleted = self.tables.entries.delete(wtxn, key)?;
 
-                self.tables.events.put(&mut wtxn, &key, &value)?;
+        self.update_directories(wtxn, key, Some(&entry), None, &Timestamp::now())?;
+
+        // create DELETE event
+        if key
+            .split_once('/')
+            .is_some_and(|(_, path)| path.starts_with("pub/"))
+        {
+            self.write_event(wtxn, &Event::delete(&format!("pubky://{key}")))?;
+        }
 
-                // TODO: delete events older than a threshold.
-                // TODO: move to events.rs
+        Ok(deleted)
+    }
+
+    /// Copy the entry at `source` to `destination`, or every entry under
+    /// `source` if it is a directory (ends with `/`), without duplicating blobs.
+    ///
+    /// If `remove_source` is true, the source entries are deleted, effectively
+    /// moving them.
+    ///
+    /// Directories are copied in batches of [WRITE_BATCH_SIZE] entries per write transaction.
+    ///
+    /// Returns the number of copied (or moved) entries.
+    pub fn copy_entries(
+        &mut self,
+        public_key: &PublicKey,
+        source: &str,
+        destination: &str,
+        remove_source: bool,
+    ) -> anyhow::Result<usize> {
+        let source_key = format!("{public_key}/{source}");
+
+        if !source.ends_with('/') {
+            let mut wtxn = self.env.write_txn()?;
+
+            let Some(bytes) = self
+                .tables
+                .entries
+                .get(&wtxn, &source_key)?
+                .map(<[u8]>::to_vec)
+            else {
+                return Ok(0);
+            };
+
+            self.copy_entry(
+                &mut wtxn,
+                public_key,
+                source,
+                destination,
+                &bytes,
+                remove_source,
+            )?;
+
+            wtxn.commit()?;
+
+            return Ok(1);
+        }
+
+        let mut count = 0;
+        // Last copied key, to continue after it, as copies don't remove the sources.
+        let mut last_key: Option<String> = None;
+
+        loop {
+            let mut wtxn = self.env.write_txn()?;
+
+            let start = match &last_key {
+                Some(key) => Bound::Excluded(key.as_str()),
+                None => Bound::Included(source_key.as_str()),
+            };
+
+            let sources = self
+                .tables
+                .entries
+                .range(&wtxn, &(start, Bound::Unbounded))?
+                .take_while(|result| {
+                    result
+                        .as_ref()
+                        .map_or(true, |(key, _)| key.starts_with(&source_key))
+                })
+                .take(WRITE_BATCH_SIZE)
+                .map(|result| result.map(|(key, bytes)| (key.to_string(), bytes.to_vec())))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            for (key, bytes) in &sources {
+                let suffix = &key[source_key.len()..];
+
+                self.copy_entry(
+                    &mut wtxn,
+                    public_key,
+                    &format!("{source}{suffix}"),
+                    &format!("{destination}{suffix}"),
+                    bytes,
+                    remove_source,
+                )?;
             }
 
-            deleted_entry && deleted_chunks
-        } else {
-            false
+            wtxn.commit()?;
+
+            count += sources.len();
+
+            if sources.len() < WRITE_BATCH_SIZE {
+                break;
+            }
+            last_key = sources.last().map(|(key, _)| key.clone());
+        }
+
+        Ok(count)
+    }
+
+    /// Copy the serialized entry `bytes` at `source` to `destination`, see [DB::copy_entries].
+    fn copy_entry(
+        &self,
+        wtxn: &mut RwTxn,
+        public_key: &PublicKey,
+        source: &str,
+        destination: &str,
+        bytes: &[u8],
+        remove_source: bool,
+    ) -> anyhow::Result<()> {
+        let from = format!("{public_key}/{source}");
+        let to = format!("{public_key}/{destination}");
+
+        let entry = Entry::deserialize(bytes)?;
+
+        let existing = match self.tables.entries.get(wtxn, &to)? {
+            Some(existing) => Some(Entry::deserialize(existing)?),
+            None => None,
         };
+        if let Some(existing) = &existing {
+            self.release_blob(wtxn, &existing.timestamp)?;
+        }
 
-        wtxn.commit()?;
+        self.tables.entries.put(wtxn, &to, bytes)?;
 
-        Ok(deleted)
+        let now = Timestamp::now();
+        self.update_directories(wtxn, &to, existing.as_ref(), Some(&entry), &now)?;
+
+        if remove_source {
+            self.tables.entries.delete(wtxn, &from)?;
+            self.update_directories(wtxn, &from, Some(&entry), None, &now)?;
+        } else {
+            self.retain_blob(wtxn, &entry.timestamp)?;
+        }
+
+        if destination.starts_with("pub/") {
+            self.write_event(wtxn, &Event::put(&format!("pubky://{to}")))?;
+        }
+        if remove_source && source.starts_with("pub/") {
+            self.write_event(wtxn, &Event::delete(&format!("pubky://{from}")))?;
+        }
+
+        Ok(())
     }
 
     pub fn get_entry(
@@ -105,10 +276,11 @@ impl DB {
         Ok(self.tables.entries.get_greater_than(txn, path)?.is_some())
     }
 
-    /// Return a list of pubky urls.
+    /// Return a list of [ListEntry]s with the metadata of each file,
+    /// and the cursor of the next page.
     ///
     /// - limit defaults to [Config::default_list_limit] and capped by [Config::max_list_limit]
-    pub fn list(
+    pub fn list_entries(
         &self,
         txn: &RoTxn,
         path: &str,
@@ -116,7 +288,7 @@ impl DB {
         limit: Option<u16>,
         cursor: Option<String>,
         shallow: bool,
-    ) -> anyhow::Result<Vec<String>> {
+    ) -> anyhow::Result<ListResponse> {
         // Vector to store results
         let mut results = Vec::new();
 
@@ -146,7 +318,7 @@ impl DB {
             .unwrap_or(next_threshold(path, "", false, reverse, shallow));
 
         for _ in 0..limit {
-            if let Some((key, _)) = if reverse {
+            if let Some((key, bytes)) = if reverse {
                 self.tables.entries.get_lower_than(txn, &threshold)?
             } else {
                 self.tables.entries.get_greater_than(txn, &threshold)?
@@ -164,18 +336,35 @@ impl DB {
                     threshold =
                         next_threshold(path, file_or_directory, is_directory, reverse, shallow);
 
-                    results.push(format!(
-                        "pubky://{path}{file_or_directory}{}",
-                        if is_directory { "/" } else { "" }
-                    ));
+                    if is_directory {
+                        results.push(ListEntry {
+                            url: format!("pubky://{path}{file_or_directory}/"),
+                            is_directory,
+                            content_length: None,
+                            content_type: None,
+                            last_modified: None,
+                            etag: None,
+                        });
+                    } else {
+                        results.push(Entry::deserialize(bytes)?.to_list_entry(key));
+                    }
                 } else {
                     threshold = key.to_string();
-                    results.push(format!("pubky://{}", key))
+                    results.push(Entry::deserialize(bytes)?.to_list_entry(key))
                 }
             };
         }
 
-        Ok(results)
+        let next_cursor = if results.len() == limit as usize {
+            results.last().map(|entry| entry.url.clone())
+        } else {
+            None
+        };
+
+        Ok(ListResponse {
+            entries: results,
+            next_cursor,
+        })
     }
 }
 
@@ -275,6 +464,11 @@ impl Entry {
         self
     }
 
+    pub fn set_content_type(&mut self, content_type: &str) -> &mut Self {
+        content_type.clone_into(&mut self.content_type);
+        self
+    }
+
     // === Getters ===
 
     pub fn timestamp(&self) -> &Timestamp {
@@ -295,6 +489,22 @@ impl Entry {
 
     // === Public Method ===
 
+    /// Create a [ListEntry] for this entry stored at `key` (`<public_key>/<path>`).
+    pub fn to_list_entry(&self, key: &str) -> ListEntry {
+        ListEntry {
+            url: format!("pubky://{key}"),
+            is_directory: false,
+            content_length: Some(self.content_length as u64),
+            content_type: if self.content_type.is_empty() {
+                None
+            } else {
+                Some(self.content_type.clone())
+            },
+            last_modified: Some(self.timestamp.format_http_date()),
+            etag: Some(format!("\"{}\"", self.content_hash.0)),
+        }
+    }
+
     pub fn read_content<'txn>(
         &self,
         db: &'txn DB,
@@ -316,6 +526,10 @@ impl Entry {
     }
 }
 
+/// Writes an [Entry] to a filesystem buffer, until it is committed to LMDB.
+///
+/// The buffer is removed when the writer is dropped, even if it was never committed,
+/// for example if the request writing the entry is aborted.
 pub struct EntryWriter<'db> {
     db: &'db DB,
     buffer: File,
@@ -323,7 +537,9 @@ pub struct EntryWriter<'db> {
     buffer_path: PathBuf,
     entry_key: String,
     timestamp: Timestamp,
+    content_type: String,
     is_public: bool,
+    committed: bool,
 }
 
 impl<'db> EntryWriter<'db> {
@@ -345,10 +561,18 @@ impl<'db> EntryWriter<'db> {
             buffer_path,
             entry_key,
             timestamp,
+            content_type: String::new(),
             is_public: path.starts_with("pub/"),
+            committed: false,
         })
     }
 
+    /// Set the content type of the [Entry], returned as `Content-Type` when it is read.
+    pub fn set_content_type(&mut self, content_type: &str) -> &mut Self {
+        content_type.clone_into(&mut self.content_type);
+        self
+    }
+
     /// Same ase [EntryWriter::write_all] but returns a Result of a mutable reference of itself
     /// to enable chaining with [Self::commit].
     pub fn update(&mut self, chunk: &[u8]) -> Result<&mut Self, std::io::Error> {
@@ -357,9 +581,14 @@ impl<'db> EntryWriter<'db> {
         Ok(self)
     }
 
+    /// The hash of the content written so far.
+    pub fn hash(&self) -> Hash {
+        self.hasher.finalize()
+    }
+
     /// Commit blob from the filesystem buffer to LMDB,
     /// write the [Entry], and commit the write transaction.
-    pub fn commit(&self) -> anyhow::Result<Entry> {
+    pub fn commit(&mut self) -> anyhow::Result<Entry> {
         let hash = self.hasher.finalize();
 
         let mut buffer = File::open(&self.buffer_path)?;
@@ -394,15 +623,32 @@ impl<'db> EntryWriter<'db> {
         entry.set_timestamp(&self.timestamp);
 
         entry.set_content_hash(hash);
+        entry.set_content_type(&self.content_type);
 
         let length = buffer.metadata()?.len();
         entry.set_content_length(length as usize);
 
+        let existing = match self.db.tables.entries.get(&wtxn, &self.entry_key)? {
+            Some(existing) => Some(Entry::deserialize(existing)?),
+            None => None,
+        };
+        if let Some(existing) = &existing {
+            self.db.release_blob(&mut wtxn, &existing.timestamp)?;
+        }
+
         self.db
             .tables
             .entries
             .put(&mut wtxn, &self.entry_key, &entry.serialize())?;
 
+        self.db.update_directories(
+            &mut wtxn,
+            &self.entry_key,
+            existing.as_ref(),
+            Some(&entry),
+            &self.timestamp,
+        )?;
+
         // Write a public [Event].
         if self.is_public {
             let url = format!("pubky://{}", self.entry_key);
@@ -420,11 +666,23 @@ impl<'db> EntryWriter<'db> {
         wtxn.commit()?;
 
         std::fs::remove_file(&self.buffer_path)?;
+        self.committed = true;
 
         Ok(entry)
     }
 }
 
+impl<'db> Drop for EntryWriter<'db> {
+    fn drop(&mut self) {
+        // Already removed if committed.
+        if self.committed {
+            return;
+        }
+
+        remove_buffer(std::mem::take(&mut self.buffer_path));
+    }
+}
+
 impl<'db> std::io::Write for EntryWriter<'db> {
     /// Write a chunk to a Filesystem based buffer.
     #[inline]
@@ -444,10 +702,12 @@ impl<'db> std::io::Write for EntryWriter<'db> {
 
 #[cfg(test)]
 mod tests {
+    use std::io::Write;
+
     use bytes::Bytes;
     use pkarr::{mainline::Testnet, Keypair};
 
-    use crate::config::Config;
+    use crate::{config::Config, test_utils::wait_until};
 
     use super::DB;
 
@@ -482,7 +742,7 @@ mod tests {
             let mut iter = entry.read_content(&db, &rtxn).unwrap();
 
             while let Some(Ok(chunk)) = iter.next() {
-                blob.extend_from_slice(&chunk);
+                blob.extend_from_slice(chunk);
             }
         }
 
@@ -524,7 +784,7 @@ mod tests {
             let mut iter = entry.read_content(&db, &rtxn).unwrap();
 
             while let Some(Ok(chunk)) = iter.next() {
-                blob.extend_from_slice(&chunk);
+                blob.extend_from_slice(chunk);
             }
         }
 
@@ -537,4 +797,29 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn aborted_entry() -> anyhow::Result<()> {
+        let mut db = DB::open(Config::test(&Testnet::new(0))).unwrap();
+
+        let public_key = Keypair::random().public_key();
+        let path = "pub/foo.txt";
+
+        let buffer_path = {
+            let mut writer = db.write_entry(&public_key, path)?;
+            writer.write_all(&[1, 2, 3])?;
+
+            assert!(writer.buffer_path.exists());
+
+            writer.buffer_path.clone()
+        };
+
+        // Dropped without being committed, removed in a blocking task.
+        assert!(wait_until(|| !buffer_path.exists()).await);
+
+        let rtxn = db.env.read_txn()?;
+        assert!(db.get_entry(&rtxn, &public_key, path)?.is_none());
+
+        Ok(())
+    }
 }
@@ -5,11 +5,13 @@
 
 use heed::{
     types::{Bytes, Str},
-    Database,
+    Database, RwTxn,
 };
 use postcard::{from_bytes, to_allocvec};
 use serde::{Deserialize, Serialize};
 
+use pubky_common::timestamp::Timestamp;
+
 use crate::database::DB;
 
 /// Event [Timestamp] base32 => Encoded event.
@@ -60,6 +62,17 @@ impl Event {
 }
 
 impl DB {
+    /// Write an [Event] keyed by the current [Timestamp].
+    pub(crate) fn write_event(&self, wtxn: &mut RwTxn, event: &Event) -> anyhow::Result<()> {
+        let key = Timestamp::now().to_string();
+
+        self.tables.events.put(wtxn, &key, &event.serialize())?;
+
+        // TODO: delete events older than a threshold.
+
+        Ok(())
+    }
+
     /// Returns a list of events formatted as `<OP> <url>`.
     ///
     /// - limit defaults to [Config::default_list_limit] and capped by [Config::max_list_limit]
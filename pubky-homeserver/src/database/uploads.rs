@@ -0,0 +1,419 @@
+//! Resumable upload sessions, staged as numbered part files under
+//! `<buffers_dir>/uploads/<id>/` until they are finalized into an [Entry].
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use pkarr::PublicKey;
+use postcard::{from_bytes, to_allocvec};
+use serde::{Deserialize, Serialize};
+
+use pubky_common::{crypto::Hash, timestamp::Timestamp, upload::UploadStatus};
+
+use super::{remove_buffer, tables::entries::Entry, DB};
+
+const UPLOADS_DIR: &str = "uploads";
+const SESSION_FILE: &str = "session";
+/// Suffix of a part that is still being received.
+const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Metadata of an upload session, stored next to its parts.
+#[derive(Clone, Default, Serialize, Deserialize, Debug, Eq, PartialEq)]
+struct UploadSession {
+    /// Encoding version
+    version: usize,
+    /// Key of the entry this upload is finalized into (`<public_key>/<path>`).
+    entry_key: String,
+    content_type: String,
+}
+
+impl UploadSession {
+    fn serialize(&self) -> Vec<u8> {
+        to_allocvec(self).expect("UploadSession::serialize")
+    }
+
+    fn deserialize(bytes: &[u8]) -> core::result::Result<Self, postcard::Error> {
+        if bytes[0] > 0 {
+            panic!("Unknown UploadSession version");
+        }
+
+        from_bytes(bytes)
+    }
+}
+
+impl DB {
+    /// Create an upload session for the entry at `path`, and return its id.
+    pub fn create_upload(
+        &self,
+        public_key: &PublicKey,
+        path: &str,
+        content_type: &str,
+    ) -> anyhow::Result<Timestamp> {
+        let id = Timestamp::now();
+
+        let dir = self.upload_dir(&id);
+        fs::create_dir_all(&dir)?;
+
+        let session = UploadSession {
+            version: 0,
+            entry_key: format!("{public_key}/{path}"),
+            content_type: content_type.to_string(),
+        };
+
+        fs::write(dir.join(SESSION_FILE), session.serialize())?;
+
+        Ok(id)
+    }
+
+    /// Returns the [UploadStatus] of the upload session `id`,
+    /// or `None` if it doesn't exist or belongs to a different entry.
+    pub fn get_upload(
+        &self,
+        public_key: &PublicKey,
+        path: &str,
+        id: &Timestamp,
+    ) -> anyhow::Result<Option<UploadStatus>> {
+        let dir = self.upload_dir(id);
+
+        let session = match fs::read(dir.join(SESSION_FILE)) {
+            Ok(bytes) => UploadSession::deserialize(&bytes)?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+
+        if session.entry_key != format!("{public_key}/{path}") {
+            return Ok(None);
+        }
+
+        let parts = self.upload_parts(id)?;
+
+        let offset = parts
+            .iter()
+            .enumerate()
+            .take_while(|(index, (part, _))| *index as u32 == *part)
+            .map(|(_, (_, length))| length)
+            .sum();
+
+        Ok(Some(UploadStatus {
+            parts: parts.into_iter().map(|(part, _)| part).collect(),
+            offset,
+        }))
+    }
+
+    /// Returns the ids of the upload sessions of `public_key`.
+    pub fn list_uploads(&self, public_key: &PublicKey) -> anyhow::Result<Vec<Timestamp>> {
+        let uploads_dir = self.buffers_dir.join(UPLOADS_DIR);
+
+        let dir_entries = match fs::read_dir(&uploads_dir) {
+            Ok(dir_entries) => dir_entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error.into()),
+        };
+
+        let prefix = format!("{public_key}/");
+        let mut ids = Vec::new();
+
+        for dir_entry in dir_entries {
+            let dir = dir_entry?.path();
+
+            let Some(id) = dir
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| Timestamp::try_from(name.to_string()).ok())
+            else {
+                continue;
+            };
+
+            // Skip sessions removed concurrently.
+            let session = match fs::read(dir.join(SESSION_FILE)) {
+                Ok(bytes) => UploadSession::deserialize(&bytes)?,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                Err(error) => return Err(error.into()),
+            };
+
+            if session.entry_key.starts_with(&prefix) {
+                ids.push(id);
+            }
+        }
+
+        Ok(ids)
+    }
+
+    /// Returns the total size of the received parts of the upload session `id`,
+    /// except `excluded_part`, for example a part that is being replaced.
+    pub fn staged_upload_size(
+        &self,
+        id: &Timestamp,
+        excluded_part: Option<u32>,
+    ) -> anyhow::Result<u64> {
+        Ok(self
+            .upload_parts(id)?
+            .into_iter()
+            .filter(|(part, _)| Some(*part) != excluded_part)
+            .map(|(_, length)| length)
+            .sum())
+    }
+
+    /// Returns an [UploadPartWriter] for the `part` of the upload session `id`,
+    /// replacing any previously received content of that part once committed.
+    pub fn write_upload_part(&self, id: &Timestamp, part: u32) -> anyhow::Result<UploadPartWriter> {
+        let dir = self.upload_dir(id);
+
+        let path = dir.join(part.to_string());
+        // Unique per writer, so removing the file of a dropped writer in the background
+        // can't remove the file of a newer writer of the same part.
+        let partial_path = dir.join(format!("{part}.{}{PARTIAL_SUFFIX}", Timestamp::now()));
+
+        Ok(UploadPartWriter {
+            file: File::create(&partial_path)?,
+            partial_path,
+            path,
+            committed: false,
+        })
+    }
+
+    /// Write the first `parts_count` parts of the upload session `id` as an [Entry],
+    /// and remove the session.
+    ///
+    /// The content is hashed as it is copied, so parts replaced concurrently can't
+    /// be committed unverified. Returns `Err` with the actual hash, without writing
+    /// the entry nor removing the session, if it isn't `expected`.
+    pub fn finalize_upload(
+        &mut self,
+        id: &Timestamp,
+        parts_count: u32,
+        expected: &Hash,
+    ) -> anyhow::Result<Result<Entry, Hash>> {
+        let dir = self.upload_dir(id);
+
+        let session = UploadSession::deserialize(&fs::read(dir.join(SESSION_FILE))?)?;
+
+        let (public_key, path) = session
+            .entry_key
+            .split_once('/')
+            .ok_or(anyhow::anyhow!("Invalid upload session entry key"))?;
+        let public_key = PublicKey::try_from(public_key)?;
+
+        let mut chunk = vec![0_u8; self.max_chunk_size];
+
+        let mut writer = self.write_entry(&public_key, path)?;
+        writer.set_content_type(&session.content_type);
+
+        for part in 0..parts_count {
+            let mut file = File::open(dir.join(part.to_string()))?;
+
+            loop {
+                let bytes_read = file.read(&mut chunk)?;
+                if bytes_read == 0 {
+                    break;
+                }
+                writer.write_all(&chunk[..bytes_read])?;
+            }
+        }
+
+        let hash = writer.hash();
+        if hash != *expected {
+            return Ok(Err(hash));
+        }
+
+        let entry = writer.commit()?;
+
+        fs::remove_dir_all(&dir)?;
+
+        Ok(Ok(entry))
+    }
+
+    /// Remove the upload session `id` and all its parts.
+    pub fn delete_upload(&self, id: &Timestamp) -> anyhow::Result<()> {
+        fs::remove_dir_all(self.upload_dir(id))?;
+
+        Ok(())
+    }
+
+    /// Remove upload sessions that didn't receive any part for longer than `ttl`.
+    ///
+    /// Returns the number of removed sessions.
+    pub fn remove_abandoned_uploads(&self, ttl: Duration) -> anyhow::Result<usize> {
+        let uploads_dir = self.buffers_dir.join(UPLOADS_DIR);
+
+        let dir_entries = match fs::read_dir(&uploads_dir) {
+            Ok(dir_entries) => dir_entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(error) => return Err(error.into()),
+        };
+
+        let now = SystemTime::now();
+        let mut count = 0;
+
+        for dir_entry in dir_entries {
+            let dir = dir_entry?.path();
+
+            // Adding or replacing a part updates the modification time of the directory.
+            let last_activity = fs::metadata(&dir)?.modified()?;
+
+            if now.duration_since(last_activity).unwrap_or_default() >= ttl {
+                fs::remove_dir_all(&dir)?;
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Returns the numbers and lengths of the received parts of the upload session `id`,
+    /// sorted by number.
+    fn upload_parts(&self, id: &Timestamp) -> anyhow::Result<Vec<(u32, u64)>> {
+        let mut parts = Vec::new();
+
+        for dir_entry in fs::read_dir(self.upload_dir(id))? {
+            let dir_entry = dir_entry?;
+            if let Some(part) = dir_entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse::<u32>().ok())
+            {
+                parts.push((part, dir_entry.metadata()?.len()));
+            }
+        }
+        parts.sort();
+
+        Ok(parts)
+    }
+
+    fn upload_dir(&self, id: &Timestamp) -> PathBuf {
+        self.buffers_dir.join(UPLOADS_DIR).join(id.to_string())
+    }
+}
+
+/// Writes a part of an upload session to a temporary file,
+/// only replacing the part when [UploadPartWriter::commit] is called,
+/// so an interrupted request never leaves a truncated part behind.
+///
+/// The temporary file is removed when the writer is dropped without being committed.
+pub struct UploadPartWriter {
+    file: File,
+    partial_path: PathBuf,
+    path: PathBuf,
+    committed: bool,
+}
+
+impl UploadPartWriter {
+    /// Replace the part with the written content.
+    pub fn commit(mut self) -> anyhow::Result<()> {
+        self.file.sync_all()?;
+        fs::rename(&self.partial_path, &self.path)?;
+        self.committed = true;
+
+        Ok(())
+    }
+}
+
+impl Drop for UploadPartWriter {
+    fn drop(&mut self) {
+        // Already renamed if committed.
+        if !self.committed {
+            remove_buffer(std::mem::take(&mut self.partial_path));
+        }
+    }
+}
+
+impl Write for UploadPartWriter {
+    #[inline]
+    fn write(&mut self, chunk: &[u8]) -> io::Result<usize> {
+        self.file.write(chunk)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pkarr::{mainline::Testnet, Keypair};
+
+    use crate::{config::Config, test_utils::wait_until};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn upload_parts() -> anyhow::Result<()> {
+        let mut db = DB::open(Config::test(&Testnet::new(0))).unwrap();
+
+        let public_key = Keypair::random().public_key();
+        let path = "pub/foo.txt";
+
+        let id = db.create_upload(&public_key, path, "text/plain")?;
+
+        assert_eq!(
+            db.get_upload(&public_key, path, &id)?,
+            Some(UploadStatus::default())
+        );
+        assert_eq!(db.get_upload(&public_key, "pub/bar.txt", &id)?, None);
+
+        for (part, content) in [(1, b"world"), (0, b"hello")] {
+            let mut writer = db.write_upload_part(&id, part)?;
+            writer.write_all(content)?;
+            writer.commit()?;
+        }
+
+        // Interrupted part
+        let mut writer = db.write_upload_part(&id, 2)?;
+        writer.write_all(b"!")?;
+        let partial_path = writer.partial_path.clone();
+        drop(writer);
+        assert!(wait_until(|| !partial_path.exists()).await);
+
+        assert_eq!(
+            db.get_upload(&public_key, path, &id)?,
+            Some(UploadStatus {
+                parts: vec![0, 1],
+                offset: 10
+            })
+        );
+        assert_eq!(db.staged_upload_size(&id, None)?, 10);
+        assert_eq!(db.staged_upload_size(&id, Some(0))?, 5);
+
+        db.create_upload(&Keypair::random().public_key(), path, "")?;
+        assert_eq!(db.list_uploads(&public_key)?, vec![id]);
+
+        let hash = pubky_common::crypto::hash(b"helloworld");
+
+        // Mismatching hash
+        assert_eq!(
+            db.finalize_upload(&id, 2, &Hash::from_bytes([0; 32]))?
+                .unwrap_err(),
+            hash
+        );
+        assert!(db.get_upload(&public_key, path, &id)?.is_some());
+
+        let entry = db.finalize_upload(&id, 2, &hash)?.unwrap();
+        assert_eq!(entry.content_length(), 10);
+        assert_eq!(entry.content_type(), "text/plain");
+
+        assert_eq!(db.get_upload(&public_key, path, &id)?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn remove_abandoned_uploads() -> anyhow::Result<()> {
+        let db = DB::open(Config::test(&Testnet::new(0))).unwrap();
+
+        let public_key = Keypair::random().public_key();
+
+        let id = db.create_upload(&public_key, "pub/foo.txt", "")?;
+
+        assert_eq!(db.remove_abandoned_uploads(Duration::from_secs(60))?, 0);
+        assert_eq!(db.remove_abandoned_uploads(Duration::ZERO)?, 1);
+
+        assert_eq!(db.get_upload(&public_key, "pub/foo.txt", &id)?, None);
+
+        Ok(())
+    }
+}
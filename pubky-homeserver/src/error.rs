@@ -2,11 +2,12 @@
 
 use axum::{
     extract::rejection::{ExtensionRejection, PathRejection, QueryRejection},
-    http::StatusCode,
+    http::{header, StatusCode},
     response::IntoResponse,
 };
+use pubky_common::limits::{Limit, PayloadTooLarge};
 use tokio::task::JoinError;
-use tracing::debug;
+use tracing::{debug, error};
 
 pub type Result<T, E = Error> = core::result::Result<T, E>;
 
@@ -15,6 +16,7 @@ pub struct Error {
     // #[serde(with = "serde_status_code")]
     status: StatusCode,
     detail: Option<String>,
+    payload_too_large: Option<PayloadTooLarge>,
 }
 
 impl Default for Error {
@@ -22,6 +24,7 @@ impl Default for Error {
         Self {
             status: StatusCode::INTERNAL_SERVER_ERROR,
             detail: None,
+            payload_too_large: None,
         }
     }
 }
@@ -31,6 +34,7 @@ impl Error {
         Self {
             status,
             detail: None,
+            payload_too_large: None,
         }
     }
 
@@ -39,12 +43,31 @@ impl Error {
         Self {
             status: status_code,
             detail: message.map(|m| m.to_string()),
+            payload_too_large: None,
+        }
+    }
+
+    /// Create a `413 Payload Too Large` [`Error`], with a JSON [PayloadTooLarge] body.
+    pub fn payload_too_large(limit: Limit, max: u64) -> Error {
+        Self {
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+            detail: None,
+            payload_too_large: Some(PayloadTooLarge { limit, max }),
         }
     }
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
+        if let Some(body) = self.payload_too_large {
+            return (
+                self.status,
+                [(header::CONTENT_TYPE, "application/json")],
+                serde_json::to_vec(&body).expect("PayloadTooLarge::serialize"),
+            )
+                .into_response();
+        }
+
         match self.detail {
             Some(detail) => (self.status, detail).into_response(),
             _ => (self.status,).into_response(),
@@ -74,6 +97,25 @@ impl From<PathRejection> for Error {
 
 impl From<pubky_common::auth::Error> for Error {
     fn from(error: pubky_common::auth::Error) -> Self {
+        match error {
+            pubky_common::auth::Error::ReplayCacheFull => {
+                Self::new(StatusCode::SERVICE_UNAVAILABLE, Some(error))
+            }
+            pubky_common::auth::Error::ReplayCache(_) => {
+                // Storage errors are logged, but not exposed to the client.
+                error!(?error);
+                Self::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Some("Failed to verify the AuthToken"),
+                )
+            }
+            _ => Self::new(StatusCode::BAD_REQUEST, Some(error)),
+        }
+    }
+}
+
+impl From<pubky_common::path::Error> for Error {
+    fn from(error: pubky_common::path::Error) -> Self {
         Self::new(StatusCode::BAD_REQUEST, Some(error))
     }
 }
@@ -114,6 +156,13 @@ impl From<postcard::Error> for Error {
     }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        debug!(?error);
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, error.into())
+    }
+}
+
 impl From<axum::Error> for Error {
     fn from(error: axum::Error) -> Self {
         debug!(?error);
@@ -9,6 +9,7 @@ use axum::{
 };
 
 use pkarr::PublicKey;
+use pubky_common::path;
 
 use crate::error::{Error, Result};
 
@@ -46,12 +47,16 @@ where
     }
 }
 
-pub struct EntryPath(pub(crate) String);
+pub struct EntryPath(pub(crate) path::Path);
 
 impl EntryPath {
     pub fn as_str(&self) -> &str {
         self.0.as_str()
     }
+
+    pub fn path(&self) -> &path::Path {
+        &self.0
+    }
 }
 
 #[async_trait]
@@ -65,13 +70,15 @@ where
         let params: Path<HashMap<String, String>> =
             parts.extract().await.map_err(IntoResponse::into_response)?;
 
-        // TODO: enforce path limits like no trailing '/'
-
         let path = params
             .get("path")
             .ok_or_else(|| (StatusCode::NOT_FOUND, "entry path missing").into_response())?;
 
-        Ok(EntryPath(path.to_string()))
+        let path = path::Path::parse(path)
+            .map_err(Error::from)
+            .map_err(IntoResponse::into_response)?;
+
+        Ok(EntryPath(path))
     }
 }
 
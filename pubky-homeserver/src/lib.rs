@@ -1,9 +1,19 @@
+mod compression;
 pub mod config;
+mod content_type;
 mod database;
 mod error;
 mod extractors;
+mod listener;
+mod metrics;
 mod pkarr;
+mod rate_limiter;
 mod routes;
 mod server;
+mod subdomains;
+mod tls;
+
+#[cfg(test)]
+mod test_utils;
 
 pub use server::Homeserver;
@@ -0,0 +1,229 @@
+//! Accepting connections on [ListenAddress]es, see [Config::listen](crate::config::Config::listen).
+
+use std::{convert::Infallible, io, net::SocketAddr, time::Duration};
+
+use axum::{http::Request, response::Response, Router};
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+    sync::watch,
+    task::JoinSet,
+};
+use tokio_rustls::TlsAcceptor;
+use tower_service::Service;
+use tracing::debug;
+
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::config::ListenAddress;
+
+/// Maximum duration of a TLS handshake, so idle connections don't hold a task forever.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+enum Connection {
+    Tcp(TcpStream, SocketAddr),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Listener {
+    /// Bind `address`, replacing the socket file left by a previous run for Unix sockets.
+    pub(crate) async fn bind(address: &ListenAddress) -> io::Result<Self> {
+        match address {
+            ListenAddress::Tcp(address) => Ok(Listener::Tcp(TcpListener::bind(address).await?)),
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => {
+                use std::os::unix::fs::FileTypeExt;
+
+                if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                    std::fs::remove_file(path)?;
+                }
+
+                Ok(Listener::Unix(UnixListener::bind(path)?))
+            }
+            #[cfg(not(unix))]
+            ListenAddress::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Unix domain sockets are not supported on this platform",
+            )),
+        }
+    }
+
+    /// The bound address, with the actual port if bound to port `0`.
+    pub(crate) fn local_address(&self) -> io::Result<ListenAddress> {
+        match self {
+            Listener::Tcp(listener) => Ok(ListenAddress::Tcp(listener.local_addr()?)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener
+                .local_addr()?
+                .as_pathname()
+                .map(|path| ListenAddress::Unix(path.to_path_buf()))
+                .ok_or_else(|| io::Error::other("unnamed Unix socket")),
+        }
+    }
+
+    async fn accept(&self) -> io::Result<Connection> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, address) = listener.accept().await?;
+                Ok(Connection::Tcp(stream, address))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok(Connection::Unix(stream))
+            }
+        }
+    }
+}
+
+/// Serve `app` on connections accepted by `listener`, over TLS if `acceptor` is set,
+/// until `shutdown` is set.
+///
+/// On shutdown, stops accepting connections, lets in-flight requests complete,
+/// and returns once all connections are closed. Aborting the returned future
+/// aborts the remaining connections.
+///
+/// Requests over Unix sockets have no client IP address, so they are only
+/// rate limited per pubky.
+pub(crate) async fn serve(
+    listener: Listener,
+    acceptor: Option<TlsAcceptor>,
+    app: Router,
+    mut shutdown: watch::Receiver<bool>,
+) -> io::Result<()> {
+    let mut make_service = app
+        .clone()
+        .into_make_service_with_connect_info::<SocketAddr>();
+
+    let mut connections = JoinSet::new();
+
+    loop {
+        let connection = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(connection) => connection,
+                Err(error) => {
+                    // Like `axum::serve`, back off on errors like running out of file descriptors.
+                    debug!(?error, "Failed to accept connection");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            },
+            // Reap closed connections.
+            Some(_) = connections.join_next() => continue,
+            _ = shutdown_requested(&mut shutdown) => break,
+        };
+
+        match connection {
+            Connection::Tcp(stream, address) => {
+                let service = match make_service.call(address).await {
+                    Ok(service) => service,
+                    Err(infallible) => match infallible {},
+                };
+
+                connections.spawn(serve_connection(
+                    stream,
+                    acceptor.clone(),
+                    service,
+                    shutdown.clone(),
+                ));
+            }
+            #[cfg(unix)]
+            Connection::Unix(stream) => {
+                connections.spawn(serve_connection(
+                    stream,
+                    acceptor.clone(),
+                    app.clone(),
+                    shutdown.clone(),
+                ));
+            }
+        }
+    }
+
+    drop(listener);
+
+    while connections.join_next().await.is_some() {}
+
+    Ok(())
+}
+
+/// Resolves once a shutdown is requested, or the [Homeserver](crate::Homeserver) is dropped.
+pub(crate) async fn shutdown_requested(shutdown: &mut watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|shutdown| *shutdown).await;
+}
+
+/// Serve HTTP/1 or HTTP/2 requests on a single connection, until it is closed,
+/// or its in-flight requests complete after `shutdown` is set.
+///
+/// Connections that don't complete their TLS handshake within
+/// [TLS_HANDSHAKE_TIMEOUT] are dropped.
+async fn serve_connection<I, S>(
+    io: I,
+    acceptor: Option<TlsAcceptor>,
+    service: S,
+    shutdown: watch::Receiver<bool>,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Service<Request<Incoming>, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    let result = match acceptor {
+        Some(acceptor) => {
+            match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(io)).await {
+                Ok(Ok(stream)) => serve_http(stream, service, shutdown).await,
+                Ok(Err(error)) => {
+                    debug!(?error, "TLS handshake failed");
+                    return;
+                }
+                Err(_) => {
+                    debug!("TLS handshake timed out");
+                    return;
+                }
+            }
+        }
+        None => serve_http(io, service, shutdown).await,
+    };
+
+    if let Err(error) = result {
+        debug!(?error, "Failed to serve connection");
+    }
+}
+
+async fn serve_http<I, S>(
+    io: I,
+    service: S,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Service<Request<Incoming>, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    let builder = auto::Builder::new(TokioExecutor::new());
+
+    let connection =
+        builder.serve_connection_with_upgrades(TokioIo::new(io), TowerToHyperService::new(service));
+    tokio::pin!(connection);
+
+    tokio::select! {
+        result = connection.as_mut() => result,
+        _ = shutdown_requested(&mut shutdown) => {
+            // Finish in-flight requests, refusing new ones on this connection.
+            connection.as_mut().graceful_shutdown();
+            connection.await
+        }
+    }
+}
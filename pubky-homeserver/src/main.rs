@@ -1,29 +1,72 @@
 use std::path::PathBuf;
 
 use anyhow::Result;
-use pubky_homeserver::{config::Config, Homeserver};
+use pkarr::Keypair;
+use pubky_homeserver::{
+    config::{write_secret_key_file, Config},
+    Homeserver,
+};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
+/// Config keys are read from the `--config` file, overridden by `PUBKY_*` environment
+/// variables (like `PUBKY_PORT=6287` or `PUBKY_TLS__CERT=/etc/pubky/cert.pem`),
+/// overridden by `--set` flags.
 #[derive(Parser, Debug)]
 struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// [tracing_subscriber::EnvFilter]
     #[clap(short, long)]
     tracing_env_filter: Option<String>,
 
-    /// Run Homeserver in a local testnet
-    #[clap(long)]
+    /// Run Homeserver in a local testnet, ignoring any other configuration.
+    #[clap(long, conflicts_with_all = ["config", "set"])]
     testnet: bool,
 
     /// Optional Path to config file.
     #[clap(short, long)]
     config: Option<PathBuf>,
+
+    /// Override a config key, like `--set port=6287` or `--set tls.cert=/etc/pubky/cert.pem`.
+    #[clap(long, value_name = "KEY=VALUE")]
+    set: Vec<String>,
+
+    /// Print the effective config, with the secret key redacted, and exit.
+    #[clap(long)]
+    print_config: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Generate a secret key for `secret_key_file`, and print its public key.
+    Keygen {
+        /// File to create, only accessible by the current user.
+        ///
+        /// Prints the secret key in hex instead if not set.
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Cli::parse();
 
+    if let Some(Command::Keygen { output }) = args.command {
+        let keypair = Keypair::random();
+
+        match output {
+            Some(path) => write_secret_key_file(&path, &keypair)?,
+            None => println!("{}", hex::encode(keypair.secret_key())),
+        }
+
+        eprintln!("Public key: {}", keypair.public_key());
+
+        return Ok(());
+    }
+
     tracing_subscriber::fmt()
         .with_env_filter(
             args.tracing_env_filter
@@ -31,14 +74,32 @@ async fn main() -> Result<()> {
         )
         .init();
 
-    let server = Homeserver::start(if args.testnet {
+    let config = if args.testnet {
         Config::testnet()
-    } else if let Some(config_path) = args.config {
-        Config::load(config_path).await?
     } else {
-        Config::default()
-    })
-    .await?;
+        let mut builder = Config::builder();
+
+        if let Some(config_path) = args.config {
+            builder = builder.file(config_path).await?;
+        }
+
+        builder = builder.env(std::env::vars_os().filter_map(|(name, value)| {
+            Some((name.into_string().ok()?, value.into_string().ok()?))
+        }));
+
+        for assignment in &args.set {
+            builder = builder.set(assignment)?;
+        }
+
+        builder.build()?
+    };
+
+    if args.print_config {
+        print!("{}", config.to_toml_redacted()?);
+        return Ok(());
+    }
+
+    let server = Homeserver::start(config).await?;
 
     server.run_until_done().await?;
 
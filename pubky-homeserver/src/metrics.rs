@@ -0,0 +1,361 @@
+//! Prometheus metrics, served at `/metrics` on [Config::metrics_listen](crate::config::Config::metrics_listen).
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::{MatchedPath, Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use hyper::body::{Frame, SizeHint};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use pubky_common::auth;
+
+use crate::{database::DB, error::Result, rate_limiter::Budget, server::AppState};
+
+const NAMESPACE: &str = "pubky_homeserver";
+
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Registry,
+
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    request_bytes: IntCounter,
+    response_bytes: IntCounter,
+    rate_limited_requests: IntCounterVec,
+    auth_failures: IntCounterVec,
+    pkarr_publishes: IntCounterVec,
+
+    // === Sampled on every scrape ===
+    users: IntGauge,
+    stored_sessions: IntGauge,
+    events: IntGauge,
+    db_used_bytes: IntGauge,
+    db_map_size_bytes: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry =
+            Registry::new_custom(Some(NAMESPACE.into()), None).expect("valid metrics namespace");
+
+        let metrics = Self {
+            requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests by route and status"),
+                &["method", "route", "status"],
+            )
+            .expect("valid metric"),
+            request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_request_duration_seconds",
+                    "Time to respond to HTTP requests, by route",
+                ),
+                &["method", "route"],
+            )
+            .expect("valid metric"),
+            request_bytes: IntCounter::new(
+                "http_request_bytes_total",
+                "Bytes received in HTTP request bodies",
+            )
+            .expect("valid metric"),
+            response_bytes: IntCounter::new(
+                "http_response_bytes_total",
+                "Bytes sent in HTTP response bodies",
+            )
+            .expect("valid metric"),
+            rate_limited_requests: IntCounterVec::new(
+                Opts::new(
+                    "rate_limited_requests_total",
+                    "Requests rejected by rate limits, by budget",
+                ),
+                &["budget"],
+            )
+            .expect("valid metric"),
+            auth_failures: IntCounterVec::new(
+                Opts::new("auth_failures_total", "Rejected AuthTokens, by error"),
+                &["error"],
+            )
+            .expect("valid metric"),
+            pkarr_publishes: IntCounterVec::new(
+                Opts::new(
+                    "pkarr_publishes_total",
+                    "Publishing of the homeserver's pkarr packet, by result",
+                ),
+                &["result"],
+            )
+            .expect("valid metric"),
+            users: IntGauge::new("users", "Number of users").expect("valid metric"),
+            stored_sessions: IntGauge::new(
+                "stored_sessions",
+                "Number of stored sessions, which are kept until signing out",
+            )
+            .expect("valid metric"),
+            events: IntGauge::new("events", "Number of events in the events table")
+                .expect("valid metric"),
+            db_used_bytes: IntGauge::new("db_used_bytes", "Bytes used by the LMDB environment")
+                .expect("valid metric"),
+            db_map_size_bytes: IntGauge::new(
+                "db_map_size_bytes",
+                "Maximum size of the LMDB environment",
+            )
+            .expect("valid metric"),
+            registry,
+        };
+
+        for collector in [
+            Box::new(metrics.requests.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(metrics.request_duration.clone()),
+            Box::new(metrics.request_bytes.clone()),
+            Box::new(metrics.response_bytes.clone()),
+            Box::new(metrics.rate_limited_requests.clone()),
+            Box::new(metrics.auth_failures.clone()),
+            Box::new(metrics.pkarr_publishes.clone()),
+            Box::new(metrics.users.clone()),
+            Box::new(metrics.stored_sessions.clone()),
+            Box::new(metrics.events.clone()),
+            Box::new(metrics.db_used_bytes.clone()),
+            Box::new(metrics.db_map_size_bytes.clone()),
+        ] {
+            metrics
+                .registry
+                .register(collector)
+                .expect("unique metric names");
+        }
+
+        metrics
+    }
+
+    pub fn rate_limited(&self, budget: Budget) {
+        self.rate_limited_requests
+            .with_label_values(&[budget.as_str()])
+            .inc();
+    }
+
+    pub fn auth_failed(&self, error: &auth::Error) {
+        let label = match error {
+            auth::Error::UnknownVersion => "unknown_version",
+            auth::Error::TooFarInTheFuture => "too_far_in_the_future",
+            auth::Error::Expired => "expired",
+            auth::Error::InvalidSignature => "invalid_signature",
+            auth::Error::Postcard(_) => "invalid_encoding",
+            auth::Error::AlreadyUsed => "already_used",
+            auth::Error::ReplayCacheFull => "replay_cache_full",
+            auth::Error::ReplayCache(_) => "replay_cache",
+            auth::Error::Capability(_) => "invalid_capability",
+        };
+
+        self.auth_failures.with_label_values(&[label]).inc();
+    }
+
+    pub fn pkarr_published(&self, success: bool) {
+        let label = if success { "success" } else { "failure" };
+
+        self.pkarr_publishes.with_label_values(&[label]).inc();
+    }
+
+    /// A more recent packet published elsewhere was republished instead of the server's own.
+    pub fn pkarr_adopted(&self) {
+        self.pkarr_publishes.with_label_values(&["adopted"]).inc();
+    }
+
+    /// Sample the database gauges and encode all metrics in the Prometheus text format.
+    fn encode(&self, db: &DB) -> anyhow::Result<String> {
+        let rtxn = db.env.read_txn()?;
+
+        self.users.set(db.tables.users.len(&rtxn)? as i64);
+        self.stored_sessions
+            .set(db.tables.sessions.len(&rtxn)? as i64);
+        self.events.set(db.tables.events.len(&rtxn)? as i64);
+
+        rtxn.commit()?;
+
+        self.db_used_bytes.set(db.env.non_free_pages_size()? as i64);
+        self.db_map_size_bytes.set(db.env.info().map_size as i64);
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Router serving the metrics at `/metrics`.
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/metrics", get(handler))
+        .with_state(state)
+}
+
+async fn handler(State(state): State<AppState>) -> Result<impl IntoResponse> {
+    let text = tokio::task::spawn_blocking(move || state.metrics.encode(&state.db)).await??;
+
+    Ok(([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], text))
+}
+
+/// Count requests, their latency and the bytes received and sent, by matched route.
+///
+/// Bytes are counted as the bodies are streamed, so aborted transfers only count
+/// the bytes that were actually received or sent.
+pub async fn middleware(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let start = Instant::now();
+
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let metrics = &state.metrics;
+
+    let request = request.map(|body| CountedBody::wrap(body, metrics.request_bytes.clone()));
+
+    let response = next.run(request).await;
+
+    metrics
+        .requests
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .inc();
+    metrics
+        .request_duration
+        .with_label_values(&[&method, &route])
+        .observe(start.elapsed().as_secs_f64());
+    response.map(|body| CountedBody::wrap(body, metrics.response_bytes.clone()))
+}
+
+/// A [Body] adding the length of its data frames to a counter, as they are polled.
+struct CountedBody {
+    inner: Body,
+    counter: IntCounter,
+}
+
+impl CountedBody {
+    fn wrap(inner: Body, counter: IntCounter) -> Body {
+        Body::new(Self { inner, counter })
+    }
+}
+
+impl HttpBody for CountedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<std::result::Result<Frame<Bytes>, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+
+        if let Poll::Ready(Some(Ok(frame))) = &poll {
+            if let Some(data) = frame.data_ref() {
+                self.counter.inc_by(data.len() as u64);
+            }
+        }
+
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pkarr::mainline::Testnet;
+
+    use crate::{
+        config::{Config, DEFAULT_MAP_SIZE},
+        Homeserver,
+    };
+
+    #[tokio::test]
+    async fn metrics() -> anyhow::Result<()> {
+        let testnet = Testnet::new(3);
+
+        let config = Config::try_from_str(&format!(
+            r#"
+            testnet = true
+            bootstrap = {:?}
+            storage = {:?}
+            metrics_listen = "127.0.0.1:0"
+            "#,
+            testnet.bootstrap,
+            Config::test(&testnet).storage(),
+        ))?;
+
+        let server = Homeserver::start(config).await?;
+
+        let client = reqwest::Client::new();
+
+        client
+            .get(format!("http://localhost:{}/", server.port()))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let response = client
+            .post(format!("http://localhost:{}/session", server.port()))
+            .body(vec![0; 200])
+            .send()
+            .await?;
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        let text = client
+            .get(format!(
+                "http://{}/metrics",
+                server.metrics_address().unwrap()
+            ))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        for line in [
+            r#"pubky_homeserver_http_requests_total{method="GET",route="/",status="200"} 1"#,
+            r#"pubky_homeserver_pkarr_publishes_total{result="success"} 1"#,
+            "pubky_homeserver_users 0",
+            "pubky_homeserver_stored_sessions 0",
+            "pubky_homeserver_http_request_bytes_total 200",
+            &format!("pubky_homeserver_db_map_size_bytes {DEFAULT_MAP_SIZE}"),
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "missing {line} in:\n{text}"
+            );
+        }
+
+        let response_bytes = text
+            .lines()
+            .find_map(|l| l.strip_prefix("pubky_homeserver_http_response_bytes_total "))
+            .and_then(|bytes| bytes.parse::<u64>().ok());
+        assert!(response_bytes > Some(0), "{text}");
+
+        assert!(text.contains("pubky_homeserver_auth_failures_total{error="));
+        assert!(text.contains("pubky_homeserver_http_request_duration_seconds_bucket"));
+
+        Ok(())
+    }
+}
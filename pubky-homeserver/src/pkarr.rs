@@ -1,46 +1,340 @@
 //! Pkarr related task
 
+use std::{net::IpAddr, time::Duration};
+
 use pkarr::{
-    dns::{rdata::SVCB, Packet},
-    Keypair, PkarrClientAsync, SignedPacket,
+    dns::{
+        rdata::{RData, A, AAAA, SVCB},
+        Packet,
+    },
+    mainline::dht::DhtSettings,
+    Keypair, PkarrClient, PkarrClientAsync, PublicKey, Settings, SignedPacket,
 };
+use pubky_common::endpoint::{EndpointParams, PROTOCOL_VERSION, SUBDOMAINS_FEATURE};
+use tracing::{debug, warn};
+
+/// Optional features announced in the server's SVCB record.
+pub(crate) const FEATURES: [&str; 4] = ["uploads", "copy", "events", "pkarr"];
+
+/// Parameters announced in the server's SVCB record, see [EndpointParams].
+pub(crate) fn endpoint_params(config: &crate::config::Config) -> EndpointParams {
+    let (ipv4_hints, ipv6_hints) =
+        config
+            .public_ips()
+            .iter()
+            .fold((vec![], vec![]), |(mut ipv4, mut ipv6), ip| {
+                match ip {
+                    IpAddr::V4(ip) => ipv4.push(*ip),
+                    IpAddr::V6(ip) => ipv6.push(*ip),
+                };
+                (ipv4, ipv6)
+            });
 
+    let mut features: Vec<String> = FEATURES.iter().map(|f| f.to_string()).collect();
+    if config.subdomains() {
+        features.push(SUBDOMAINS_FEATURE.to_string());
+    }
+
+    EndpointParams {
+        alpn: config.alpn().to_vec(),
+        ipv4_hints,
+        ipv6_hints,
+        versions: vec![PROTOCOL_VERSION.to_string()],
+        max_upload_size: Some(config.default_max_entry_size()),
+        features,
+    }
+}
+
+/// The packet republished by [publish_server_packet].
+#[derive(Debug)]
+pub(crate) enum Published {
+    /// The server's own packet.
+    Own(SignedPacket),
+    /// A more recent packet with different records, published elsewhere (for example
+    /// by another instance sharing the same key), republished as is instead of being clobbered.
+    Adopted(SignedPacket),
+}
+
+/// Resolve the most recent packet of the server's key, then publish the server's
+/// `signed_packet`, see [server_packet].
+///
+/// `own` is the last packet this server published itself. If the most recent packet
+/// has different records and is more recent than `own`, it was published elsewhere,
+/// and it is republished instead. Without `own`, on startup, the server's packet replaces
+/// any other packet, so configuration changes take effect.
 pub(crate) async fn publish_server_packet(
     pkarr_client: &PkarrClientAsync,
-    keypair: &Keypair,
-    domain: &str,
-    port: u16,
+    resolver: &Resolver,
+    signed_packet: SignedPacket,
+    own: Option<&SignedPacket>,
+) -> anyhow::Result<Published> {
+    let most_recent = resolver
+        .resolve(&signed_packet.public_key())
+        .await?
+        .filter(|most_recent| most_recent.encoded_packet() != signed_packet.encoded_packet());
+
+    if let Some(most_recent) = most_recent {
+        match own {
+            Some(own) if most_recent.more_recent_than(own) => {
+                warn!("A more recent packet was published for this homeserver's key, republishing it instead.");
+
+                // Best effort, nodes that already store it reject it as not more recent.
+                if let Err(error) = pkarr_client.publish(&most_recent).await {
+                    debug!(?error, "Failed to republish the more recent packet");
+                }
+
+                return Ok(Published::Adopted(most_recent));
+            }
+            Some(_) => {}
+            None => {
+                warn!("Replacing a different packet published for this homeserver's key, by a previous configuration or another instance.");
+            }
+        }
+    }
+
+    pkarr_client.publish(&signed_packet).await?;
+
+    Ok(Published::Own(signed_packet))
+}
+
+/// Resolves the server's packet straight from the DHT, with a short lived client
+/// for every resolution, as the publishing client caches its own packet, and a DHT node
+/// can't publish a key, or resolve it again, while a resolution is still in flight.
+#[derive(Debug, Clone)]
+pub(crate) struct Resolver {
+    pub(crate) bootstrap: Option<Vec<String>>,
+    pub(crate) request_timeout: Option<Duration>,
+}
+
+impl Resolver {
+    pub(crate) async fn resolve(
+        &self,
+        public_key: &PublicKey,
+    ) -> anyhow::Result<Option<SignedPacket>> {
+        let mut client = PkarrClient::new(Settings {
+            dht: DhtSettings {
+                bootstrap: self.bootstrap.clone(),
+                request_timeout: self.request_timeout,
+                ..Default::default()
+            },
+            ..Default::default()
+        })?
+        .as_async();
+
+        let result = client.resolve(public_key).await;
+
+        client.shutdown().await?;
+
+        Ok(result?)
+    }
+}
+
+/// Publish a packet of a `previous` key of the server, redirecting to its `current` key.
+pub(crate) async fn publish_redirect_packet(
+    pkarr_client: &PkarrClientAsync,
+    previous: &Keypair,
+    current: &PublicKey,
+    ttl: u32,
 ) -> anyhow::Result<()> {
-    // TODO: Try to resolve first before publishing.
+    pkarr_client
+        .publish(&redirect_packet(previous, current, ttl)?)
+        .await?;
+
+    Ok(())
+}
 
+/// A packet with an AliasMode SVCB record to `current`, followed by clients
+/// resolving homeservers like any other pkarr target.
+fn redirect_packet(
+    previous: &Keypair,
+    current: &PublicKey,
+    ttl: u32,
+) -> anyhow::Result<SignedPacket> {
     let mut packet = Packet::new_reply(0);
 
-    let mut svcb = SVCB::new(0, domain.try_into()?);
+    let target = current.to_string();
 
-    // Publishing port only for localhost domain,
-    // assuming any other domain will point to a reverse proxy
-    // at the conventional ports.
-    if domain == "localhost" {
-        svcb.priority = 1;
-        svcb.set_port(port);
+    packet.answers.push(pkarr::dns::ResourceRecord::new(
+        "@".try_into().unwrap(),
+        pkarr::dns::CLASS::IN,
+        ttl,
+        RData::SVCB(SVCB::new(0, target.as_str().try_into()?)),
+    ));
+
+    Ok(SignedPacket::from_packet(previous, &packet)?)
+}
 
-        // TODO: Add more parameteres like the signer key!
-        // svcb.set_param(key, value)
+/// The server's packet, announcing `port` if set, or the conventional ports otherwise.
+pub(crate) fn server_packet(
+    keypair: &Keypair,
+    domain: &str,
+    port: Option<u16>,
+    ttl: u32,
+    params: &EndpointParams,
+) -> anyhow::Result<SignedPacket> {
+    let mut packet = Packet::new_reply(0);
+
+    // ServiceMode (priority > 0), as AliasMode records can't have any params.
+    let mut svcb = SVCB::new(1, domain.try_into()?);
+
+    if let Some(port) = port {
+        svcb.set_port(port);
     };
 
-    // TODO: announce A/AAAA records as well for Noise connections?
-    // Or maybe Iroh's magic socket
+    params.write_to(&mut svcb)?;
 
     packet.answers.push(pkarr::dns::ResourceRecord::new(
         "@".try_into().unwrap(),
         pkarr::dns::CLASS::IN,
-        60 * 60,
-        pkarr::dns::rdata::RData::SVCB(svcb),
+        ttl,
+        RData::SVCB(svcb),
     ));
 
-    let signed_packet = SignedPacket::from_packet(keypair, &packet)?;
+    for ip in &params.ipv4_hints {
+        packet.answers.push(pkarr::dns::ResourceRecord::new(
+            "@".try_into().unwrap(),
+            pkarr::dns::CLASS::IN,
+            ttl,
+            RData::A(A::from(*ip)),
+        ));
+    }
+    for ip in &params.ipv6_hints {
+        packet.answers.push(pkarr::dns::ResourceRecord::new(
+            "@".try_into().unwrap(),
+            pkarr::dns::CLASS::IN,
+            ttl,
+            RData::AAAA(AAAA::from(*ip)),
+        ));
+    }
 
-    pkarr_client.publish(&signed_packet).await?;
+    Ok(SignedPacket::from_packet(keypair, &packet)?)
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use pkarr::{mainline::Testnet, PkarrClient};
+
+    use crate::config::Config;
+
+    use super::*;
+
+    #[test]
+    fn server_packet_params() -> anyhow::Result<()> {
+        let config = Config::try_from_str(
+            r#"
+            public_ips = ["192.0.2.1", "2001:db8::1"]
+            alpn = ["h2"]
+            max_entry_size = 1000
+            "#,
+        )?;
+
+        let params = endpoint_params(&config);
+        let keypair = Keypair::random();
+
+        let signed_packet = server_packet(&keypair, "example.com", None, 60, &params)?;
+        let origin = keypair.public_key().to_string();
+
+        let svcb = signed_packet
+            .resource_records(&origin)
+            .find_map(|record| match &record.rdata {
+                RData::SVCB(svcb) => Some(svcb.clone()),
+                _ => None,
+            })
+            .unwrap();
+
+        assert_eq!(svcb.priority, 1);
+        assert_eq!(svcb.get_param(SVCB::PORT), None);
+
+        let parsed = EndpointParams::from_svcb(&svcb);
+        assert_eq!(parsed, params);
+        assert_eq!(parsed.max_upload_size, Some(1000));
+        assert!(parsed.supports_version(PROTOCOL_VERSION));
+        assert!(parsed.supports_feature("uploads"));
+        assert!(!parsed.supports_feature(SUBDOMAINS_FEATURE));
+
+        let addresses = signed_packet
+            .resource_records(&origin)
+            .filter_map(|record| match &record.rdata {
+                RData::A(a) => Some(IpAddr::from(std::net::Ipv4Addr::from(a.address))),
+                RData::AAAA(aaaa) => Some(IpAddr::from(std::net::Ipv6Addr::from(aaaa.address))),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(addresses, config.public_ips());
+
+        Ok(())
+    }
+
+    #[test]
+    fn redirect_to_current_key() -> anyhow::Result<()> {
+        let previous = Keypair::random();
+        let current = Keypair::random().public_key();
+
+        let signed_packet = redirect_packet(&previous, &current, 60)?;
+
+        assert_eq!(signed_packet.public_key(), previous.public_key());
+
+        let records = signed_packet
+            .resource_records(&previous.public_key().to_string())
+            .filter_map(|record| match &record.rdata {
+                RData::SVCB(svcb) => Some((svcb.priority, svcb.target.to_string())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(records, vec![(0, current.to_string())]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn do_not_clobber_more_recent_packet() -> anyhow::Result<()> {
+        let testnet = Testnet::new(10);
+
+        let keypair = Keypair::random();
+        let params = EndpointParams::default();
+
+        let client = PkarrClient::builder().testnet(&testnet).build()?.as_async();
+        let resolver = Resolver {
+            bootstrap: Some(testnet.bootstrap.clone()),
+            request_timeout: None,
+        };
+        let other_client = PkarrClient::builder().testnet(&testnet).build()?.as_async();
+
+        let publish = |domain, own| {
+            let signed_packet = server_packet(&keypair, domain, None, 60, &params).unwrap();
+
+            publish_server_packet(&client, &resolver, signed_packet, own)
+        };
+
+        let Published::Own(first) = publish("localhost", None).await? else {
+            panic!("expected own packet");
+        };
+
+        // Another instance publishes a different packet for the same key.
+        let other = server_packet(&keypair, "example.com", None, 60, &params)?;
+        other_client.publish(&other).await?;
+
+        // It is adopted for as long as it is more recent than our own.
+        for _ in 0..2 {
+            let Published::Adopted(adopted) = publish("localhost", Some(&first)).await? else {
+                panic!("expected adopted packet");
+            };
+            assert!(adopted.is_same_as(&other));
+        }
+
+        // On startup, our own packet replaces it.
+        let Published::Own(restarted) = publish("localhost", None).await? else {
+            panic!("expected own packet");
+        };
+        assert!(restarted.more_recent_than(&other));
+
+        // A packet with the same records is replaced by a fresh one.
+        let Published::Own(fresh) = publish("localhost", Some(&first)).await? else {
+            panic!("expected own packet");
+        };
+        assert!(fresh.more_recent_than(&restarted));
+
+        Ok(())
+    }
 }
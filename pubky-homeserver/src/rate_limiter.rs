@@ -0,0 +1,538 @@
+//! Token bucket rate limiting of requests, per client IP address and per pubky.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use pkarr::PublicKey;
+use tracing::debug;
+
+use crate::{
+    config::{BudgetLimits, Quota, RateLimits},
+    server::AppState,
+};
+
+/// How often to forget clients that were idle long enough to refill their buckets.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The kind of request, each with its own [BudgetLimits].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Budget {
+    Auth,
+    Write,
+    Read,
+    Events,
+}
+
+impl Budget {
+    pub const ALL: [Budget; 4] = [Budget::Auth, Budget::Write, Budget::Read, Budget::Events];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Budget::Auth => "auth",
+            Budget::Write => "write",
+            Budget::Read => "read",
+            Budget::Events => "events",
+        }
+    }
+
+    fn of(method: &Method, path: &str) -> Self {
+        if path == "/signup" || path == "/session" {
+            return Budget::Auth;
+        }
+        if path.starts_with("/events") {
+            return Budget::Events;
+        }
+        if path
+            .strip_prefix('/')
+            .and_then(|path| path.split_once('/'))
+            .is_some_and(|(_, rest)| rest == "session")
+        {
+            return Budget::Auth;
+        }
+
+        match *method {
+            Method::GET | Method::HEAD => Budget::Read,
+            _ => Budget::Write,
+        }
+    }
+
+    fn limits(self, limits: &RateLimits) -> &BudgetLimits {
+        match self {
+            Budget::Auth => &limits.auth,
+            Budget::Write => &limits.write,
+            Budget::Read => &limits.read,
+            Budget::Events => &limits.events,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Ip(IpAddr),
+    Pubky(PublicKey),
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn new(quota: &Quota, now: Instant) -> Self {
+        Self {
+            tokens: quota.burst as f64,
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, quota: &Quota, now: Instant) {
+        let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * refill_rate(quota)).min(quota.burst as f64);
+        self.updated_at = now;
+    }
+
+    /// Returns how long until a token is available, or `None` if one is available now.
+    fn wait_time(&self, quota: &Quota) -> Option<Duration> {
+        if self.tokens >= 1.0 {
+            return None;
+        }
+
+        let rate = refill_rate(quota);
+        if rate == 0.0 {
+            return Some(PRUNE_INTERVAL);
+        }
+
+        Some(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+    }
+
+    fn is_full(&self, quota: &Quota) -> bool {
+        self.tokens >= quota.burst as f64
+    }
+}
+
+/// Tokens added per second.
+fn refill_rate(quota: &Quota) -> f64 {
+    quota.per_minute as f64 / 60.0
+}
+
+#[derive(Debug)]
+struct Buckets {
+    buckets: HashMap<(Budget, Key), Bucket>,
+    pruned_at: Instant,
+}
+
+#[derive(Debug)]
+struct Inner {
+    limits: RateLimits,
+    buckets: Mutex<Buckets>,
+    rejections: [AtomicU64; Budget::ALL.len()],
+}
+
+/// Shared rate limiter, checking every request against its [Budget]
+/// for the client's IP address and the pubky it targets.
+#[derive(Debug, Clone)]
+pub struct RateLimiter(Arc<Inner>);
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self(Arc::new(Inner {
+            limits,
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                pruned_at: Instant::now(),
+            }),
+            rejections: Default::default(),
+        }))
+    }
+
+    /// Take a token from every bucket of `budget` that applies to this request,
+    /// or return how long the client should wait before retrying.
+    ///
+    /// No token is taken unless all buckets have one available.
+    pub fn check(
+        &self,
+        budget: Budget,
+        ip: Option<IpAddr>,
+        pubky: Option<&PublicKey>,
+    ) -> Result<(), Duration> {
+        let limits = budget.limits(&self.0.limits);
+
+        let keys = [
+            limits.per_ip.zip(ip.map(Key::Ip)),
+            limits.per_pubky.zip(pubky.cloned().map(Key::Pubky)),
+        ];
+
+        if keys.iter().all(Option::is_none) {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let mut buckets = self.0.buckets.lock().expect("RateLimiter lock poisoned");
+
+        if now.duration_since(buckets.pruned_at) >= PRUNE_INTERVAL {
+            buckets.prune(&self.0.limits, now);
+        }
+
+        let mut wait_time = None;
+
+        for (quota, key) in keys.iter().flatten() {
+            let bucket = buckets
+                .buckets
+                .entry((budget, key.clone()))
+                .or_insert_with(|| Bucket::new(quota, now));
+
+            bucket.refill(quota, now);
+
+            wait_time = wait_time.max(bucket.wait_time(quota));
+        }
+
+        if let Some(wait_time) = wait_time {
+            self.0.rejections[budget as usize].fetch_add(1, Ordering::Relaxed);
+
+            return Err(wait_time);
+        }
+
+        for (_, key) in keys.into_iter().flatten() {
+            if let Some(bucket) = buckets.buckets.get_mut(&(budget, key)) {
+                bucket.tokens -= 1.0;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Number of requests rejected for exceeding `budget` since the server started.
+    pub fn rejections(&self, budget: Budget) -> u64 {
+        self.0.rejections[budget as usize].load(Ordering::Relaxed)
+    }
+}
+
+impl Buckets {
+    /// Remove buckets that refilled completely, they are equivalent to new ones.
+    fn prune(&mut self, limits: &RateLimits, now: Instant) {
+        self.buckets.retain(|(budget, key), bucket| {
+            let limits = budget.limits(limits);
+            let quota = match key {
+                Key::Ip(_) => limits.per_ip,
+                Key::Pubky(_) => limits.per_pubky,
+            };
+
+            match quota {
+                Some(quota) => {
+                    bucket.refill(&quota, now);
+                    !bucket.is_full(&quota)
+                }
+                None => false,
+            }
+        });
+
+        self.pruned_at = now;
+    }
+}
+
+/// Reject requests exceeding their [Budget] with `429 Too Many Requests`
+/// and a `Retry-After` header.
+pub async fn middleware(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let path = request.uri().path();
+
+    // Health probes come from orchestrators and load balancers, often sharing one IP.
+    if request.method() == Method::OPTIONS || path == "/health" || path == "/ready" {
+        return next.run(request).await;
+    }
+
+    let budget = Budget::of(request.method(), path);
+    let pubky = pubky_of(path);
+    let ip = client_ip(&request, state.config.trusted_proxies());
+
+    if let Err(wait_time) = state.rate_limiter.check(budget, ip, pubky.as_ref()) {
+        state.metrics.rate_limited(budget);
+
+        debug!(
+            ?ip,
+            ?pubky,
+            budget = budget.as_str(),
+            "Rate limited request"
+        );
+
+        // Round up, to avoid retrying a bit too early.
+        let retry_after = wait_time.as_secs() + u64::from(wait_time.subsec_nanos() > 0);
+
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after.to_string())],
+            "Too many requests",
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+/// The IP address of the client, as reported by the reverse proxies in `trusted_proxies`
+/// with the `X-Forwarded-For` header, or the `Forwarded` header, if the connection comes from one.
+///
+/// Proxies append the address they received the request from, so the client is the
+/// rightmost address that isn't a trusted proxy itself.
+fn client_ip(request: &Request, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+    let mut ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(address)| address.ip())?;
+
+    if !trusted_proxies.contains(&ip) {
+        return Some(ip);
+    }
+
+    let headers = request.headers();
+
+    let forwarded: Vec<&str> = if headers.contains_key(X_FORWARDED_FOR) {
+        headers
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect()
+    } else {
+        headers
+            .get_all(header::FORWARDED)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|element| {
+                element.split(';').find_map(|pair| {
+                    let (name, value) = pair.trim().split_once('=')?;
+                    name.eq_ignore_ascii_case("for").then_some(value)
+                })
+            })
+            .collect()
+    };
+
+    for hop in forwarded.into_iter().rev() {
+        match parse_forwarded_ip(hop) {
+            Some(hop) => ip = hop,
+            // Obfuscated or invalid, the hops before it can't be trusted.
+            None => break,
+        }
+
+        if !trusted_proxies.contains(&ip) {
+            break;
+        }
+    }
+
+    Some(ip)
+}
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Parse an address like `192.0.2.1`, `192.0.2.1:4711` or `"[2001:db8::1]:4711"`.
+fn parse_forwarded_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+
+    value
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|address| address.ip()))
+        .or_else(|| {
+            value
+                .strip_prefix('[')
+                .and_then(|value| value.strip_suffix(']'))?
+                .parse()
+                .ok()
+        })
+}
+
+/// The pubky targeted by requests to `/:pubky/...` or `/pkarr/:pubky`.
+fn pubky_of(path: &str) -> Option<PublicKey> {
+    let path = path.strip_prefix('/')?;
+    let path = path.strip_prefix("pkarr/").unwrap_or(path);
+
+    let segment = path.split('/').next()?;
+
+    PublicKey::try_from(segment).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use pkarr::Keypair;
+
+    use crate::{config::Config, Homeserver};
+
+    use super::*;
+
+    #[test]
+    fn budget_of_request() {
+        let pubky = Keypair::random().public_key();
+
+        for (method, path, budget) in [
+            (Method::POST, "/signup".to_string(), Budget::Auth),
+            (Method::POST, "/session".to_string(), Budget::Auth),
+            (Method::GET, format!("/{pubky}/session"), Budget::Auth),
+            (Method::DELETE, format!("/{pubky}/session"), Budget::Auth),
+            (Method::GET, "/events/".to_string(), Budget::Events),
+            (Method::GET, format!("/{pubky}/pub/session"), Budget::Read),
+            (Method::HEAD, format!("/{pubky}/pub/foo"), Budget::Read),
+            (Method::PUT, format!("/{pubky}/pub/foo"), Budget::Write),
+            (Method::DELETE, format!("/{pubky}/pub/foo"), Budget::Write),
+            (Method::PUT, format!("/pkarr/{pubky}"), Budget::Write),
+        ] {
+            assert_eq!(Budget::of(&method, &path), budget, "{method} {path}");
+        }
+
+        assert_eq!(pubky_of(&format!("/{pubky}/pub/foo")), Some(pubky.clone()));
+        assert_eq!(pubky_of(&format!("/pkarr/{pubky}")), Some(pubky));
+        assert_eq!(pubky_of("/events/"), None);
+    }
+
+    #[test]
+    fn client_ip_behind_proxy() {
+        let proxy = IpAddr::from([10, 0, 0, 1]);
+        let client = IpAddr::from([192, 0, 2, 1]);
+        let trusted = [proxy, IpAddr::from([10, 0, 0, 2])];
+
+        let request = |peer: IpAddr, headers: &[(&'static str, &str)]| {
+            let mut request = Request::new(axum::body::Body::empty());
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::new(peer, 1234)));
+            for (name, value) in headers {
+                request.headers_mut().append(
+                    header::HeaderName::from_static(name),
+                    value.parse().unwrap(),
+                );
+            }
+            request
+        };
+
+        // Untrusted peers can't spoof their address.
+        let spoofed = request(client, &[("x-forwarded-for", "203.0.113.1")]);
+        assert_eq!(client_ip(&spoofed, &trusted), Some(client));
+        assert_eq!(client_ip(&spoofed, &[]), Some(client));
+
+        for headers in [
+            &[("x-forwarded-for", "192.0.2.1")][..],
+            &[("x-forwarded-for", "203.0.113.1, 192.0.2.1, 10.0.0.2")],
+            &[
+                ("x-forwarded-for", "203.0.113.1"),
+                ("x-forwarded-for", "192.0.2.1"),
+            ],
+            &[("forwarded", "for=192.0.2.1;proto=https")],
+            &[("forwarded", "for=\"192.0.2.1:4711\", for=10.0.0.2")],
+        ] {
+            assert_eq!(
+                client_ip(&request(proxy, headers), &trusted),
+                Some(client),
+                "{headers:?}"
+            );
+        }
+
+        let ipv6 = request(proxy, &[("forwarded", "For=\"[2001:db8::1]:4711\"")]);
+        assert_eq!(
+            client_ip(&ipv6, &trusted),
+            Some("2001:db8::1".parse().unwrap())
+        );
+
+        // Without a usable header, the proxy is the client.
+        assert_eq!(client_ip(&request(proxy, &[]), &trusted), Some(proxy));
+        let obfuscated = request(proxy, &[("forwarded", "for=_hidden")]);
+        assert_eq!(client_ip(&obfuscated, &trusted), Some(proxy));
+    }
+
+    #[test]
+    fn token_bucket() {
+        let pubky = Keypair::random().public_key();
+        let ip = IpAddr::from([127, 0, 0, 1]);
+
+        let limiter = RateLimiter::new(RateLimits {
+            write: BudgetLimits {
+                per_ip: Some(Quota::new(3, 60)),
+                per_pubky: Some(Quota::new(2, 60)),
+            },
+            ..RateLimits::disabled()
+        });
+
+        assert!(limiter.check(Budget::Write, Some(ip), Some(&pubky)).is_ok());
+        assert!(limiter.check(Budget::Write, Some(ip), Some(&pubky)).is_ok());
+
+        // The pubky bucket is empty, the IP bucket still has a token.
+        let wait_time = limiter
+            .check(Budget::Write, Some(ip), Some(&pubky))
+            .unwrap_err();
+        assert!(wait_time > Duration::ZERO && wait_time <= Duration::from_secs(1));
+
+        assert!(limiter.check(Budget::Write, Some(ip), None).is_ok());
+        assert!(limiter.check(Budget::Write, Some(ip), None).is_err());
+
+        // Other budgets are unaffected.
+        assert!(limiter.check(Budget::Read, Some(ip), Some(&pubky)).is_ok());
+
+        assert_eq!(limiter.rejections(Budget::Write), 2);
+        assert_eq!(limiter.rejections(Budget::Read), 0);
+    }
+
+    #[tokio::test]
+    async fn too_many_requests() -> anyhow::Result<()> {
+        let testnet = pkarr::mainline::Testnet::new(3);
+
+        let config = Config::try_from_str(&format!(
+            r#"
+            testnet = true
+            bootstrap = {:?}
+            storage = {:?}
+
+            [rate_limits.read]
+            per_ip = {{ burst = 2, per_minute = 1 }}
+            "#,
+            testnet.bootstrap,
+            Config::test(&testnet).storage(),
+        ))?;
+
+        let server = Homeserver::start(config).await?;
+
+        let client = reqwest::Client::new();
+        let url = format!("http://localhost:{}/", server.port());
+
+        for _ in 0..2 {
+            client.get(&url).send().await?.error_for_status()?;
+        }
+
+        let response = client.get(&url).send().await?;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let retry_after: u64 = response
+            .headers()
+            .get(header::RETRY_AFTER)
+            .unwrap()
+            .to_str()?
+            .parse()?;
+        assert!(retry_after > 0 && retry_after <= 60);
+
+        // Health probes are not rate limited.
+        for path in ["health", "ready"] {
+            let response = client.get(format!("{url}{path}")).send().await?;
+            assert_ne!(response.status(), StatusCode::TOO_MANY_REQUESTS, "/{path}");
+        }
+
+        // Other budgets use their default limits.
+        let response = client.get(format!("{url}events/")).send().await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        assert!(server.rate_limited_requests().contains(&("read", 1)));
+
+        Ok(())
+    }
+}
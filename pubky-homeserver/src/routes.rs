@@ -1,12 +1,26 @@
+use ::pkarr::PublicKey;
 use axum::{
-    extract::DefaultBodyLimit,
+    body::{Body, Bytes},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{from_fn_with_state, map_response},
     routing::{delete, get, head, post, put},
     Router,
 };
+use futures_util::stream::StreamExt;
+use pubky_common::limits::Limit;
 use tower_cookies::CookieManagerLayer;
-use tower_http::{cors::CorsLayer, trace::TraceLayer};
+use tower_http::{
+    cors::{AllowCredentials, AllowHeaders, AllowMethods, AllowOrigin, CorsLayer},
+    trace::TraceLayer,
+};
 
-use crate::server::AppState;
+use crate::{
+    compression, content_type,
+    error::{Error, Result},
+    metrics, rate_limiter,
+    server::AppState,
+    subdomains,
+};
 
 use self::pkarr::pkarr_router;
 
@@ -15,30 +29,210 @@ mod feed;
 mod pkarr;
 mod public;
 mod root;
+mod uploads;
 
 fn base(state: AppState) -> Router {
     Router::new()
         .route("/", get(root::handler))
+        .route("/health", get(root::health))
+        .route("/ready", get(root::ready))
         .route("/signup", post(auth::signup))
         .route("/session", post(auth::signin))
         .route("/:pubky/session", get(auth::session))
         .route("/:pubky/session", delete(auth::signout))
         .route("/:pubky/*path", put(public::put))
+        .route("/:pubky/*path", post(public::post))
         .route("/:pubky/*path", get(public::get))
         .route("/:pubky/*path", head(public::head))
         .route("/:pubky/*path", delete(public::delete))
         .route("/events/", get(feed::feed))
         .layer(CookieManagerLayer::new())
-        // TODO: revisit if we enable streaming big payloads
-        // TODO: maybe add to a separate router (drive router?).
-        .layer(DefaultBodyLimit::max(100 * 1024 * 1024))
+        .layer(compression::layer())
+        .layer(map_response(compression::weaken_etag))
         .with_state(state)
 }
 
 pub fn create_app(state: AppState) -> Router {
-    base(state.clone())
+    let app = base(state.clone())
         // TODO: Only enable this for test environments?
-        .nest("/pkarr", pkarr_router(state))
-        .layer(CorsLayer::very_permissive())
-        .layer(TraceLayer::new_for_http())
+        .nest("/pkarr", pkarr_router(state.clone()))
+        .layer(from_fn_with_state(state.clone(), rate_limiter::middleware))
+        .layer(from_fn_with_state(state.clone(), metrics::middleware))
+        .layer(map_response(content_type::nosniff))
+        .layer(cors(&state))
+        .layer(TraceLayer::new_for_http());
+
+    if state.config.subdomains() {
+        // Rewrite subdomain requests before they are routed.
+        return Router::new()
+            .fallback_service(app)
+            .layer(from_fn_with_state(state, subdomains::middleware));
+    }
+
+    app
+}
+
+/// Allow cross-origin requests from any origin, but only with credentials (session cookies)
+/// from the [allowed origins](crate::config::Config::cors_allowed_origins).
+///
+/// Without configured origins, any origin can send credentials, unless users are served
+/// on their own subdomains, whose cookies shouldn't be usable by other origins.
+fn cors(state: &AppState) -> CorsLayer {
+    let allowed_origins = match state.config.cors_allowed_origins() {
+        Some(origins) => origins.to_vec(),
+        None if state.config.subdomains() => Vec::new(),
+        None => return CorsLayer::very_permissive(),
+    };
+
+    CorsLayer::new()
+        .allow_credentials(AllowCredentials::predicate(move |origin, _| {
+            allowed_origins
+                .iter()
+                .any(|allowed| allowed == "*" || allowed.as_bytes() == origin.as_bytes())
+        }))
+        .allow_headers(AllowHeaders::mirror_request())
+        .allow_methods(AllowMethods::mirror_request())
+        .allow_origin(AllowOrigin::mirror_request())
+}
+
+/// Read a small request body in memory, responding with `413 Payload Too Large`
+/// if it is larger than `max` bytes.
+///
+/// Handlers that stream large bodies (entries) enforce their own limits instead.
+pub(crate) async fn read_body(body: Body, max: u64) -> Result<Bytes> {
+    let mut bytes = Vec::new();
+
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+
+        if (bytes.len() + chunk.len()) as u64 > max {
+            return Err(Error::payload_too_large(Limit::BodySize, max));
+        }
+
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(bytes.into())
+}
+
+/// The validated `Content-Type` of a request, responding with `400 Bad Request`
+/// if it isn't a valid media type.
+pub(crate) fn request_content_type(headers: &HeaderMap) -> Result<Option<String>> {
+    let Some(value) = headers.get(header::CONTENT_TYPE) else {
+        return Ok(None);
+    };
+
+    value
+        .to_str()
+        .ok()
+        .and_then(content_type::parse)
+        .map(Some)
+        .ok_or_else(|| Error::new(StatusCode::BAD_REQUEST, Some("Invalid Content-Type")))
+}
+
+/// Check that replacing the entry at `path` with `content_length` bytes
+/// keeps `public_key` within its entry size limit and storage quota.
+pub(crate) fn check_entry_limits(
+    state: &AppState,
+    public_key: &PublicKey,
+    path: &str,
+    content_length: u64,
+) -> Result<()> {
+    let max_entry_size = state.config.max_entry_size(public_key);
+    if content_length > max_entry_size {
+        return Err(Error::payload_too_large(Limit::EntrySize, max_entry_size));
+    }
+
+    check_storage_quota(state, public_key, path, content_length)
+}
+
+/// Check that writing `content_length` bytes at `path` keeps `public_key`
+/// within its storage quota.
+pub(crate) fn check_storage_quota(
+    state: &AppState,
+    public_key: &PublicKey,
+    path: &str,
+    content_length: u64,
+) -> Result<()> {
+    if let Some(quota) = state.config.storage_quota(public_key) {
+        if state
+            .db
+            .storage_usage_after_write(public_key, path, content_length)?
+            > quota
+        {
+            return Err(Error::payload_too_large(Limit::StorageQuota, quota));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pkarr::mainline::Testnet;
+    use reqwest::{header, Method};
+
+    use crate::{config::Config, Homeserver};
+
+    #[tokio::test]
+    async fn cors_credentials() -> anyhow::Result<()> {
+        let testnet = Testnet::new(3);
+
+        let config = Config::try_from_str(&format!(
+            r#"
+            bootstrap = {:?}
+            storage = {:?}
+            cors_allowed_origins = ["https://app.example.com/"]
+            "#,
+            testnet.bootstrap,
+            Config::test(&testnet).storage(),
+        ))?;
+
+        let server = Homeserver::start(config).await?;
+
+        let response = preflight(&server, "https://app.example.com").await?;
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_CREDENTIALS],
+            "true"
+        );
+
+        // Other origins can make requests, but without credentials.
+        let response = preflight(&server, "https://evil.example.com").await?;
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://evil.example.com"
+        );
+        assert!(response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS)
+            .is_none());
+
+        // Without configured origins, any origin can send credentials.
+        let server = Homeserver::start_test(&testnet).await?;
+
+        let response = preflight(&server, "https://evil.example.com").await?;
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_CREDENTIALS],
+            "true"
+        );
+
+        Ok(())
+    }
+
+    async fn preflight(server: &Homeserver, origin: &str) -> reqwest::Result<reqwest::Response> {
+        reqwest::Client::new()
+            .request(
+                Method::OPTIONS,
+                format!("http://localhost:{}/signup", server.port()),
+            )
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .send()
+            .await
+    }
 }
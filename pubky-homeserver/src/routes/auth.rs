@@ -1,10 +1,11 @@
 use axum::{
+    body::Body,
     extract::{Host, State},
     http::StatusCode,
     response::IntoResponse,
+    Extension,
 };
 use axum_extra::{headers::UserAgent, TypedHeader};
-use bytes::Bytes;
 use tower_cookies::{cookie::SameSite, Cookie, Cookies};
 
 use pubky_common::{crypto::random_bytes, session::Session, timestamp::Timestamp};
@@ -17,18 +18,22 @@ use crate::{
     error::{Error, Result},
     extractors::Pubky,
     server::AppState,
+    subdomains::Subdomain,
 };
 
+use super::read_body;
+
 pub async fn signup(
     State(state): State<AppState>,
     user_agent: Option<TypedHeader<UserAgent>>,
     cookies: Cookies,
     host: Host,
-    body: Bytes,
+    subdomain: Option<Extension<Subdomain>>,
+    body: Body,
 ) -> Result<impl IntoResponse> {
     // TODO: Verify invitation link.
     // TODO: add errors in case of already axisting user.
-    signin(State(state), user_agent, cookies, host, body).await
+    signin(State(state), user_agent, cookies, host, subdomain, body).await
 }
 
 pub async fn session(
@@ -88,12 +93,27 @@ pub async fn signin(
     user_agent: Option<TypedHeader<UserAgent>>,
     cookies: Cookies,
     Host(host): Host,
-    body: Bytes,
+    subdomain: Option<Extension<Subdomain>>,
+    body: Body,
 ) -> Result<impl IntoResponse> {
-    let token = state.verifier.verify(&body)?;
+    let body = read_body(body, state.config.max_auth_body_size()).await?;
+
+    let token = state
+        .verifier
+        .verify(&body)
+        .inspect_err(|error| state.metrics.auth_failed(error))?;
 
     let public_key = token.pubky();
 
+    if let Some(Extension(Subdomain(subdomain))) = subdomain {
+        if &subdomain != public_key {
+            return Err(Error::new(
+                StatusCode::FORBIDDEN,
+                Some("AuthToken pubky does not match the subdomain"),
+            ));
+        }
+    }
+
     let mut wtxn = state.db.env.write_txn()?;
 
     let users = state.db.tables.users;
@@ -137,12 +157,12 @@ pub async fn signin(
 }
 
 /// Assuming that if the server is addressed by anything other than
-/// localhost, or IP addresses, it is not addressed from a browser in an
+/// localhost (or its subdomains), or IP addresses, it is not addressed from a browser in an
 /// secure (HTTPs) window, thus it no need to `secure` and `same_site=none` to cookies
 fn is_secure(host: &str) -> bool {
     url::Host::parse(host)
         .map(|host| match host {
-            url::Host::Domain(domain) => domain != "localhost",
+            url::Host::Domain(domain) => domain != "localhost" && !domain.ends_with(".localhost"),
             _ => false,
         })
         .unwrap_or_default()
@@ -150,7 +170,10 @@ fn is_secure(host: &str) -> bool {
 
 #[cfg(test)]
 mod tests {
-    use pkarr::Keypair;
+    use pkarr::{mainline::Testnet, Keypair};
+    use pubky_common::limits::{Limit, PayloadTooLarge};
+
+    use crate::{config::DEFAULT_MAX_AUTH_BODY_SIZE, Homeserver};
 
     use super::*;
 
@@ -162,7 +185,34 @@ mod tests {
         assert!(!is_secure("[2001:0db8:0000:0000:0000:ff00:0042:8329]"));
         assert!(!is_secure("localhost"));
         assert!(!is_secure("localhost:23423"));
+        assert!(!is_secure(&format!(
+            "{}.localhost",
+            Keypair::random().public_key()
+        )));
         assert!(is_secure(&Keypair::random().public_key().to_string()));
         assert!(is_secure("example.com"));
     }
+
+    #[tokio::test]
+    async fn auth_body_size_limit() -> anyhow::Result<()> {
+        let testnet = Testnet::new(3);
+        let server = Homeserver::start_test(&testnet).await?;
+
+        let response = reqwest::Client::new()
+            .post(format!("http://localhost:{}/signup", server.port()))
+            .body(vec![0; DEFAULT_MAX_AUTH_BODY_SIZE as usize + 1])
+            .send()
+            .await?;
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            serde_json::from_slice::<PayloadTooLarge>(&response.bytes().await?)?,
+            PayloadTooLarge {
+                limit: Limit::BodySize,
+                max: DEFAULT_MAX_AUTH_BODY_SIZE
+            }
+        );
+
+        Ok(())
+    }
 }
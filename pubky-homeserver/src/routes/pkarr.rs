@@ -1,13 +1,11 @@
 use axum::{
-    body::{Body, Bytes},
+    body::Body,
     extract::State,
     http::StatusCode,
     response::IntoResponse,
     routing::{get, put},
     Router,
 };
-use futures_util::stream::StreamExt;
-
 use pkarr::SignedPacket;
 
 use crate::{
@@ -16,6 +14,8 @@ use crate::{
     server::AppState,
 };
 
+use super::read_body;
+
 /// Pkarr relay, helpful for testing.
 ///
 /// For real productioin, you should use a [production ready
@@ -32,17 +32,11 @@ pub async fn pkarr_put(
     pubky: Pubky,
     body: Body,
 ) -> Result<impl IntoResponse> {
-    let mut bytes = Vec::with_capacity(1104);
-
-    let mut stream = body.into_data_stream();
-
-    while let Some(chunk) = stream.next().await {
-        bytes.extend_from_slice(&chunk?)
-    }
+    let bytes = read_body(body, state.config.max_pkarr_body_size()).await?;
 
     let public_key = pubky.public_key().to_owned();
 
-    let signed_packet = SignedPacket::from_relay_payload(&public_key, &Bytes::from(bytes))?;
+    let signed_packet = SignedPacket::from_relay_payload(&public_key, &bytes)?;
 
     state.pkarr_client.publish(&signed_packet).await?;
 
@@ -1,66 +1,137 @@
 use axum::{
     body::Body,
     debug_handler,
-    extract::State,
+    extract::{Query, State},
     http::{header, HeaderMap, HeaderValue, Response, StatusCode},
     response::IntoResponse,
 };
 use futures_util::stream::StreamExt;
 use httpdate::HttpDate;
 use pkarr::PublicKey;
-use std::{io::Write, str::FromStr};
+use pubky_common::{capabilities::Action, limits::Limit, list::DirectoryStat, path::Path};
+use std::{collections::HashMap, io::Write, str::FromStr};
 use tower_cookies::Cookies;
 
 use crate::{
+    content_type,
     database::tables::entries::Entry,
     error::{Error, Result},
     extractors::{EntryPath, ListQueryParams, Pubky},
     server::AppState,
 };
 
+use super::{check_entry_limits, check_storage_quota, request_content_type, uploads};
+
 pub async fn put(
     State(mut state): State<AppState>,
     pubky: Pubky,
     path: EntryPath,
     cookies: Cookies,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
     body: Body,
-) -> Result<impl IntoResponse> {
+) -> Result<Response<Body>> {
     let public_key = pubky.public_key().clone();
+
+    if path.path().is_directory() {
+        return Err(Error::new(
+            StatusCode::BAD_REQUEST,
+            "Writing to a directory path (ending with '/') is forbidden".into(),
+        ));
+    }
+
+    verify(path.as_str())?;
+    authorize(&mut state, cookies, &public_key, path.path())?;
+
+    if params.contains_key("upload") {
+        return uploads::put_part(state, &public_key, path.as_str(), &params, body).await;
+    }
+
     let path = path.as_str().to_string();
+    let content_type = request_content_type(&headers)?;
+
+    // Reject early if the client declared the length of the body.
+    if let Some(content_length) = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<u64>().ok())
+    {
+        check_entry_limits(&state, &public_key, &path, content_length)?;
+    }
 
-    verify(&path)?;
-    authorize(&mut state, cookies, &public_key, &path)?;
+    let max_entry_size = state.config.max_entry_size(&public_key);
 
-    let mut entry_writer = state.db.write_entry(&public_key, &path)?;
+    let mut db = state.db.clone();
+    let mut entry_writer = db.write_entry(&public_key, &path)?;
+
+    if let Some(content_type) = &content_type {
+        entry_writer.set_content_type(content_type);
+    }
+
+    let mut content_length = 0;
 
     let mut stream = body.into_data_stream();
     while let Some(next) = stream.next().await {
         let chunk = next?;
+
+        content_length += chunk.len() as u64;
+        if content_length > max_entry_size {
+            return Err(Error::payload_too_large(Limit::EntrySize, max_entry_size));
+        }
+
         entry_writer.write_all(&chunk)?;
     }
 
+    check_entry_limits(&state, &public_key, &path, content_length)?;
+
     let _entry = entry_writer.commit()?;
 
     // TODO: return relevant headers, like Etag?
 
-    Ok(())
+    Ok(().into_response())
 }
 
 #[debug_handler]
 pub async fn get(
-    State(state): State<AppState>,
+    State(mut state): State<AppState>,
     headers: HeaderMap,
     pubky: Pubky,
     path: EntryPath,
+    cookies: Cookies,
     params: ListQueryParams,
+    Query(query): Query<HashMap<String, String>>,
 ) -> Result<impl IntoResponse> {
     verify(path.as_str())?;
     let public_key = pubky.public_key().clone();
+
+    if query.contains_key("upload") {
+        authorize(&mut state, cookies, &public_key, path.path())?;
+
+        return uploads::status(state, &public_key, path.as_str(), &query).await;
+    }
     let path = path.as_str().to_string();
 
     if path.ends_with('/') {
         let txn = state.db.env.read_txn()?;
 
+        if query.contains_key("stat") {
+            let stats = state
+                .db
+                .get_directory_stats(&txn, &public_key, &path)?
+                .ok_or_else(|| Error::new(StatusCode::NOT_FOUND, "Directory Not Found".into()))?;
+
+            return Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(
+                    header::LAST_MODIFIED,
+                    stats.last_modified().format_http_date(),
+                )
+                .body(Body::from(serde_json::to_vec(&DirectoryStat::from(
+                    &stats,
+                ))?))?);
+        }
+
         let path = format!("{public_key}/{path}");
 
         if !state.db.contains_directory(&txn, &path)? {
@@ -71,7 +142,7 @@ pub async fn get(
         }
 
         // Handle listing
-        let vec = state.db.list(
+        let list = state.db.list_entries(
             &txn,
             &path,
             params.reverse,
@@ -80,10 +151,23 @@ pub async fn get(
             params.shallow,
         )?;
 
+        if accepts_json(&headers) {
+            return Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_vec(&list)?))?);
+        }
+
+        let urls = list
+            .entries
+            .into_iter()
+            .map(|entry| entry.url)
+            .collect::<Vec<_>>();
+
         return Ok(Response::builder()
             .status(StatusCode::OK)
             .header(header::CONTENT_TYPE, "text/plain")
-            .body(Body::from(vec.join("\n")))?);
+            .body(Body::from(urls.join("\n")))?);
     }
 
     let (entry_tx, entry_rx) = flume::bounded::<Option<Entry>>(1);
@@ -142,7 +226,6 @@ pub fn get_entry(
 ) -> Result<Response<Body>> {
     if let Some(entry) = entry {
         // TODO: Enable seek API (range requests)
-        // TODO: Gzip? or brotli?
 
         let mut response = HeaderMap::from(&entry).into_response();
 
@@ -159,17 +242,17 @@ pub fn get_entry(
             }
         };
 
-        // Handle IF_NONE_MATCH
+        // Handle IF_NONE_MATCH, using weak comparison since
+        // compressed responses have weak ETags.
         if let Some(str) = headers
             .get(header::IF_NONE_MATCH)
             .and_then(|h| h.to_str().ok())
         {
             let etag = format!("\"{}\"", entry.content_hash());
             if str
-                .trim()
                 .split(',')
-                .collect::<Vec<_>>()
-                .contains(&etag.as_str())
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == etag || tag == "*")
             {
                 *response.status_mut() = StatusCode::NOT_MODIFIED;
             };
@@ -190,22 +273,152 @@ pub async fn delete(
     pubky: Pubky,
     path: EntryPath,
     cookies: Cookies,
+    Query(params): Query<HashMap<String, String>>,
 ) -> Result<impl IntoResponse> {
     let public_key = pubky.public_key().clone();
-    let path = path.as_str();
 
-    authorize(&mut state, cookies, &public_key, path)?;
-    verify(path)?;
+    authorize(&mut state, cookies, &public_key, path.path())?;
+    verify(path.as_str())?;
+
+    if params.contains_key("upload") {
+        return uploads::delete(state, &public_key, path.as_str(), &params).await;
+    }
+
+    let path = path.as_str().to_string();
+
+    if path.ends_with('/') {
+        if !params.contains_key("recursive") {
+            return Err(Error::new(
+                StatusCode::CONFLICT,
+                "Deleting a directory requires the `recursive` query parameter".into(),
+            ));
+        }
+
+        let count =
+            tokio::task::spawn_blocking(move || state.db.delete_entries(&public_key, &path))
+                .await??;
+
+        if count == 0 {
+            return Err(Error::with_status(StatusCode::NOT_FOUND));
+        }
+
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/plain")
+            .body(Body::from(count.to_string()))?);
+    }
 
     // TODO: should we wrap this with `tokio::task::spawn_blocking` in case it takes too long?
-    let deleted = state.db.delete_entry(&public_key, path)?;
+    let deleted = state.db.delete_entry(&public_key, &path)?;
 
     if !deleted {
-        // TODO: if the path ends with `/` return a `CONFLICT` error?
         return Err(Error::with_status(StatusCode::NOT_FOUND));
     };
 
-    Ok(())
+    Ok(Response::new(Body::empty()))
+}
+
+/// Copy or move entries within the same user's drive.
+///
+/// - `?copy=<destination>` copies the entry at `path` to `destination`.
+/// - `?move=<destination>` moves the entry at `path` to `destination`.
+///
+/// If `path` ends with `/`, every entry under it is copied (or moved)
+/// under `destination`, which should also end with `/`.
+pub async fn post(
+    State(mut state): State<AppState>,
+    pubky: Pubky,
+    path: EntryPath,
+    cookies: Cookies,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response<Body>> {
+    let public_key = pubky.public_key().clone();
+    let source = path.as_str();
+
+    if params.contains_key("uploads") || params.contains_key("upload") {
+        if path.path().is_directory() {
+            return Err(Error::new(
+                StatusCode::BAD_REQUEST,
+                "Uploading to a directory path (ending with '/') is forbidden".into(),
+            ));
+        }
+
+        verify(source)?;
+        authorize(&mut state, cookies, &public_key, path.path())?;
+
+        return if params.contains_key("uploads") {
+            uploads::create(state, &public_key, source, &headers).await
+        } else {
+            uploads::finalize(state, &public_key, source, &params).await
+        };
+    }
+
+    let (destination, remove_source) = match (params.get("copy"), params.get("move")) {
+        (Some(destination), None) => (Path::parse(destination)?, false),
+        (None, Some(destination)) => (Path::parse(destination)?, true),
+        _ => {
+            return Err(Error::new(
+                StatusCode::BAD_REQUEST,
+                "Expected exactly one of `copy` or `move` query parameters".into(),
+            ))
+        }
+    };
+
+    verify(source)?;
+    verify(destination.as_str())?;
+
+    if path.path().is_directory() != destination.is_directory() {
+        return Err(Error::new(
+            StatusCode::BAD_REQUEST,
+            "Source and destination should both be files or both be directories".into(),
+        ));
+    }
+
+    if path.path().is_within(&destination) || destination.is_within(path.path()) {
+        return Err(Error::new(
+            StatusCode::BAD_REQUEST,
+            "Source and destination overlap".into(),
+        ));
+    }
+
+    authorize_action(
+        &mut state,
+        cookies.clone(),
+        &public_key,
+        path.path(),
+        if remove_source {
+            Action::Write
+        } else {
+            Action::Read
+        },
+    )?;
+    authorize(&mut state, cookies, &public_key, &destination)?;
+
+    // Moving entries doesn't change the storage usage.
+    if !remove_source {
+        let content_length = state.db.content_length(&public_key, source)?;
+
+        if path.path().is_directory() {
+            check_storage_quota(&state, &public_key, destination.as_str(), content_length)?;
+        } else {
+            check_entry_limits(&state, &public_key, destination.as_str(), content_length)?;
+        }
+    }
+
+    let source = source.to_string();
+    let count = tokio::task::spawn_blocking(move || {
+        state
+            .db
+            .copy_entries(&public_key, &source, destination.as_str(), remove_source)
+    })
+    .await??;
+
+    if count == 0 {
+        return Err(Error::with_status(StatusCode::NOT_FOUND));
+    }
+
+    Ok(().into_response())
 }
 
 /// Authorize write (PUT or DELETE) for Public paths.
@@ -213,7 +426,18 @@ fn authorize(
     state: &mut AppState,
     cookies: Cookies,
     public_key: &PublicKey,
-    path: &str,
+    path: &Path,
+) -> Result<()> {
+    authorize_action(state, cookies, public_key, path, Action::Write)
+}
+
+/// Authorize an [Action] on a path for the session in the cookies.
+fn authorize_action(
+    state: &mut AppState,
+    cookies: Cookies,
+    public_key: &PublicKey,
+    path: &Path,
+    action: Action,
 ) -> Result<()> {
     // TODO: can we move this logic to the extractor or a layer
     // to perform this validation?
@@ -223,12 +447,10 @@ fn authorize(
         .ok_or(Error::with_status(StatusCode::UNAUTHORIZED))?;
 
     if session.pubky() == public_key
-        && session.capabilities().iter().any(|cap| {
-            path.starts_with(&cap.scope[1..])
-                && cap
-                    .actions
-                    .contains(&pubky_common::capabilities::Action::Write)
-        })
+        && session
+            .capabilities()
+            .iter()
+            .any(|cap| cap.allows(path, &action))
     {
         return Ok(());
     }
@@ -236,6 +458,18 @@ fn authorize(
     Err(Error::with_status(StatusCode::FORBIDDEN))
 }
 
+/// Whether the `Accept` header prefers a JSON response.
+fn accepts_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|accept| {
+            accept
+                .split(',')
+                .any(|media_type| media_type.trim().starts_with("application/json"))
+        })
+}
+
 fn verify(path: &str) -> Result<()> {
     if !path.starts_with("pub/") {
         return Err(Error::new(
@@ -244,8 +478,6 @@ fn verify(path: &str) -> Result<()> {
         ));
     }
 
-    // TODO: should we forbid paths ending with `/`?
-
     Ok(())
 }
 
@@ -258,15 +490,17 @@ impl From<&Entry> for HeaderMap {
             HeaderValue::from_str(&entry.timestamp().format_http_date())
                 .expect("http date is valid header value"),
         );
-        headers.insert(
-            header::CONTENT_TYPE,
-            // TODO: when setting content type from user input, we should validate it as a HeaderValue
-            entry
-                .content_type()
-                .try_into()
-                .or(HeaderValue::from_str(""))
-                .expect("valid header value"),
-        );
+        // Entries written before content types were validated may have invalid ones.
+        let content_type = content_type::parse(entry.content_type())
+            .and_then(|content_type| HeaderValue::try_from(content_type).ok())
+            .unwrap_or(HeaderValue::from_static("application/octet-stream"));
+        if content_type::is_active(content_type.to_str().unwrap_or_default()) {
+            headers.insert(
+                header::CONTENT_DISPOSITION,
+                HeaderValue::from_static("attachment"),
+            );
+        }
+        headers.insert(header::CONTENT_TYPE, content_type);
         headers.insert(
             header::ETAG,
             format!("\"{}\"", entry.content_hash())
@@ -282,9 +516,70 @@ impl From<&Entry> for HeaderMap {
 mod tests {
     use axum::http::header;
     use pkarr::{mainline::Testnet, Keypair};
+    use pubky_common::{
+        auth::AuthToken,
+        capabilities::Capability,
+        limits::{Limit, PayloadTooLarge},
+        session::Session,
+    };
     use reqwest::{self, Method, StatusCode};
 
-    use crate::Homeserver;
+    use crate::{
+        config::Config,
+        test_utils::{entry_buffers, signup, start_partial_put, wait_until},
+        Homeserver,
+    };
+
+    #[tokio::test]
+    async fn existing_session_scope_is_a_prefix() -> anyhow::Result<()> {
+        let testnet = Testnet::new(3);
+        let mut server = Homeserver::start_test(&testnet).await?;
+
+        let keypair = Keypair::random();
+        let public_key = keypair.public_key();
+
+        let client = reqwest::Client::builder().build()?;
+
+        let base = format!("http://localhost:{}/{public_key}", server.port());
+
+        client
+            .post(format!("http://localhost:{}/signup", server.port()))
+            .body(AuthToken::sign(&keypair, vec![Capability::root()]).serialize())
+            .send()
+            .await?
+            .error_for_status()?;
+
+        // A session stored before scopes were parsed as paths.
+        let token = AuthToken::sign(&keypair, vec![Capability::try_from("/pub/foo:rw")?]);
+        let db = server.database_mut();
+        let mut wtxn = db.env.write_txn()?;
+        db.tables.sessions.put(
+            &mut wtxn,
+            "existing",
+            &Session::new(&token, None).serialize(),
+        )?;
+        wtxn.commit()?;
+
+        let cookie = format!("{public_key}=existing");
+
+        for (path, status) in [
+            ("pub/foo", StatusCode::OK),
+            ("pub/foo/bar.txt", StatusCode::OK),
+            ("pub/foo.txt", StatusCode::OK),
+            ("pub/bar.txt", StatusCode::FORBIDDEN),
+        ] {
+            let response = client
+                .put(format!("{base}/{path}"))
+                .header(header::COOKIE, &cookie)
+                .body(vec![0])
+                .send()
+                .await?;
+
+            assert_eq!(response.status(), status, "{path}");
+        }
+
+        Ok(())
+    }
 
     #[tokio::test]
     async fn if_last_modified() -> anyhow::Result<()> {
@@ -377,4 +672,314 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn invalid_paths() -> anyhow::Result<()> {
+        let testnet = Testnet::new(3);
+        let server = Homeserver::start_test(&testnet).await?;
+
+        let public_key = Keypair::random().public_key();
+
+        let client = reqwest::Client::builder().build()?;
+
+        for path in ["pub//foo", "pub/foo%7F", "PUB/foo"] {
+            let url = format!("http://localhost:{}/{public_key}/{path}", server.port());
+
+            let response = client.request(Method::GET, &url).send().await?;
+
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{path}");
+        }
+
+        let url = format!("http://localhost:{}/{public_key}/pub/foo/", server.port());
+
+        let response = client
+            .request(Method::PUT, &url)
+            .body(vec![0])
+            .send()
+            .await?;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn compression() -> anyhow::Result<()> {
+        let testnet = Testnet::new(3);
+        let mut server = Homeserver::start_test(&testnet).await?;
+
+        let public_key = Keypair::random().public_key();
+
+        let json = format!("[{}]", vec!["{\"foo\":\"bar\"}"; 100].join(","));
+
+        server
+            .database_mut()
+            .write_entry(&public_key, "pub/foo.json")?
+            .set_content_type("application/json")
+            .update(json.as_bytes())?
+            .commit()?;
+        server
+            .database_mut()
+            .write_entry(&public_key, "pub/foo.bin")?
+            .update(json.as_bytes())?
+            .commit()?;
+
+        let client = reqwest::Client::builder().build()?;
+
+        let url = format!(
+            "http://localhost:{}/{public_key}/pub/foo.json",
+            server.port()
+        );
+
+        let identity = client.request(Method::GET, &url).send().await?;
+        assert!(identity.headers().get(header::CONTENT_ENCODING).is_none());
+        let strong_etag = identity.headers().get(header::ETAG).unwrap().clone();
+        assert_eq!(identity.bytes().await?, json.as_bytes());
+
+        for encoding in ["gzip", "br", "zstd"] {
+            let response = client
+                .request(Method::GET, &url)
+                .header(header::ACCEPT_ENCODING, encoding)
+                .send()
+                .await?;
+
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                response.headers().get(header::CONTENT_ENCODING).unwrap(),
+                encoding
+            );
+
+            let etag = response.headers().get(header::ETAG).unwrap().clone();
+            assert_eq!(
+                etag.as_bytes(),
+                [b"W/", strong_etag.as_bytes()].concat().as_slice()
+            );
+            assert!(response.bytes().await?.len() < json.len());
+
+            // Weak comparison for If-None-Match
+            let response = client
+                .request(Method::GET, &url)
+                .header(header::ACCEPT_ENCODING, encoding)
+                .header(header::IF_NONE_MATCH, etag)
+                .send()
+                .await?;
+
+            assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        }
+
+        // Not compressible
+        let response = client
+            .request(
+                Method::GET,
+                format!(
+                    "http://localhost:{}/{public_key}/pub/foo.bin",
+                    server.port()
+                ),
+            )
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .send()
+            .await?;
+
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(response.headers().get(header::ETAG).unwrap(), &strong_etag);
+
+        // Listings
+        let response = client
+            .request(
+                Method::GET,
+                format!("http://localhost:{}/{public_key}/pub/", server.port()),
+            )
+            .header(header::ACCEPT, "application/json")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .send()
+            .await?;
+
+        assert_eq!(
+            response.headers().get(header::CONTENT_ENCODING).unwrap(),
+            "gzip"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn content_types() -> anyhow::Result<()> {
+        let testnet = Testnet::new(3);
+        let server = Homeserver::start_test(&testnet).await?;
+
+        let keypair = Keypair::random();
+        let public_key = keypair.public_key();
+
+        let client = reqwest::Client::builder().build()?;
+
+        let cookie = signup(&server, &keypair).await?;
+
+        let url = |path: &str| format!("http://localhost:{}/{public_key}/{path}", server.port());
+
+        let response = client
+            .put(url("pub/foo"))
+            .header(header::COOKIE, &cookie)
+            .header(header::CONTENT_TYPE, "text/html<script>")
+            .body("foo")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        for (path, content_type, attachment) in [
+            ("pub/foo.html", "text/html; charset=utf-8", true),
+            ("pub/foo.svg", "image/svg+xml", true),
+            ("pub/foo.txt", "Text/Plain", false),
+        ] {
+            client
+                .put(url(path))
+                .header(header::COOKIE, &cookie)
+                .header(header::CONTENT_TYPE, content_type)
+                .body("<script>alert(1)</script>")
+                .send()
+                .await?
+                .error_for_status()?;
+
+            let response = client.get(url(path)).send().await?.error_for_status()?;
+            let headers = response.headers();
+
+            assert_eq!(
+                headers.get(header::CONTENT_TYPE).unwrap(),
+                &content_type.to_ascii_lowercase()
+            );
+            assert_eq!(
+                headers.get(header::X_CONTENT_TYPE_OPTIONS).unwrap(),
+                "nosniff"
+            );
+            assert_eq!(
+                headers.get(header::CONTENT_DISPOSITION).is_some(),
+                attachment,
+                "{content_type}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn entry_limits() -> anyhow::Result<()> {
+        let testnet = Testnet::new(3);
+
+        let keypair = Keypair::random();
+        let public_key = keypair.public_key();
+
+        let config = Config::try_from_str(&format!(
+            r#"
+            testnet = true
+            bootstrap = {:?}
+            storage = {:?}
+            storage_quota = 8
+
+            [user_limits.{public_key}]
+            max_entry_size = 5
+            "#,
+            testnet.bootstrap,
+            Config::test(&testnet).storage(),
+        ))?;
+
+        let server = Homeserver::start(config).await?;
+
+        let client = reqwest::Client::builder().build()?;
+
+        let cookie = signup(&server, &keypair).await?;
+
+        let put = |path: &'static str, body: &'static [u8]| {
+            client
+                .put(format!(
+                    "http://localhost:{}/{public_key}/{path}",
+                    server.port()
+                ))
+                .header(header::COOKIE, &cookie)
+                .body(body)
+                .send()
+        };
+
+        let response = put("pub/foo", b"123456").await?;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            serde_json::from_slice::<PayloadTooLarge>(&response.bytes().await?)?,
+            PayloadTooLarge {
+                limit: Limit::EntrySize,
+                max: 5
+            }
+        );
+
+        put("pub/foo", b"12345").await?.error_for_status()?;
+
+        let response = put("pub/bar", b"1234").await?;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            serde_json::from_slice::<PayloadTooLarge>(&response.bytes().await?)?,
+            PayloadTooLarge {
+                limit: Limit::StorageQuota,
+                max: 8
+            }
+        );
+
+        // Replacing an entry only counts the difference.
+        put("pub/foo", b"1234").await?.error_for_status()?;
+        put("pub/bar", b"1234").await?.error_for_status()?;
+
+        let post = |query: &'static str| {
+            client
+                .post(format!(
+                    "http://localhost:{}/{public_key}/pub/foo?{query}",
+                    server.port()
+                ))
+                .header(header::COOKIE, &cookie)
+                .send()
+        };
+
+        let response = post("copy=pub/baz").await?;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            serde_json::from_slice::<PayloadTooLarge>(&response.bytes().await?)?,
+            PayloadTooLarge {
+                limit: Limit::StorageQuota,
+                max: 8
+            }
+        );
+
+        // Moving doesn't change the storage usage.
+        post("move=pub/baz").await?.error_for_status()?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn aborted_put() -> anyhow::Result<()> {
+        let testnet = Testnet::new(3);
+        let config = Config::test(&testnet);
+        let buffers_dir = config.storage().join("buffers");
+
+        let server = Homeserver::start(config).await?;
+
+        let keypair = Keypair::random();
+        let public_key = keypair.public_key();
+
+        let client = reqwest::Client::builder().build()?;
+
+        let cookie = signup(&server, &keypair).await?;
+
+        // Send half of the body, then drop the connection.
+        let stream = start_partial_put(&server, &public_key, &cookie, &buffers_dir).await?;
+        drop(stream);
+
+        assert!(wait_until(|| entry_buffers(&buffers_dir) == 0).await);
+
+        let response = client
+            .get(format!(
+                "http://localhost:{}/{public_key}/pub/foo",
+                server.port()
+            ))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
 }
@@ -1,5 +1,116 @@
-use axum::response::IntoResponse;
+use std::time::{Duration, Instant};
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde_json::json;
+
+use crate::{error::Result, server::AppState};
 
 pub async fn handler() -> Result<impl IntoResponse, String> {
     Ok("This a Pubky homeserver.".to_string())
 }
+
+/// Liveness: the process is running and the database is readable.
+pub async fn health(State(state): State<AppState>) -> Result<impl IntoResponse> {
+    state.db.env.read_txn()?.commit()?;
+
+    Ok(Json(json!({
+        "status": "ok",
+        "last_published": last_published(&state),
+    })))
+}
+
+/// How long a readiness probe reuses the last resolution of the server's packet,
+/// instead of querying the DHT on every probe.
+const RESOLVE_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Readiness: the database is readable, and the server's pkarr packet
+/// was published and is resolvable, so clients can find this homeserver.
+pub async fn ready(State(state): State<AppState>) -> Result<impl IntoResponse> {
+    state.db.env.read_txn()?.commit()?;
+
+    let published = state.last_published().is_some();
+
+    let resolvable = published && resolvable(&state).await;
+
+    let status = if resolvable {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    Ok((
+        status,
+        Json(json!({
+            "status": if resolvable { "ready" } else { "not_ready" },
+            "published": published,
+            "resolvable": resolvable,
+            "last_published": last_published(&state),
+        })),
+    ))
+}
+
+/// Whether the server's own packet resolves, cached for [RESOLVE_CACHE_TTL].
+///
+/// The lock is held while resolving, so concurrent probes share a single query.
+async fn resolvable(state: &AppState) -> bool {
+    let mut last_resolved = state.last_resolved.lock().await;
+
+    if let Some((checked_at, resolvable)) = *last_resolved {
+        if checked_at.elapsed() < RESOLVE_CACHE_TTL {
+            return resolvable;
+        }
+    }
+
+    let resolvable = state
+        .pkarr_client
+        .resolve(&state.config.keypair().public_key())
+        .await
+        .ok()
+        .flatten()
+        .is_some();
+
+    *last_resolved = Some((Instant::now(), resolvable));
+
+    resolvable
+}
+
+fn last_published(state: &AppState) -> Option<String> {
+    state
+        .last_published()
+        .map(|timestamp| timestamp.format_http_date())
+}
+
+#[cfg(test)]
+mod tests {
+    use pkarr::mainline::Testnet;
+    use reqwest::StatusCode;
+
+    use crate::Homeserver;
+
+    #[tokio::test]
+    async fn health_and_ready() -> anyhow::Result<()> {
+        let testnet = Testnet::new(10);
+        let server = Homeserver::start_test(&testnet).await?;
+
+        let last_published = server.last_published().unwrap();
+
+        let client = reqwest::Client::new();
+
+        for path in ["health", "ready"] {
+            let response = client
+                .get(format!("http://localhost:{}/{path}", server.port()))
+                .send()
+                .await?;
+
+            assert_eq!(response.status(), StatusCode::OK, "/{path}");
+
+            let body: serde_json::Value = serde_json::from_slice(&response.bytes().await?)?;
+            assert_eq!(
+                body["last_published"],
+                last_published.format_http_date().as_str()
+            );
+        }
+
+        Ok(())
+    }
+}
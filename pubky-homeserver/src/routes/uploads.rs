@@ -0,0 +1,337 @@
+//! Resumable uploads, dispatched from the [super::public] handlers
+//! when an `uploads` or `upload=<id>` query parameter is present.
+//!
+//! - `POST <path>?uploads` creates a session and returns its id.
+//! - `PUT <path>?upload=<id>&part=<n>` uploads (or replaces) the part `n`.
+//! - `GET <path>?upload=<id>` returns the [UploadStatus](pubky_common::upload::UploadStatus).
+//! - `POST <path>?upload=<id>&hash=<blake3 hex>` writes the parts as the entry at `path`.
+//! - `DELETE <path>?upload=<id>` aborts the session.
+//!
+//! Callers are responsible for authorizing writes to `path` first.
+//!
+//! Staged parts count against the entry size limit and the storage quota as they are
+//! received, and each user can have at most [MAX_UPLOAD_SESSIONS] sessions of at most
+//! [MAX_UPLOAD_PARTS] parts.
+
+use std::{collections::HashMap, io::Write};
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, Response, StatusCode},
+    response::IntoResponse,
+};
+use futures_util::stream::StreamExt;
+use pkarr::PublicKey;
+use pubky_common::{crypto::Hash, limits::Limit, timestamp::Timestamp};
+
+use crate::{
+    error::{Error, Result},
+    server::AppState,
+};
+
+use super::{check_entry_limits, request_content_type};
+
+/// Maximum number of open upload sessions per user.
+pub const MAX_UPLOAD_SESSIONS: usize = 16;
+/// Maximum number of parts of an upload session, numbered from 0.
+pub const MAX_UPLOAD_PARTS: u32 = 10_000;
+
+pub async fn create(
+    state: AppState,
+    public_key: &PublicKey,
+    path: &str,
+    headers: &HeaderMap,
+) -> Result<Response<Body>> {
+    let content_type = request_content_type(headers)?.unwrap_or_default();
+
+    if state.db.list_uploads(public_key)?.len() >= MAX_UPLOAD_SESSIONS {
+        return Err(Error::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            format!("At most {MAX_UPLOAD_SESSIONS} upload sessions can be open at once").into(),
+        ));
+    }
+
+    let id = state.db.create_upload(public_key, path, &content_type)?;
+
+    Ok(Response::builder()
+        .status(StatusCode::CREATED)
+        .header(header::CONTENT_TYPE, "text/plain")
+        .body(Body::from(id.to_string()))?)
+}
+
+pub async fn put_part(
+    state: AppState,
+    public_key: &PublicKey,
+    path: &str,
+    params: &HashMap<String, String>,
+    body: Body,
+) -> Result<Response<Body>> {
+    let id = session_id(&state, public_key, path, params)?;
+
+    let part = params
+        .get("part")
+        .and_then(|part| part.parse::<u32>().ok())
+        .filter(|part| *part < MAX_UPLOAD_PARTS)
+        .ok_or(Error::new(
+            StatusCode::BAD_REQUEST,
+            format!("Expected a numeric `part` query parameter lower than {MAX_UPLOAD_PARTS}")
+                .into(),
+        ))?;
+
+    // The session's other parts, and the other staged parts of the user, are counted
+    // against the limits as if they were already written.
+    let max_entry_size = state.config.max_entry_size(public_key);
+    let max_part_length =
+        max_entry_size.saturating_sub(state.db.staged_upload_size(&id, Some(part))?);
+
+    let quota = state.config.storage_quota(public_key);
+    let remaining_quota = match quota {
+        Some(quota) => {
+            let mut staged = 0;
+            for other in state.db.list_uploads(public_key)? {
+                let excluded_part = (other == id).then_some(part);
+                staged += state.db.staged_upload_size(&other, excluded_part)?;
+            }
+
+            Some(
+                quota.saturating_sub(
+                    state
+                        .db
+                        .storage_usage_after_write(public_key, path, staged)?,
+                ),
+            )
+        }
+        None => None,
+    };
+
+    let mut writer = state.db.write_upload_part(&id, part)?;
+    let mut part_length = 0;
+
+    let mut stream = body.into_data_stream();
+    while let Some(next) = stream.next().await {
+        let chunk = next?;
+
+        part_length += chunk.len() as u64;
+        if part_length > max_part_length {
+            return Err(Error::payload_too_large(Limit::EntrySize, max_entry_size));
+        }
+        if let (Some(quota), Some(remaining_quota)) = (quota, remaining_quota) {
+            if part_length > remaining_quota {
+                return Err(Error::payload_too_large(Limit::StorageQuota, quota));
+            }
+        }
+
+        writer.write_all(&chunk)?;
+    }
+
+    writer.commit()?;
+
+    Ok(().into_response())
+}
+
+pub async fn status(
+    state: AppState,
+    public_key: &PublicKey,
+    path: &str,
+    params: &HashMap<String, String>,
+) -> Result<Response<Body>> {
+    let id = parse_id(params)?;
+
+    let status = state
+        .db
+        .get_upload(public_key, path, &id)?
+        .ok_or_else(not_found)?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(&status)?))?)
+}
+
+pub async fn finalize(
+    mut state: AppState,
+    public_key: &PublicKey,
+    path: &str,
+    params: &HashMap<String, String>,
+) -> Result<Response<Body>> {
+    let id = parse_id(params)?;
+
+    let expected = params
+        .get("hash")
+        .and_then(|hash| Hash::from_hex(hash).ok())
+        .ok_or(Error::new(
+            StatusCode::BAD_REQUEST,
+            "Expected a hex encoded blake3 `hash` query parameter".into(),
+        ))?;
+
+    let status = state
+        .db
+        .get_upload(public_key, path, &id)?
+        .ok_or_else(not_found)?;
+
+    let parts_count = status.parts.len() as u32;
+
+    if status
+        .parts
+        .last()
+        .is_some_and(|last| *last != parts_count - 1)
+    {
+        return Err(Error::new(
+            StatusCode::BAD_REQUEST,
+            "Parts should be numbered consecutively starting from 0".into(),
+        ));
+    }
+
+    // Parts are consecutive, so the offset is the total length.
+    check_entry_limits(&state, public_key, path, status.offset)?;
+
+    tokio::task::spawn_blocking(move || {
+        if let Err(hash) = state.db.finalize_upload(&id, parts_count, &expected)? {
+            return Err(Error::new(
+                StatusCode::BAD_REQUEST,
+                format!("Hash mismatch, uploaded parts hash to {hash}").into(),
+            ));
+        }
+
+        Ok(().into_response())
+    })
+    .await?
+}
+
+pub async fn delete(
+    state: AppState,
+    public_key: &PublicKey,
+    path: &str,
+    params: &HashMap<String, String>,
+) -> Result<Response<Body>> {
+    let id = session_id(&state, public_key, path, params)?;
+
+    state.db.delete_upload(&id)?;
+
+    Ok(().into_response())
+}
+
+/// Parse the `upload` query parameter, and check that it is the id
+/// of an existing session for the entry at `path`.
+fn session_id(
+    state: &AppState,
+    public_key: &PublicKey,
+    path: &str,
+    params: &HashMap<String, String>,
+) -> Result<Timestamp> {
+    let id = parse_id(params)?;
+
+    if state.db.get_upload(public_key, path, &id)?.is_none() {
+        return Err(not_found());
+    }
+
+    Ok(id)
+}
+
+fn parse_id(params: &HashMap<String, String>) -> Result<Timestamp> {
+    params
+        .get("upload")
+        .and_then(|id| Timestamp::try_from(id.to_string()).ok())
+        .ok_or_else(not_found)
+}
+
+fn not_found() -> Error {
+    Error::new(StatusCode::NOT_FOUND, "Upload session Not Found".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use pkarr::{mainline::Testnet, Keypair};
+    use pubky_common::limits::PayloadTooLarge;
+    use reqwest::{header, StatusCode};
+
+    use crate::{config::Config, test_utils::signup, Homeserver};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn staged_limits() -> anyhow::Result<()> {
+        let testnet = Testnet::new(3);
+
+        let config = Config::try_from_str(&format!(
+            r#"
+            testnet = true
+            bootstrap = {:?}
+            storage = {:?}
+            max_entry_size = 5
+            storage_quota = 8
+            db_map_size = 10485760
+            "#,
+            testnet.bootstrap,
+            Config::test(&testnet).storage(),
+        ))?;
+
+        let server = Homeserver::start(config).await?;
+
+        let keypair = Keypair::random();
+        let cookie = signup(&server, &keypair).await?;
+
+        let client = reqwest::Client::new();
+        let url = format!(
+            "http://localhost:{}/{}/pub/foo",
+            server.port(),
+            keypair.public_key()
+        );
+
+        let create = || async {
+            client
+                .post(format!("{url}?uploads"))
+                .header(header::COOKIE, &cookie)
+                .send()
+                .await
+        };
+        let put_part = |id: String, part: u32, body: &'static [u8]| {
+            client
+                .put(format!("{url}?upload={id}&part={part}"))
+                .header(header::COOKIE, &cookie)
+                .body(body)
+                .send()
+        };
+        let exceeded = |response: reqwest::Response| async move {
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+            serde_json::from_slice::<PayloadTooLarge>(&response.bytes().await.unwrap())
+                .unwrap()
+                .limit
+        };
+
+        let first = create().await?.error_for_status()?.text().await?;
+
+        put_part(first.clone(), 0, b"abc")
+            .await?
+            .error_for_status()?;
+        let response = put_part(first.clone(), 1, b"def").await?;
+        assert_eq!(exceeded(response).await, Limit::EntrySize);
+
+        // Replacing a part doesn't count its previous content.
+        put_part(first.clone(), 1, b"de")
+            .await?
+            .error_for_status()?;
+        put_part(first.clone(), 1, b"ef")
+            .await?
+            .error_for_status()?;
+
+        // Other sessions count against the quota.
+        let second = create().await?.error_for_status()?.text().await?;
+
+        let response = put_part(second.clone(), 0, b"abcd").await?;
+        assert_eq!(exceeded(response).await, Limit::StorageQuota);
+        put_part(second.clone(), 0, b"abc")
+            .await?
+            .error_for_status()?;
+
+        let response = put_part(second, MAX_UPLOAD_PARTS, b"").await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        for _ in 2..MAX_UPLOAD_SESSIONS {
+            create().await?.error_for_status()?;
+        }
+        assert_eq!(create().await?.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        Ok(())
+    }
+}
@@ -1,21 +1,44 @@
-use std::{future::IntoFuture, net::SocketAddr};
+use std::{
+    future::IntoFuture,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
 
-use anyhow::{Error, Result};
-use pubky_common::auth::AuthVerifier;
-use tokio::{net::TcpListener, signal, task::JoinSet};
+use anyhow::{anyhow, Error, Result};
+use pubky_common::{auth::AuthVerifier, timestamp::Timestamp};
+use tokio::{net::TcpListener, signal, sync::watch, task::JoinSet};
 use tracing::{debug, info, warn};
 
 use pkarr::{
     mainline::dht::{DhtSettings, Testnet},
-    PkarrClient, PkarrClientAsync, PublicKey, Settings,
+    PkarrClient, PkarrClientAsync, PublicKey, Settings, SignedPacket,
 };
 
-use crate::{config::Config, database::DB, pkarr::publish_server_packet};
+use crate::{
+    config::{Config, ListenAddress},
+    database::DB,
+    listener::{self, shutdown_requested, Listener},
+    metrics::Metrics,
+    pkarr::{
+        endpoint_params, publish_redirect_packet, publish_server_packet, server_packet, Published,
+        Resolver,
+    },
+    rate_limiter::{Budget, RateLimiter},
+    tls::{self, reload_certificates},
+};
 
 #[derive(Debug)]
 pub struct Homeserver {
     state: AppState,
+    /// Tasks serving requests, completing after a shutdown is requested.
+    servers: JoinSet<std::io::Result<()>>,
+    /// Background tasks, aborted once the servers complete.
     tasks: JoinSet<std::io::Result<()>>,
+    /// Set to request a graceful shutdown.
+    shutdown: watch::Sender<bool>,
+    listen_addresses: Vec<ListenAddress>,
+    metrics_address: Option<SocketAddr>,
 }
 
 #[derive(Clone, Debug)]
@@ -23,12 +46,92 @@ pub(crate) struct AppState {
     pub(crate) verifier: AuthVerifier,
     pub(crate) db: DB,
     pub(crate) pkarr_client: PkarrClientAsync,
+    /// Resolves the server's own packet before republishing it.
+    pub(crate) pkarr_resolver: Resolver,
     pub(crate) config: Config,
     pub(crate) port: u16,
+    pub(crate) rate_limiter: RateLimiter,
+    pub(crate) metrics: Metrics,
+    /// Last time the server's pkarr packet was successfully published.
+    pub(crate) last_published: Arc<RwLock<Option<Timestamp>>>,
+    /// Last result of resolving the server's own packet for readiness probes,
+    /// and when it was checked.
+    pub(crate) last_resolved: Arc<tokio::sync::Mutex<Option<(Instant, bool)>>>,
+}
+
+impl AppState {
+    pub(crate) fn last_published(&self) -> Option<Timestamp> {
+        *self
+            .last_published
+            .read()
+            .expect("last_published lock poisoned")
+    }
+
+    /// Publish the server's pkarr packet, see [publish_server_packet],
+    /// and record the result.
+    pub(crate) async fn publish_server_packet(
+        &self,
+        own: Option<&SignedPacket>,
+    ) -> Result<Published> {
+        let domain = self.config.domain().as_deref().unwrap_or("localhost");
+
+        // Without TLS, any domain other than localhost is assumed to point
+        // to a reverse proxy at the conventional ports.
+        let port = self.config.public_port().or_else(|| {
+            (self.port != 0 && (domain == "localhost" || self.config.tls().is_some()))
+                .then_some(self.port)
+        });
+
+        let signed_packet = server_packet(
+            self.config.keypair(),
+            domain,
+            port,
+            self.config.pkarr_ttl(),
+            &endpoint_params(&self.config),
+        )?;
+
+        let result =
+            publish_server_packet(&self.pkarr_client, &self.pkarr_resolver, signed_packet, own)
+                .await;
+
+        match &result {
+            Ok(Published::Own(_)) => {
+                self.metrics.pkarr_published(true);
+
+                *self
+                    .last_published
+                    .write()
+                    .expect("last_published lock poisoned") = Some(Timestamp::now());
+            }
+            Ok(Published::Adopted(_)) => self.metrics.pkarr_adopted(),
+            Err(_) => self.metrics.pkarr_published(false),
+        }
+
+        result
+    }
+
+    /// Publish the packets of [Config::previous_keypairs], redirecting to the current key.
+    pub(crate) async fn publish_redirect_packets(&self) -> Result<()> {
+        let current = self.config.keypair().public_key();
+
+        for previous in self.config.previous_keypairs() {
+            publish_redirect_packet(
+                &self.pkarr_client,
+                previous,
+                &current,
+                self.config.pkarr_ttl(),
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
 }
 
 impl Homeserver {
-    pub async fn start(config: Config) -> Result<Self> {
+    pub async fn start(mut config: Config) -> Result<Self> {
+        config.load_or_persist_secret_key()?;
+
         debug!(?config);
 
         let db = DB::open(config.clone())?;
@@ -42,53 +145,143 @@ impl Homeserver {
             ..Default::default()
         })?
         .as_async();
+        let pkarr_resolver = Resolver {
+            bootstrap: config.bootstsrap(),
+            request_timeout: config.dht_request_timeout(),
+        };
 
+        let mut servers = JoinSet::new();
         let mut tasks = JoinSet::new();
 
-        let listener = TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], config.port()))).await?;
+        let (shutdown, _) = watch::channel(false);
 
-        let port = listener.local_addr()?.port();
+        tasks.spawn({
+            let shutdown = shutdown.clone();
+
+            async move {
+                shutdown_signal().await;
+                shutdown.send_replace(true);
+
+                Ok(())
+            }
+        });
+
+        let mut listeners = Vec::new();
+        for address in config.listen() {
+            let listener = Listener::bind(&address)
+                .await
+                .map_err(|error| anyhow!("failed to listen on {address}: {error}"))?;
+
+            listeners.push((listener.local_address()?, listener));
+        }
+
+        let listen_addresses: Vec<ListenAddress> = listeners
+            .iter()
+            .map(|(address, _)| address.clone())
+            .collect();
+
+        // Port of the first TCP listener.
+        let port = listen_addresses
+            .iter()
+            .find_map(|address| match address {
+                ListenAddress::Tcp(address) => Some(address.port()),
+                ListenAddress::Unix(_) => None,
+            })
+            .unwrap_or_default();
 
         let state = AppState {
-            verifier: AuthVerifier::default(),
+            verifier: AuthVerifier::new(db.clone()),
             db,
             pkarr_client,
+            pkarr_resolver,
             config: config.clone(),
             port,
+            rate_limiter: RateLimiter::new(config.rate_limits().clone()),
+            metrics: Metrics::new(),
+            last_published: Arc::new(RwLock::new(None)),
+            last_resolved: Arc::new(tokio::sync::Mutex::new(None)),
         };
 
         let app = crate::routes::create_app(state.clone());
 
-        // Spawn http server task
-        tasks.spawn(
-            axum::serve(
+        let acceptor = match config.tls() {
+            Some(tls) => {
+                let (acceptor, resolver) = tls::acceptor(tls)?;
+
+                tasks.spawn(reload_certificates(
+                    resolver,
+                    tls.cert.clone(),
+                    tls.key.clone(),
+                    tls.reload_interval,
+                ));
+
+                Some(acceptor)
+            }
+            None => None,
+        };
+
+        let scheme = if acceptor.is_some() { "https" } else { "http" };
+
+        // Spawn server tasks
+        for (address, listener) in listeners {
+            servers.spawn(listener::serve(
                 listener,
-                app.into_make_service_with_connect_info::<SocketAddr>(),
-            )
-            .with_graceful_shutdown(shutdown_signal())
-            .into_future(),
-        );
+                acceptor.clone(),
+                app.clone(),
+                shutdown.subscribe(),
+            ));
 
-        info!("Homeserver listening on http://localhost:{port}");
+            info!("Homeserver listening on {address} ({scheme})");
+        }
 
-        publish_server_packet(
-            &state.pkarr_client,
-            config.keypair(),
-            &state
-                .config
-                .domain()
-                .clone()
-                .unwrap_or("localhost".to_string()),
-            port,
-        )
-        .await?;
+        let metrics_address = match config.metrics_listen() {
+            Some(address) => {
+                let listener = TcpListener::bind(address).await?;
+                let address = listener.local_addr()?;
+
+                let mut shutdown = shutdown.subscribe();
+
+                servers.spawn(
+                    axum::serve(listener, crate::metrics::router(state.clone()))
+                        .with_graceful_shutdown(
+                            async move { shutdown_requested(&mut shutdown).await },
+                        )
+                        .into_future(),
+                );
+
+                info!("Metrics available at http://{address}/metrics");
+
+                Some(address)
+            }
+            None => None,
+        };
+
+        tasks.spawn(remove_abandoned_uploads(
+            state.db.clone(),
+            config.upload_session_ttl(),
+        ));
+
+        let own = match state.publish_server_packet(None).await? {
+            Published::Own(signed_packet) => Some(signed_packet),
+            Published::Adopted(_) => None,
+        };
+        state.publish_redirect_packets().await?;
+
+        tasks.spawn(republish_server_packet(state.clone(), own));
 
         info!(
             "Homeserver listening on pubky://{}",
             config.keypair().public_key()
         );
 
-        Ok(Self { tasks, state })
+        Ok(Self {
+            servers,
+            tasks,
+            shutdown,
+            state,
+            listen_addresses,
+            metrics_address,
+        })
     }
 
     /// Test version of [Homeserver::start], using mainline Testnet, and a temporary storage.
@@ -100,14 +293,39 @@ impl Homeserver {
 
     // === Getters ===
 
+    /// The port of the first TCP listener, or `0` if only listening on Unix sockets.
     pub fn port(&self) -> u16 {
         self.state.port
     }
 
+    /// The addresses the server is listening on, see [Config::listen].
+    pub fn listen_addresses(&self) -> &[ListenAddress] {
+        &self.listen_addresses
+    }
+
     pub fn public_key(&self) -> PublicKey {
         self.state.config.keypair().public_key()
     }
 
+    /// Number of requests rejected by rate limits since the server started,
+    /// for each budget (`auth`, `write`, `read` and `events`).
+    pub fn rate_limited_requests(&self) -> Vec<(&'static str, u64)> {
+        Budget::ALL
+            .iter()
+            .map(|budget| (budget.as_str(), self.state.rate_limiter.rejections(*budget)))
+            .collect()
+    }
+
+    /// Last time the server's pkarr packet was successfully published, if ever.
+    pub fn last_published(&self) -> Option<Timestamp> {
+        self.state.last_published()
+    }
+
+    /// The address serving Prometheus metrics, if [Config::metrics_listen] is set.
+    pub fn metrics_address(&self) -> Option<SocketAddr> {
+        self.metrics_address
+    }
+
     #[cfg(test)]
     pub(crate) fn database_mut(&mut self) -> &mut DB {
         &mut self.state.db
@@ -115,37 +333,174 @@ impl Homeserver {
 
     // === Public Methods ===
 
-    /// Shutdown the server and wait for all tasks to complete.
-    pub async fn shutdown(mut self) -> Result<()> {
-        self.tasks.abort_all();
-        self.run_until_done().await?;
-        Ok(())
+    /// Shutdown the server immediately, aborting in-flight requests.
+    pub async fn shutdown(self) -> Result<()> {
+        self.shutdown_graceful(Duration::ZERO).await
     }
 
-    /// Wait for all tasks to complete.
+    /// Stop accepting connections, and wait up to `timeout` for in-flight requests,
+    /// including uploads, to complete, before aborting the remaining ones.
+    pub async fn shutdown_graceful(mut self, timeout: Duration) -> Result<()> {
+        self.shutdown.send_replace(true);
+
+        self.drain(timeout).await
+    }
+
+    /// Wait until a shutdown is requested with a `SIGINT` or `SIGTERM` signal,
+    /// then shutdown gracefully, see [Homeserver::shutdown_graceful] and [Config::shutdown_timeout].
     ///
     /// Runs forever unless tasks fail.
     pub async fn run_until_done(mut self) -> Result<()> {
-        let mut final_res: Result<()> = Ok(());
-        while let Some(res) = self.tasks.join_next().await {
-            match res {
-                Ok(Ok(())) => {}
-                Err(err) if err.is_cancelled() => {}
-                Ok(Err(err)) => {
-                    warn!(?err, "task failed");
-                    final_res = Err(Error::from(err));
+        let mut shutdown = self.shutdown.subscribe();
+
+        // Servers only complete after a shutdown request, unless they fail.
+        let result = tokio::select! {
+            _ = shutdown_requested(&mut shutdown) => Ok(()),
+            Some(result) = self.servers.join_next() => join_result(result),
+        };
+
+        self.shutdown.send_replace(true);
+
+        let timeout = self.state.config.shutdown_timeout();
+        let drained = self.drain(timeout).await;
+
+        result.and(drained)
+    }
+
+    async fn drain(&mut self, timeout: Duration) -> Result<()> {
+        let result = match tokio::time::timeout(timeout, join_all(&mut self.servers)).await {
+            Ok(result) => result,
+            Err(_) => {
+                if !timeout.is_zero() {
+                    warn!(
+                        ?timeout,
+                        "Aborting requests still in-flight after the shutdown timeout"
+                    );
                 }
-                Err(err) => {
-                    warn!(?err, "task panicked");
-                    final_res = Err(err.into());
+
+                self.servers.abort_all();
+                join_all(&mut self.servers).await
+            }
+        };
+
+        self.tasks.abort_all();
+
+        result.and(join_all(&mut self.tasks).await)
+    }
+}
+
+/// Wait for all `tasks` to complete, returning the last error, if any.
+async fn join_all(tasks: &mut JoinSet<std::io::Result<()>>) -> Result<()> {
+    let mut final_res: Result<()> = Ok(());
+
+    while let Some(res) = tasks.join_next().await {
+        if let Err(err) = join_result(res) {
+            final_res = Err(err);
+        }
+    }
+
+    final_res
+}
+
+fn join_result(res: Result<std::io::Result<()>, tokio::task::JoinError>) -> Result<()> {
+    match res {
+        Ok(Ok(())) => Ok(()),
+        Err(err) if err.is_cancelled() => Ok(()),
+        Ok(Err(err)) => {
+            warn!(?err, "task failed");
+            Err(Error::from(err))
+        }
+        Err(err) => {
+            warn!(?err, "task panicked");
+            Err(err.into())
+        }
+    }
+}
+
+/// Delay before retrying a failed publishing of the server's pkarr packet,
+/// doubled for every consecutive failure, up to the republish interval.
+const REPUBLISH_RETRY_BASE_DELAY: Duration = Duration::from_secs(5);
+
+/// Periodically republish the server's pkarr packet, to keep it alive in the DHT.
+async fn republish_server_packet(
+    state: AppState,
+    mut own: Option<SignedPacket>,
+) -> std::io::Result<()> {
+    let interval = state.config.pkarr_republish_interval();
+
+    let mut failures = 0;
+
+    loop {
+        let delay = if failures == 0 {
+            interval
+        } else {
+            REPUBLISH_RETRY_BASE_DELAY
+                .saturating_mul(2_u32.saturating_pow(failures - 1))
+                .min(interval)
+        };
+
+        tokio::time::sleep(delay).await;
+
+        let result = match state.publish_server_packet(own.as_ref()).await {
+            Ok(published) => {
+                match published {
+                    Published::Own(signed_packet) => own = Some(signed_packet),
+                    // Keep comparing with our own packet, to not republish ours
+                    // over the adopted one on the next tick.
+                    Published::Adopted(signed_packet) => {
+                        debug!(timestamp = signed_packet.timestamp(), "Adopted packet")
+                    }
                 }
+                state.publish_redirect_packets().await
+            }
+            Err(error) => Err(error),
+        };
+
+        match result {
+            Ok(()) => {
+                debug!("Republished the server's pkarr packet");
+
+                failures = 0;
+            }
+            Err(error) => {
+                failures += 1;
+
+                warn!(
+                    ?error,
+                    ?failures,
+                    "Failed to republish the server's pkarr packet"
+                );
             }
         }
-        final_res
     }
 }
 
-async fn shutdown_signal() {
+/// How often to look for abandoned upload sessions.
+const UPLOADS_CLEANUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Periodically remove upload sessions that exceeded their `ttl`.
+async fn remove_abandoned_uploads(db: DB, ttl: Duration) -> std::io::Result<()> {
+    let mut interval = tokio::time::interval(
+        UPLOADS_CLEANUP_INTERVAL
+            .min(ttl)
+            .max(Duration::from_secs(1)),
+    );
+
+    loop {
+        interval.tick().await;
+
+        let db = db.clone();
+
+        match tokio::task::spawn_blocking(move || db.remove_abandoned_uploads(ttl)).await {
+            Ok(Ok(0)) => {}
+            Ok(Ok(count)) => debug!(?count, "Removed abandoned upload sessions"),
+            Ok(Err(error)) => warn!(?error, "Failed to remove abandoned upload sessions"),
+            Err(error) => warn!(?error, "Upload sessions cleanup task panicked"),
+        }
+    }
+}
+
+pub(crate) async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
             .await
@@ -172,3 +527,275 @@ async fn shutdown_signal() {
         _ = terminate => graceful_shutdown(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{entry_buffers, signup, start_partial_put, wait_until};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn republish_server_packet() -> Result<()> {
+        let testnet = Testnet::new(10);
+
+        let config = Config::try_from_str(&format!(
+            r#"
+            testnet = true
+            bootstrap = {:?}
+            storage = {:?}
+            pkarr_republish_interval = {{ secs = 0, nanos = 100000000 }}
+            "#,
+            testnet.bootstrap,
+            Config::test(&testnet).storage(),
+        ))?;
+
+        let server = Homeserver::start(config).await?;
+
+        let first = server.last_published().unwrap();
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        assert!(server.last_published().unwrap() > first);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn republish_with_another_instance() -> Result<()> {
+        use pkarr::dns::rdata::RData;
+
+        let testnet = Testnet::new(10);
+        let keypair = pkarr::Keypair::random();
+
+        let config = |domain: &str| {
+            Config::try_from_str(&format!(
+                r#"
+                bootstrap = {:?}
+                storage = {:?}
+                domain = "{domain}"
+                secret_key = "{}"
+                pkarr_republish_interval = {{ secs = 0, nanos = 300000000 }}
+                "#,
+                testnet.bootstrap,
+                Config::test(&testnet).storage(),
+                hex::encode(keypair.secret_key()),
+            ))
+        };
+
+        let first = Homeserver::start(config("first.example.com")?).await?;
+        let first_published = first.last_published().unwrap();
+
+        // The last instance to start takes over the packet.
+        let second = Homeserver::start(config("second.example.com")?).await?;
+
+        let resolver = Resolver {
+            bootstrap: Some(testnet.bootstrap.clone()),
+            request_timeout: None,
+        };
+
+        for _ in 0..5 {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+
+            let signed_packet = resolver.resolve(&keypair.public_key()).await?.unwrap();
+            let targets = signed_packet
+                .resource_records("@")
+                .filter_map(|record| match &record.rdata {
+                    RData::SVCB(svcb) => Some(svcb.target.to_string()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(targets, vec!["second.example.com"]);
+        }
+
+        // The first instance only republished the second one's packet.
+        assert_eq!(first.last_published(), Some(first_published));
+        assert!(second.last_published().unwrap() > first_published);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn listen_addresses() -> Result<()> {
+        use pkarr::dns::rdata::{RData, SVCB};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let testnet = Testnet::new(10);
+        let storage = Config::test(&testnet).storage().clone();
+        std::fs::create_dir_all(&storage)?;
+
+        let socket = storage.join("homeserver.sock");
+
+        let config = Config::try_from_str(&format!(
+            r#"
+            bootstrap = {:?}
+            storage = {storage:?}
+            listen = ["127.0.0.1:0", "[::1]:0", "unix:{}"]
+            public_port = 8443
+            "#,
+            testnet.bootstrap,
+            socket.display(),
+        ))?;
+
+        let server = Homeserver::start(config).await?;
+
+        let addresses = server.listen_addresses().to_vec();
+        assert_eq!(addresses.len(), 3);
+        assert_eq!(addresses[2], ListenAddress::Unix(socket.clone()));
+
+        for address in &addresses[..2] {
+            let ListenAddress::Tcp(address) = address else {
+                panic!("expected a TCP address, got {address}")
+            };
+            assert_ne!(address.port(), 0);
+
+            let response = reqwest::get(format!("http://{address}/health")).await?;
+            assert_eq!(response.status(), 200);
+        }
+
+        let mut stream = tokio::net::UnixStream::connect(&socket).await?;
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+
+        // The public port is announced instead of the bound ones.
+        let client = PkarrClient::builder().testnet(&testnet).build()?.as_async();
+        let signed_packet = client.resolve(&server.public_key()).await?.unwrap();
+        let port = signed_packet
+            .resource_records("@")
+            .find_map(|record| match &record.rdata {
+                RData::SVCB(svcb) => svcb.get_param(SVCB::PORT).map(|p| p.to_vec()),
+                _ => None,
+            });
+        assert_eq!(port, Some(8443_u16.to_be_bytes().to_vec()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn redirect_previous_keys() -> Result<()> {
+        use pkarr::dns::rdata::RData;
+
+        use crate::config::write_secret_key_file;
+
+        let testnet = Testnet::new(10);
+        let storage = Config::test(&testnet).storage().clone();
+        std::fs::create_dir_all(&storage)?;
+
+        let current = pkarr::Keypair::random();
+        let previous = pkarr::Keypair::random();
+        write_secret_key_file(&storage.join("current"), &current)?;
+        write_secret_key_file(&storage.join("previous"), &previous)?;
+
+        let config = Config::try_from_str(&format!(
+            r#"
+            bootstrap = {:?}
+            storage = {storage:?}
+            secret_key_file = {:?}
+            previous_secret_key_files = [{:?}]
+            "#,
+            testnet.bootstrap,
+            storage.join("current"),
+            storage.join("previous"),
+        ))?;
+
+        let server = Homeserver::start(config).await?;
+        assert_eq!(server.public_key(), current.public_key());
+
+        let client = PkarrClient::builder().testnet(&testnet).build()?.as_async();
+        let signed_packet = client.resolve(&previous.public_key()).await?.unwrap();
+
+        let target = signed_packet
+            .resource_records("@")
+            .find_map(|record| match &record.rdata {
+                RData::SVCB(svcb) if svcb.priority == 0 => Some(svcb.target.to_string()),
+                _ => None,
+            });
+        assert_eq!(target, Some(current.public_key().to_string()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shutdown_graceful_drains_uploads() -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let testnet = Testnet::new(3);
+        let config = Config::test(&testnet);
+        let buffers_dir = config.storage().join("buffers");
+
+        let mut server = Homeserver::start(config).await?;
+        let db = server.database_mut().clone();
+        let port = server.port();
+
+        let keypair = pkarr::Keypair::random();
+        let pubky = keypair.public_key();
+
+        let cookie = signup(&server, &keypair).await?;
+        let mut stream = start_partial_put(&server, &pubky, &cookie, &buffers_dir).await?;
+
+        let shutdown = tokio::spawn(server.shutdown_graceful(Duration::from_secs(10)));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // No new connections.
+        assert!(tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .is_err());
+
+        // The in-flight upload completes.
+        stream.write_all(b"def").await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+
+        shutdown.await??;
+
+        let txn = db.env.read_txn()?;
+        let entry = db.get_entry(&txn, &pubky, "pub/foo")?.unwrap();
+        assert_eq!(entry.content_length(), 6);
+
+        assert!(wait_until(|| entry_buffers(&buffers_dir) == 0).await);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shutdown_graceful_timeout() -> Result<()> {
+        use tokio::io::AsyncReadExt;
+
+        let testnet = Testnet::new(3);
+        let config = Config::test(&testnet);
+        let buffers_dir = config.storage().join("buffers");
+
+        let mut server = Homeserver::start(config).await?;
+        let db = server.database_mut().clone();
+
+        let keypair = pkarr::Keypair::random();
+        let pubky = keypair.public_key();
+
+        let cookie = signup(&server, &keypair).await?;
+        let mut stream = start_partial_put(&server, &pubky, &cookie, &buffers_dir).await?;
+
+        // The upload never completes.
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            server.shutdown_graceful(Duration::from_millis(200)),
+        )
+        .await??;
+
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response).await;
+        assert!(!response.starts_with("HTTP/1.1 200 OK"), "{response}");
+
+        let txn = db.env.read_txn()?;
+        assert!(db.get_entry(&txn, &pubky, "pub/foo")?.is_none());
+
+        assert!(wait_until(|| entry_buffers(&buffers_dir) == 0).await);
+
+        Ok(())
+    }
+}
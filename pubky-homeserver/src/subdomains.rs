@@ -0,0 +1,347 @@
+//! Serving users at `<pubky>.<domain>`, see [Config::subdomains](crate::config::Config::subdomains).
+//!
+//! Giving each user its own origin isolates their data in browsers, and since session
+//! cookies are host-only, scopes sessions to that subdomain too.
+
+use axum::{
+    extract::{Request, State},
+    http::{
+        header,
+        uri::{Authority, PathAndQuery},
+        HeaderValue, Method, StatusCode, Uri,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use pkarr::PublicKey;
+
+use crate::{error::Error, server::AppState};
+
+/// The pubky of the subdomain a request was addressed to.
+#[derive(Debug, Clone)]
+pub struct Subdomain(pub PublicKey);
+
+/// Rewrite requests to `<pubky>.<domain>/path` into `/<pubky>/path`, so they are served
+/// by the same handlers as path based requests, except for server wide routes
+/// like `/signup` or `/events/`, and insert the [Subdomain] in the request extensions.
+///
+/// Path based requests to `<domain>/<pubky>/path` are redirected to the subdomain instead,
+/// so users' data is only served on their own origin.
+///
+/// Must wrap the router, as layers added with [axum::Router::layer] run after routing.
+pub async fn middleware(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let domain = state.config.domain().as_deref().unwrap_or("localhost");
+
+    if let Some(pubky) = host(&request).and_then(|host| pubky_of(host, domain)) {
+        if !is_server_route(request.method(), request.uri().path()) {
+            if let Some(uri) = rewrite(request.uri(), &pubky) {
+                *request.uri_mut() = uri;
+            }
+        }
+
+        request.extensions_mut().insert(Subdomain(pubky));
+    } else if let Some((pubky, path)) = path_pubky(request.uri().path()) {
+        let location = host(&request)
+            .and_then(|host| subdomain_location(host, domain, &pubky, path, request.uri().query()));
+
+        return match location {
+            Some(location) => (
+                StatusCode::PERMANENT_REDIRECT,
+                [(header::LOCATION, location)],
+            )
+                .into_response(),
+            None => Error::new(
+                StatusCode::MISDIRECTED_REQUEST,
+                Some(format!("Use {pubky}.{domain}")),
+            )
+            .into_response(),
+        };
+    }
+
+    next.run(request).await
+}
+
+/// The `Host` header, or the authority of the uri for HTTP/2 requests.
+fn host(request: &Request) -> Option<&str> {
+    request
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| request.uri().authority().map(Authority::as_str))
+}
+
+/// The pubky in the first label of `host`, if the rest is `domain`.
+fn pubky_of(host: &str, domain: &str) -> Option<PublicKey> {
+    let authority = host.parse::<Authority>().ok()?;
+
+    let (label, rest) = authority.host().split_once('.')?;
+
+    if !rest.eq_ignore_ascii_case(domain.trim_end_matches('.')) {
+        return None;
+    }
+
+    PublicKey::try_from(label).ok()
+}
+
+/// The pubky in the first segment of a path based request, and the rest of the path.
+fn path_pubky(path: &str) -> Option<(PublicKey, &str)> {
+    let path = path.strip_prefix('/')?;
+    let (segment, rest) = match path.find('/') {
+        Some(index) => path.split_at(index),
+        None => (path, "/"),
+    };
+
+    Some((PublicKey::try_from(segment).ok()?, rest))
+}
+
+/// Location of a path based request on the subdomain of `pubky`, relative to the
+/// scheme of the request, if it was addressed to `domain`.
+fn subdomain_location(
+    host: &str,
+    domain: &str,
+    pubky: &PublicKey,
+    path: &str,
+    query: Option<&str>,
+) -> Option<HeaderValue> {
+    let authority = host.parse::<Authority>().ok()?;
+
+    if !authority
+        .host()
+        .eq_ignore_ascii_case(domain.trim_end_matches('.'))
+    {
+        return None;
+    }
+
+    let location = match query {
+        Some(query) => format!("//{pubky}.{authority}{path}?{query}"),
+        None => format!("//{pubky}.{authority}{path}"),
+    };
+
+    HeaderValue::try_from(location).ok()
+}
+
+/// Routes that are not about a specific user.
+fn is_server_route(method: &Method, path: &str) -> bool {
+    matches!(path, "/health" | "/ready" | "/signup")
+        || (method == Method::POST && path == "/session")
+        || path.starts_with("/events/")
+        || path == "/pkarr"
+        || path.starts_with("/pkarr/")
+}
+
+fn rewrite(uri: &Uri, pubky: &PublicKey) -> Option<Uri> {
+    let path_and_query = match uri.query() {
+        Some(query) => format!("/{pubky}{}?{query}", uri.path()),
+        None => format!("/{pubky}{}", uri.path()),
+    };
+
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(PathAndQuery::try_from(path_and_query).ok()?);
+
+    Uri::from_parts(parts).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use pkarr::{mainline::Testnet, Keypair};
+    use pubky_common::{auth::AuthToken, capabilities::Capability};
+    use reqwest::StatusCode;
+
+    use crate::{config::Config, test_utils::session_cookie, Homeserver};
+
+    use super::*;
+
+    #[test]
+    fn pubky_of_host() {
+        let pubky = Keypair::random().public_key();
+
+        assert_eq!(
+            pubky_of(&format!("{pubky}.localhost:6287"), "localhost"),
+            Some(pubky.clone())
+        );
+        assert_eq!(
+            pubky_of(&format!("{pubky}.Example.com"), "example.com"),
+            Some(pubky.clone())
+        );
+        assert_eq!(pubky_of(&format!("{pubky}.example.com"), "localhost"), None);
+        assert_eq!(
+            pubky_of(&format!("{pubky}.localhost"), "evil.localhost"),
+            None
+        );
+        assert_eq!(pubky_of("www.localhost", "localhost"), None);
+        assert_eq!(pubky_of("localhost:6287", "localhost"), None);
+        assert_eq!(pubky_of(&pubky.to_string(), "localhost"), None);
+    }
+
+    #[test]
+    fn rewrite_uri() {
+        let pubky = Keypair::random().public_key();
+
+        assert_eq!(
+            rewrite(&"/pub/foo?limit=1".parse().unwrap(), &pubky).unwrap(),
+            format!("/{pubky}/pub/foo?limit=1").as_str()
+        );
+        assert_eq!(
+            rewrite(&"/session".parse().unwrap(), &pubky).unwrap(),
+            format!("/{pubky}/session").as_str()
+        );
+
+        assert_eq!(
+            path_pubky(&format!("/{pubky}/pub/foo")),
+            Some((pubky.clone(), "/pub/foo"))
+        );
+        assert_eq!(path_pubky(&format!("/{pubky}")), Some((pubky.clone(), "/")));
+        assert_eq!(path_pubky("/events/"), None);
+
+        assert_eq!(
+            subdomain_location(
+                "localhost:6287",
+                "localhost",
+                &pubky,
+                "/pub/foo",
+                Some("limit=1")
+            )
+            .unwrap(),
+            format!("//{pubky}.localhost:6287/pub/foo?limit=1").as_str()
+        );
+        assert_eq!(
+            subdomain_location("127.0.0.1:6287", "localhost", &pubky, "/pub/foo", None),
+            None
+        );
+
+        assert!(is_server_route(&Method::POST, "/session"));
+        assert!(!is_server_route(&Method::GET, "/session"));
+        assert!(is_server_route(&Method::GET, "/events/"));
+        assert!(!is_server_route(&Method::GET, "/pub/events/"));
+    }
+
+    #[tokio::test]
+    async fn serve_subdomains() -> anyhow::Result<()> {
+        let testnet = Testnet::new(3);
+
+        let config = Config::try_from_str(&format!(
+            r#"
+            testnet = true
+            bootstrap = {:?}
+            storage = {:?}
+            subdomains = true
+            "#,
+            testnet.bootstrap,
+            Config::test(&testnet).storage(),
+        ))?;
+
+        let server = Homeserver::start(config).await?;
+
+        let keypair = Keypair::random();
+        let pubky = keypair.public_key();
+
+        let client = reqwest::Client::new();
+        let url = |path: &str| format!("http://localhost:{}{path}", server.port());
+        let subdomain = format!("{pubky}.localhost:{}", server.port());
+
+        // Signin on the user's subdomain.
+        let response = client
+            .post(url("/signup"))
+            .header(header::HOST, &subdomain)
+            .body(AuthToken::sign(&keypair, vec![Capability::root()]).serialize())
+            .send()
+            .await?
+            .error_for_status()?;
+
+        // Host-only cookie, not shared with other subdomains.
+        assert!(!response.headers()[header::SET_COOKIE]
+            .to_str()?
+            .to_lowercase()
+            .contains("domain="));
+
+        let cookie = session_cookie(&response);
+
+        client
+            .get(url("/session"))
+            .header(header::HOST, &subdomain)
+            .header(header::COOKIE, &cookie)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        client
+            .put(url("/pub/foo"))
+            .header(header::HOST, &subdomain)
+            .header(header::COOKIE, &cookie)
+            .body(vec![1, 2, 3])
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let response = client
+            .get(url("/pub/foo"))
+            .header(header::HOST, &subdomain)
+            .send()
+            .await?;
+        assert_eq!(response.bytes().await?.as_ref(), &[1, 2, 3]);
+
+        // Path based requests are redirected to the subdomain.
+        let no_redirect = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+
+        let response = no_redirect
+            .get(url(&format!("/{pubky}/pub/foo")))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            response.headers().get(header::LOCATION).unwrap(),
+            format!("//{subdomain}/pub/foo").as_str()
+        );
+
+        let response = no_redirect
+            .get(url(&format!("/{pubky}/pub/foo")))
+            .header(header::HOST, format!("127.0.0.1:{}", server.port()))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::MISDIRECTED_REQUEST);
+
+        // Server wide routes are still served on subdomains.
+        let response = client
+            .get(url("/health"))
+            .header(header::HOST, &subdomain)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Signing in as another user on this subdomain is rejected.
+        let response = client
+            .post(url("/session"))
+            .header(header::HOST, &subdomain)
+            .body(AuthToken::sign(&Keypair::random(), vec![Capability::root()]).serialize())
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn subdomains_disabled() -> anyhow::Result<()> {
+        let testnet = Testnet::new(3);
+        let server = Homeserver::start_test(&testnet).await?;
+
+        let pubky = Keypair::random().public_key();
+
+        let response = reqwest::Client::new()
+            .get(format!("http://localhost:{}/pub/foo", server.port()))
+            .header(header::HOST, format!("{pubky}.localhost"))
+            .send()
+            .await?;
+
+        // Routed as `/:pubky/*path` with `pub` as an invalid pubky.
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+//! Helpers shared by the tests of multiple modules.
+
+use std::{path::Path, time::Duration};
+
+use anyhow::{anyhow, Result};
+use pkarr::{Keypair, PublicKey};
+use pubky_common::{auth::AuthToken, capabilities::Capability};
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+
+use crate::Homeserver;
+
+/// The `name=value` pair of the session cookie set by a signup or signin `response`.
+pub fn session_cookie(response: &reqwest::Response) -> String {
+    response
+        .headers()
+        .get(reqwest::header::SET_COOKIE)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.split(';').next())
+        .unwrap()
+        .to_string()
+}
+
+/// Signup `keypair` with root capabilities, returning its session cookie.
+pub async fn signup(server: &Homeserver, keypair: &Keypair) -> Result<String> {
+    let response = reqwest::Client::new()
+        .post(format!("http://localhost:{}/signup", server.port()))
+        .body(AuthToken::sign(keypair, vec![Capability::root()]).serialize())
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(session_cookie(&response))
+}
+
+/// Start writing a 6 bytes entry at `pub/foo` with only its first 3 bytes,
+/// and wait for its buffer to be created in `buffers_dir`.
+pub async fn start_partial_put(
+    server: &Homeserver,
+    pubky: &PublicKey,
+    cookie: &str,
+    buffers_dir: &Path,
+) -> Result<TcpStream> {
+    let mut stream = TcpStream::connect(("127.0.0.1", server.port())).await?;
+    stream
+        .write_all(
+            format!(
+                "PUT /{pubky}/pub/foo HTTP/1.1\r\nHost: localhost\r\nCookie: {cookie}\r\nContent-Length: 6\r\n\r\nabc"
+            )
+            .as_bytes(),
+        )
+        .await?;
+
+    if !wait_until(|| entry_buffers(buffers_dir) > 0).await {
+        return Err(anyhow!("upload did not start"));
+    }
+
+    Ok(stream)
+}
+
+/// Number of entry buffers in `buffers_dir`, excluding upload sessions.
+pub fn entry_buffers(buffers_dir: &Path) -> usize {
+    std::fs::read_dir(buffers_dir)
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().file_type().unwrap().is_file())
+        .count()
+}
+
+/// Poll `condition` for up to 5 seconds, returning whether it became true.
+pub async fn wait_until(mut condition: impl FnMut() -> bool) -> bool {
+    for _ in 0..100 {
+        if condition() {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    false
+}
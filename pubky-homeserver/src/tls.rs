@@ -0,0 +1,211 @@
+//! Serving HTTPS directly, see [Config::tls](crate::config::Config::tls).
+
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Context, Result};
+use tokio_rustls::{
+    rustls::{
+        crypto::ring::{default_provider, sign},
+        server::{ClientHello, ResolvesServerCert},
+        sign::CertifiedKey,
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
+use tracing::{info, warn};
+
+use crate::config::Tls;
+
+/// Resolves the certificate of every handshake, replaced when the files are reloaded.
+#[derive(Debug)]
+pub(crate) struct CertResolver(RwLock<Arc<CertifiedKey>>);
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.read().expect("CertResolver lock poisoned").clone())
+    }
+}
+
+/// Build the [TlsAcceptor] of `tls`, returning its [CertResolver] to [reload_certificates].
+pub(crate) fn acceptor(tls: &Tls) -> Result<(TlsAcceptor, Arc<CertResolver>)> {
+    let certified_key = load_certified_key(&tls.cert, &tls.key)?;
+
+    let resolver = Arc::new(CertResolver(RwLock::new(Arc::new(certified_key))));
+
+    let mut config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver.clone());
+
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok((TlsAcceptor::from(Arc::new(config)), resolver))
+}
+
+/// Periodically check the certificate and key files, and reload them if they changed.
+///
+/// Files that fail to load, for example while being renewed, are retried at the next
+/// check, and the previous certificate is served in the meantime.
+pub(crate) async fn reload_certificates(
+    resolver: Arc<CertResolver>,
+    cert: PathBuf,
+    key: PathBuf,
+    interval: Duration,
+) -> std::io::Result<()> {
+    let mut loaded = modified_times(&cert, &key);
+
+    let mut interval = tokio::time::interval(interval);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        let modified = modified_times(&cert, &key);
+        if modified == loaded {
+            continue;
+        }
+
+        match load_certified_key(&cert, &key) {
+            Ok(certified_key) => {
+                *resolver.0.write().expect("CertResolver lock poisoned") = Arc::new(certified_key);
+                loaded = modified;
+
+                info!(?cert, "Reloaded TLS certificate");
+            }
+            Err(error) => warn!(?error, "Failed to reload TLS certificate"),
+        }
+    }
+}
+
+fn modified_times(cert: &Path, key: &Path) -> [Option<SystemTime>; 2] {
+    [cert, key].map(|path| path.metadata().and_then(|m| m.modified()).ok())
+}
+
+fn load_certified_key(cert: &Path, key: &Path) -> Result<CertifiedKey> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .with_context(|| format!("failed to read {}", path.display()))
+    };
+
+    let certs = rustls_pemfile::certs(&mut open(cert)?).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(anyhow!("no certificate found in {}", cert.display()));
+    }
+
+    let private_key = rustls_pemfile::private_key(&mut open(key)?)?
+        .ok_or_else(|| anyhow!("no private key found in {}", key.display()))?;
+
+    Ok(CertifiedKey::new(
+        certs,
+        sign::any_supported_type(&private_key)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+    };
+
+    use pkarr::mainline::Testnet;
+    use tokio_rustls::rustls::{
+        pki_types::{CertificateDer, ServerName},
+        ClientConfig, ClientConnection, RootCertStore, StreamOwned,
+    };
+
+    use crate::{config::Config, Homeserver};
+
+    use super::*;
+
+    /// GET `/health` over TLS, trusting only the self-signed `cert`.
+    fn get_health(port: u16, cert: CertificateDer<'static>) -> Result<String> {
+        let mut roots = RootCertStore::empty();
+        roots.add(cert)?;
+
+        let config = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        let connection =
+            ClientConnection::new(Arc::new(config), ServerName::try_from("localhost")?)?;
+        let mut stream = StreamOwned::new(connection, TcpStream::connect(("localhost", port))?);
+
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")?;
+
+        let mut response = String::new();
+        // Servers may close without close_notify.
+        let _ = stream.read_to_string(&mut response);
+
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn reload_certificate_files() -> anyhow::Result<()> {
+        let testnet = Testnet::new(3);
+        let storage = Config::test(&testnet).storage().clone();
+
+        std::fs::create_dir_all(&storage)?;
+        let cert = storage.join("cert.pem");
+        let key = storage.join("key.pem");
+
+        // Write a new self-signed certificate, and return it.
+        let write_files = || -> anyhow::Result<CertificateDer<'static>> {
+            let certified_key = rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
+
+            std::fs::write(&cert, certified_key.cert.pem())?;
+            std::fs::write(&key, certified_key.key_pair.serialize_pem())?;
+
+            Ok(certified_key.cert.der().clone())
+        };
+
+        let first = write_files()?;
+
+        let config = Config::try_from_str(&format!(
+            r#"
+            bootstrap = {:?}
+            storage = {:?}
+
+            [tls]
+            cert = {cert:?}
+            key = {key:?}
+            reload_interval = {{ secs = 0, nanos = 50000000 }}
+            "#,
+            testnet.bootstrap, storage,
+        ))?;
+
+        let server = Homeserver::start(config).await?;
+        let port = server.port();
+
+        let response = tokio::task::spawn_blocking(move || get_health(port, first)).await??;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+
+        // Renewed certificate.
+        let second = write_files()?;
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        let trusted = second.clone();
+        let response = tokio::task::spawn_blocking(move || get_health(port, trusted)).await??;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+
+        // Invalid files keep the previous certificate.
+        std::fs::write(&cert, "invalid")?;
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        let response = tokio::task::spawn_blocking(move || get_health(port, second)).await??;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+
+        Ok(())
+    }
+}
@@ -349,3 +349,49 @@ test('list shallow', async (t) => {
     );
   }
 })
+
+test('listStream', async (t) => {
+  const client = PubkyClient.testnet();
+
+  const keypair = Keypair.random()
+  const publicKey = keypair.publicKey()
+  const pubky = publicKey.z32()
+
+  await client.signup(keypair, Homeserver)
+
+  let urls = []
+  for (let i = 0; i < 150; i++) {
+    urls.push(`pubky://${pubky}/pub/example.com/${String(i).padStart(3, '0')}.txt`)
+  }
+
+  for (let url of urls) {
+    await client.put(url, Buffer.from("a"));
+  }
+
+  let url = `pubky://${pubky}/pub/example.com/`;
+
+  {
+    let stream = client.listStream(url);
+
+    let list = []
+    let entry;
+    while ((entry = await stream.next()) !== undefined) {
+      t.equal(entry.content_length, 1)
+      list.push(entry.url)
+    }
+
+    t.deepEqual(list, urls, "stream follows cursors across pages")
+  }
+
+  {
+    let stream = client.listStream(url, true, false, 3);
+
+    let list = []
+    let entry;
+    while ((entry = await stream.next()) !== undefined) {
+      list.push(entry.url)
+    }
+
+    t.deepEqual(list, urls.reverse().slice(0, 3), "reverse stream with max items")
+  }
+})
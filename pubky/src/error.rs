@@ -1,6 +1,8 @@
 //! Main Crate Error
 
 use pkarr::dns::SimpleDnsError;
+use pubky_common::limits::PayloadTooLarge;
+use reqwest::{Response, StatusCode};
 
 // Alias Result to be the crate Result.
 pub type Result<T, E = Error> = core::result::Result<T, E>;
@@ -18,6 +20,10 @@ pub enum Error {
     #[error("Could not convert the passed type into a Url")]
     InvalidUrl,
 
+    /// The homeserver rejected a request for exceeding one of its limits.
+    #[error("{0}")]
+    PayloadTooLarge(PayloadTooLarge),
+
     // === Transparent ===
     #[error(transparent)]
     Dns(#[from] SimpleDnsError),
@@ -31,6 +37,9 @@ pub enum Error {
     #[error(transparent)]
     Reqwest(#[from] reqwest::Error),
 
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
     #[error(transparent)]
     Session(#[from] pubky_common::session::Error),
 
@@ -42,6 +51,27 @@ pub enum Error {
 
     #[error(transparent)]
     AuthToken(#[from] pubky_common::auth::Error),
+
+    #[error(transparent)]
+    Path(#[from] pubky_common::path::Error),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Like [Response::error_for_status], but returns [Error::PayloadTooLarge]
+/// for `413 Payload Too Large` responses with a [PayloadTooLarge] body.
+pub(crate) async fn error_for_status(response: Response) -> Result<Response> {
+    if response.status() != StatusCode::PAYLOAD_TOO_LARGE {
+        return Ok(response.error_for_status()?);
+    }
+
+    let error = response.error_for_status_ref().unwrap_err();
+
+    match serde_json::from_slice(&response.bytes().await?) {
+        Ok(payload_too_large) => Err(Error::PayloadTooLarge(payload_too_large)),
+        Err(_) => Err(error.into()),
+    }
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -1,6 +1,9 @@
 #![doc = include_str!("../README.md")]
 //!
 
+// `pkarr::Error` is large, boxing it would be a breaking change to [Error].
+#![allow(clippy::result_large_err)]
+
 mod error;
 mod shared;
 
@@ -21,15 +24,24 @@ use wasm_bindgen::prelude::*;
 use ::pkarr::PkarrClientAsync;
 
 pub use error::Error;
+pub use pubky_common::{
+    endpoint::EndpointParams,
+    list::{DirectoryStat, ListEntry, ListResponse},
+    upload::UploadStatus,
+};
 
+pub use crate::shared::endpoint_cache::EndpointCacheStats;
 #[cfg(not(target_arch = "wasm32"))]
-pub use crate::shared::list_builder::ListBuilder;
+pub use crate::shared::{list_builder::ListBuilder, upload_builder::UploadBuilder};
+
+use crate::shared::endpoint_cache::EndpointCache;
 
 /// A client for Pubky homeserver API, as well as generic HTTP requests to Pubky urls.
 #[derive(Debug, Clone)]
 #[wasm_bindgen]
 pub struct PubkyClient {
     http: reqwest::Client,
+    pub(crate) endpoint_cache: EndpointCache,
     #[cfg(not(target_arch = "wasm32"))]
     pub(crate) pkarr: PkarrClientAsync,
     /// A cookie jar for nodejs fetch.
@@ -1,14 +1,20 @@
-use std::net::ToSocketAddrs;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
 use std::time::Duration;
 
 use bytes::Bytes;
 use pubky_common::{
     capabilities::Capabilities,
+    endpoint::EndpointParams,
+    list::DirectoryStat,
     recovery_file::{create_recovery_file, decrypt_recovery_file},
     session::Session,
 };
-use reqwest::{RequestBuilder, Response};
-use tokio::sync::oneshot;
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    RequestBuilder, Response,
+};
+use tokio::{sync::oneshot, task::JoinHandle};
 use url::Url;
 
 use pkarr::{mainline::MutableItem, Keypair, PkarrClientAsync};
@@ -17,8 +23,11 @@ use ::pkarr::{mainline::dht::Testnet, PkarrClient, PublicKey, SignedPacket};
 
 use crate::{
     error::{Error, Result},
-    shared::list_builder::ListBuilder,
-    PubkyClient,
+    shared::{
+        list_builder::ListBuilder, pkarr::DEFAULT_REPUBLISH_THRESHOLD,
+        upload_builder::UploadBuilder,
+    },
+    EndpointCacheStats, PubkyClient,
 };
 
 static DEFAULT_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
@@ -66,14 +75,47 @@ impl PubkyClientBuilder {
         PubkyClient {
             http: reqwest::Client::builder()
                 .cookie_store(true)
+                .dns_resolver(Arc::new(LocalhostResolver))
                 .user_agent(DEFAULT_USER_AGENT)
                 .build()
                 .unwrap(),
+            endpoint_cache: Default::default(),
             pkarr: PkarrClient::new(self.pkarr_settings).unwrap().as_async(),
         }
     }
 }
 
+/// Resolves subdomains of `localhost` to the loopback addresses, as specified by
+/// [RFC 6761](https://www.rfc-editor.org/rfc/rfc6761#section-6.3) but not done by every
+/// system resolver, for local homeservers serving pubky subdomains.
+///
+/// Other names are resolved by the system resolver.
+struct LocalhostResolver;
+
+impl Resolve for LocalhostResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let name = name.as_str().to_string();
+
+        Box::pin(async move {
+            if name.to_ascii_lowercase().ends_with(".localhost") {
+                let addrs: Addrs = Box::new(
+                    [
+                        SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+                        SocketAddr::from((Ipv6Addr::LOCALHOST, 0)),
+                    ]
+                    .into_iter(),
+                );
+
+                return Ok(addrs);
+            }
+
+            let addrs: Addrs = Box::new(tokio::net::lookup_host((name, 0)).await?);
+
+            Ok(addrs)
+        })
+    }
+}
+
 impl Default for PubkyClient {
     fn default() -> Self {
         PubkyClient::builder().build()
@@ -119,6 +161,11 @@ impl PubkyClient {
         &self.pkarr
     }
 
+    /// Returns the hits and misses of the cache of resolved homeserver endpoints.
+    pub fn endpoint_cache_stats(&self) -> EndpointCacheStats {
+        self.endpoint_cache.stats()
+    }
+
     // === Auth ===
 
     /// Signup to a homeserver and update Pkarr accordingly.
@@ -143,10 +190,61 @@ impl PubkyClient {
     }
 
     /// Signin to a homeserver.
+    ///
+    /// Also republishes the `_pubky` record in the background if it is older than an hour,
+    /// see [Self::republish_homeserver].
     pub async fn signin(&self, keypair: &Keypair) -> Result<Session> {
         self.inner_signin(keypair).await
     }
 
+    /// Republish the `_pubky` record pointing to the `host` homeserver,
+    /// to keep it alive in the DHT, while preserving other records in the Pkarr packet.
+    ///
+    /// Skipped if the current record already points to `host` and was published
+    /// less than an hour ago.
+    ///
+    /// Returns `true` if a new packet was published.
+    pub async fn republish_homeserver(&self, keypair: &Keypair, host: &PublicKey) -> Result<bool> {
+        self.inner_republish_homeserver(keypair, host, DEFAULT_REPUBLISH_THRESHOLD)
+            .await
+    }
+
+    /// Resolve the parameters announced by the homeserver of `pubky`,
+    /// like its supported versions, features and maximum upload size.
+    pub async fn homeserver_params(&self, pubky: &PublicKey) -> Result<EndpointParams> {
+        Ok(self.resolve_pubky_homeserver(pubky).await?.params)
+    }
+
+    /// Spawn a task calling [Self::republish_homeserver] every `interval`,
+    /// until the returned handle is aborted.
+    ///
+    /// Each tick skips a record that is still fresh, so `interval` only sets how
+    /// often to check. Failures are logged as warnings and retried on the next tick.
+    pub fn republish_homeserver_periodically(
+        &self,
+        keypair: Keypair,
+        host: PublicKey,
+        interval: Duration,
+    ) -> JoinHandle<()> {
+        let client = self.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+
+            loop {
+                ticker.tick().await;
+
+                if let Err(error) = client.republish_homeserver(&keypair, &host).await {
+                    tracing::warn!(
+                        ?error,
+                        pubky = %keypair.public_key(),
+                        "Failed to republish homeserver"
+                    );
+                }
+            }
+        })
+    }
+
     // === Public data ===
 
     /// Upload a small payload to a given path.
@@ -164,13 +262,54 @@ impl PubkyClient {
         self.inner_delete(url).await
     }
 
+    /// Delete every file under a directory relative to a pubky author.
+    ///
+    /// Returns the number of deleted files.
+    pub async fn delete_recursive<T: TryInto<Url>>(&self, url: T) -> Result<usize> {
+        self.inner_delete_recursive(url).await
+    }
+
+    /// Get the total size, files count and last modification time
+    /// of a directory relative to a pubky author, recursively.
+    ///
+    /// Returns `None` if the directory is empty or doesn't exist.
+    pub async fn stat<T: TryInto<Url>>(&self, url: T) -> Result<Option<DirectoryStat>> {
+        self.inner_stat(url).await
+    }
+
+    /// Copy a file, or a directory (url ending with `/`) recursively,
+    /// to another path of the same pubky, without re-uploading its content.
+    pub async fn copy<T: TryInto<Url>, U: TryInto<Url>>(
+        &self,
+        source: T,
+        destination: U,
+    ) -> Result<()> {
+        self.inner_copy(source, destination, false).await
+    }
+
+    /// Move a file, or a directory (url ending with `/`) recursively,
+    /// to another path of the same pubky, without re-uploading its content.
+    pub async fn rename<T: TryInto<Url>, U: TryInto<Url>>(
+        &self,
+        source: T,
+        destination: U,
+    ) -> Result<()> {
+        self.inner_copy(source, destination, true).await
+    }
+
     /// Returns a [ListBuilder] to help pass options before calling [ListBuilder::send].
     ///
     /// `url` sets the path you want to lest within.
-    pub fn list<T: TryInto<Url>>(&self, url: T) -> Result<ListBuilder> {
+    pub fn list<T: TryInto<Url>>(&self, url: T) -> Result<ListBuilder<'_>> {
         self.inner_list(url)
     }
 
+    /// Returns an [UploadBuilder] to upload a large payload in parts,
+    /// resuming and retrying failed parts, with [UploadBuilder::send].
+    pub fn upload<T: TryInto<Url>>(&self, url: T) -> Result<UploadBuilder<'_>> {
+        self.inner_upload(url)
+    }
+
     // === Helpers ===
 
     /// Create a recovery file of the `keypair`, containing the secret key encrypted
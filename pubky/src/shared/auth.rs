@@ -13,11 +13,11 @@ use pubky_common::{
 };
 
 use crate::{
-    error::{Error, Result},
+    error::{error_for_status, Error, Result},
     PubkyClient,
 };
 
-use super::pkarr::Endpoint;
+use super::pkarr::{pubky_target, spawn, PublishStrategy, DEFAULT_REPUBLISH_THRESHOLD};
 
 impl PubkyClient {
     /// Signup to a homeserver and update Pkarr accordingly.
@@ -31,21 +31,29 @@ impl PubkyClient {
     ) -> Result<Session> {
         let homeserver = homeserver.to_string();
 
-        let Endpoint { mut url, .. } = self.resolve_endpoint(&homeserver).await?;
+        // Signup on the user's subdomain if supported, to scope the session cookie to it.
+        let mut url = self
+            .resolve_endpoint(&homeserver)
+            .await?
+            .pubky_url(&keypair.public_key());
 
         url.set_path("/signup");
 
         let body = AuthToken::sign(keypair, vec![Capability::root()]).serialize();
 
         let response = self
-            .request(Method::POST, url.clone())
-            .body(body)
-            .send()
+            .send(
+                &homeserver,
+                self.request(Method::POST, url.clone()).body(body),
+            )
             .await?;
 
+        let response = error_for_status(response).await?;
+
         self.store_session(&response);
 
-        self.publish_pubky_homeserver(keypair, &homeserver).await?;
+        self.publish_pubky_homeserver(keypair, Some(&homeserver), PublishStrategy::Force)
+            .await?;
 
         let bytes = response.bytes().await?;
 
@@ -57,11 +65,16 @@ impl PubkyClient {
     /// Returns None  if not signed in, or [reqwest::Error]
     /// if the response has any other `>=404` status code.
     pub(crate) async fn inner_session(&self, pubky: &PublicKey) -> Result<Option<Session>> {
-        let Endpoint { mut url, .. } = self.resolve_pubky_homeserver(pubky).await?;
+        let mut url = self.resolve_pubky_homeserver(pubky).await?.pubky_url(pubky);
 
-        url.set_path(&format!("/{}/session", pubky));
+        url.path_segments_mut()
+            .expect("http(s) url")
+            .pop_if_empty()
+            .push("session");
 
-        let res = self.request(Method::GET, url).send().await?;
+        let res = self
+            .send(&pubky_target(pubky), self.request(Method::GET, url))
+            .await?;
 
         if res.status() == StatusCode::NOT_FOUND {
             return Ok(None);
@@ -78,11 +91,15 @@ impl PubkyClient {
 
     /// Signout from a homeserver.
     pub(crate) async fn inner_signout(&self, pubky: &PublicKey) -> Result<()> {
-        let Endpoint { mut url, .. } = self.resolve_pubky_homeserver(pubky).await?;
+        let mut url = self.resolve_pubky_homeserver(pubky).await?.pubky_url(pubky);
 
-        url.set_path(&format!("/{}/session", pubky));
+        url.path_segments_mut()
+            .expect("http(s) url")
+            .pop_if_empty()
+            .push("session");
 
-        self.request(Method::DELETE, url).send().await?;
+        self.send(&pubky_target(pubky), self.request(Method::DELETE, url))
+            .await?;
 
         self.remove_session(pubky);
 
@@ -93,7 +110,30 @@ impl PubkyClient {
     pub(crate) async fn inner_signin(&self, keypair: &Keypair) -> Result<Session> {
         let token = AuthToken::sign(keypair, vec![Capability::root()]);
 
-        self.signin_with_authtoken(&token).await
+        let session = self.signin_with_authtoken(&token).await?;
+
+        // Best effort refresh of a stale `_pubky` record in the background,
+        // so signin neither waits for the DHT nor fails with it, only logging failures.
+        let client = self.clone();
+        let keypair = keypair.clone();
+        spawn(async move {
+            if let Err(error) = client
+                .publish_pubky_homeserver(
+                    &keypair,
+                    None,
+                    PublishStrategy::IfOlderThan(DEFAULT_REPUBLISH_THRESHOLD),
+                )
+                .await
+            {
+                tracing::warn!(
+                    ?error,
+                    pubky = %keypair.public_key(),
+                    "Failed to republish homeserver"
+                );
+            }
+        });
+
+        Ok(session)
     }
 
     pub(crate) async fn inner_send_auth_token(
@@ -157,11 +197,14 @@ impl PubkyClient {
         self.resolve_url(&mut url).await?;
 
         let response = self
-            .request(Method::POST, url)
-            .body(token.serialize())
-            .send()
+            .send(
+                &pubky_target(token.pubky()),
+                self.request(Method::POST, url).body(token.serialize()),
+            )
             .await?;
 
+        let response = error_for_status(response).await?;
+
         self.store_session(&response);
 
         let bytes = response.bytes().await?;
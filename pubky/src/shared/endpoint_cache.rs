@@ -0,0 +1,516 @@
+//! In memory cache of resolved [Endpoint]s, honoring the TTL of their records,
+//! and failing over to the next candidate endpoint when one is unreachable.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+
+use reqwest::{Method, RequestBuilder, Response};
+use url::Url;
+
+use crate::{error::Result, PubkyClient};
+
+use super::pkarr::Endpoint;
+
+/// Minimum time to cache a resolved endpoint, regardless of its records' TTL.
+const MIN_TTL: Duration = Duration::from_secs(30);
+/// Maximum time to cache a resolved endpoint, regardless of its records' TTL.
+const MAX_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// How long to cache a failed resolution.
+const NEGATIVE_TTL: Duration = Duration::from_secs(30);
+/// How long an expired endpoint can still be used, while it is revalidated in the background.
+const STALE_WHILE_REVALIDATE: Duration = Duration::from_secs(10 * 60);
+
+/// Hits and misses of the endpoint cache, since the client was created.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct EndpointCacheStats {
+    /// Lookups answered by a fresh endpoint.
+    pub hits: u64,
+    /// Lookups answered by an expired endpoint, while revalidating it.
+    pub stale_hits: u64,
+    /// Lookups answered by a cached failed resolution.
+    pub negative_hits: u64,
+    /// Lookups that had to resolve the endpoint.
+    pub misses: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// Candidate endpoints in the order they should be tried,
+    /// empty for a failed resolution.
+    endpoints: Vec<Endpoint>,
+    /// Timestamp in microseconds.
+    expires_at: u64,
+    revalidating: bool,
+}
+
+/// Result of an [EndpointCache] lookup.
+#[derive(Debug)]
+pub(crate) enum Lookup {
+    Fresh(Endpoint),
+    /// An expired endpoint, that should be revalidated if `revalidate` is true,
+    /// which is only the case for the first lookup after expiry.
+    Stale {
+        endpoint: Endpoint,
+        revalidate: bool,
+    },
+    NotFound,
+    Miss,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: RwLock<HashMap<String, Entry>>,
+    hits: AtomicU64,
+    stale_hits: AtomicU64,
+    negative_hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Cache of endpoints resolved by [PubkyClient::resolve_endpoint], by target.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct EndpointCache(Arc<Inner>);
+
+impl EndpointCache {
+    pub fn get(&self, target: &str, now: u64) -> Lookup {
+        let mut entries = self.0.entries.write().expect("EndpointCache lock poisoned");
+
+        let Some(entry) = entries.get_mut(target) else {
+            self.0.misses.fetch_add(1, Ordering::Relaxed);
+            return Lookup::Miss;
+        };
+
+        match entry.endpoints.first() {
+            Some(endpoint) if now < entry.expires_at => {
+                self.0.hits.fetch_add(1, Ordering::Relaxed);
+                Lookup::Fresh(endpoint.clone())
+            }
+            Some(endpoint) if now < entry.expires_at + micros(STALE_WHILE_REVALIDATE) => {
+                self.0.stale_hits.fetch_add(1, Ordering::Relaxed);
+
+                let revalidate = !entry.revalidating;
+                entry.revalidating = true;
+
+                Lookup::Stale {
+                    endpoint: endpoint.clone(),
+                    revalidate,
+                }
+            }
+            None if now < entry.expires_at => {
+                self.0.negative_hits.fetch_add(1, Ordering::Relaxed);
+                Lookup::NotFound
+            }
+            _ => {
+                entries.remove(target);
+
+                self.0.misses.fetch_add(1, Ordering::Relaxed);
+                Lookup::Miss
+            }
+        }
+    }
+
+    /// Cache the candidate `endpoints` resolved for `target`, for `ttl` seconds,
+    /// or a failed resolution if `None`.
+    ///
+    /// A failed resolution doesn't replace a stale endpoint, that is used until
+    /// it is too old, or invalidated.
+    pub fn insert(&self, target: &str, endpoints: Option<(Vec<Endpoint>, u32)>, now: u64) {
+        let mut entries = self.0.entries.write().expect("EndpointCache lock poisoned");
+
+        let entry = match endpoints {
+            Some((endpoints, ttl)) => Entry {
+                endpoints,
+                expires_at: now + micros(Duration::from_secs(ttl.into()).clamp(MIN_TTL, MAX_TTL)),
+                revalidating: false,
+            },
+            None => {
+                if let Some(stale) = entries
+                    .get_mut(target)
+                    .filter(|entry| !entry.endpoints.is_empty())
+                {
+                    stale.revalidating = false;
+                    return;
+                }
+
+                Entry {
+                    endpoints: vec![],
+                    expires_at: now + micros(NEGATIVE_TTL),
+                    revalidating: false,
+                }
+            }
+        };
+
+        entries.insert(target.to_string(), entry);
+    }
+
+    pub fn invalidate(&self, target: &str) {
+        self.0
+            .entries
+            .write()
+            .expect("EndpointCache lock poisoned")
+            .remove(target);
+    }
+
+    /// Drop the current endpoint of `target` if it is the origin of this `url`,
+    /// or one of its pubky subdomains, and return the next candidate, if any.
+    ///
+    /// If `url` points to a pubky subdomain, the next candidate is only returned if it also
+    /// serves pubky subdomains, pointing to the same one.
+    ///
+    /// Other targets using the same endpoint are left alone, until they fail themselves.
+    /// The target is removed if it has no candidates left.
+    pub fn failover(&self, target: &str, url: &Url) -> Option<Url> {
+        let mut entries = self.0.entries.write().expect("EndpointCache lock poisoned");
+
+        let entry = entries.get_mut(target)?;
+
+        let current = entry.endpoints.first()?;
+        let subdomain = if current.url.origin() == url.origin() {
+            None
+        } else {
+            Some(current.subdomain_of(url)?)
+        };
+
+        entry.endpoints.remove(0);
+
+        let Some(candidate) = entry.endpoints.first() else {
+            entries.remove(target);
+            return None;
+        };
+
+        match &subdomain {
+            Some(pubky) if candidate.uses_subdomains() => Some(candidate.pubky_url(pubky)),
+            Some(_) => None,
+            None => Some(candidate.url.clone()),
+        }
+    }
+
+    pub fn stats(&self) -> EndpointCacheStats {
+        EndpointCacheStats {
+            hits: self.0.hits.load(Ordering::Relaxed),
+            stale_hits: self.0.stale_hits.load(Ordering::Relaxed),
+            negative_hits: self.0.negative_hits.load(Ordering::Relaxed),
+            misses: self.0.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn micros(duration: Duration) -> u64 {
+    duration.as_micros() as u64
+}
+
+impl PubkyClient {
+    /// Send a request to an endpoint resolved from `target`, failing over to its next
+    /// candidate endpoint if the current one is unreachable, see [should_failover].
+    pub(crate) async fn send(&self, target: &str, request: RequestBuilder) -> Result<Response> {
+        let (http, request) = request.build_split();
+        let mut request = request?;
+
+        loop {
+            let retry = request.try_clone();
+            let method = request.method().clone();
+
+            let error = match http.execute(request).await {
+                Ok(response) => return Ok(response),
+                Err(error) => error,
+            };
+
+            let next = match error.url() {
+                Some(url) if should_failover(&error, &method) => {
+                    self.endpoint_cache.failover(target, url)
+                }
+                _ => None,
+            };
+
+            match (retry, next) {
+                (Some(mut retry), Some(next)) => {
+                    let url = retry.url_mut();
+
+                    url.set_scheme(next.scheme()).expect("http(s) scheme");
+                    url.set_host(next.host_str())?;
+                    url.set_port(next.port()).expect("url with a host");
+
+                    request = retry;
+                }
+                _ => return Err(error.into()),
+            }
+        }
+    }
+}
+
+/// Whether the endpoint failed to respond, and the request can be safely sent again
+/// to the next candidate.
+///
+/// Connection errors happen before the request is sent, but a timed out request
+/// may have reached the endpoint, so only idempotent requests are retried then.
+#[cfg(not(target_arch = "wasm32"))]
+fn should_failover(error: &reqwest::Error, method: &Method) -> bool {
+    error.is_connect() || (error.is_timeout() && method.is_idempotent())
+}
+
+/// Whether the endpoint failed to respond, and the request can be safely sent again
+/// to the next candidate.
+///
+/// `fetch` fails the same way whether or not the request reached the endpoint,
+/// so only idempotent requests are retried.
+#[cfg(target_arch = "wasm32")]
+fn should_failover(error: &reqwest::Error, method: &Method) -> bool {
+    (error.is_request() || error.is_timeout()) && method.is_idempotent()
+}
+
+#[cfg(test)]
+mod tests {
+    use pkarr::{mainline::Testnet, Keypair};
+    use pubky_common::{
+        endpoint::{EndpointParams, SUBDOMAINS_FEATURE},
+        timestamp::Timestamp,
+    };
+    use pubky_homeserver::Homeserver;
+
+    use super::*;
+
+    const SECOND: u64 = 1_000_000;
+
+    fn endpoint(url: &str) -> Endpoint {
+        Endpoint {
+            url: Url::parse(url).unwrap(),
+            params: EndpointParams::default(),
+        }
+    }
+
+    #[test]
+    fn ttl_and_stale_while_revalidate() {
+        let cache = EndpointCache::default();
+
+        assert!(matches!(cache.get("foo", 0), Lookup::Miss));
+
+        cache.insert("foo", Some((vec![endpoint("https://example.com")], 60)), 0);
+
+        assert!(matches!(cache.get("foo", 59 * SECOND), Lookup::Fresh(_)));
+
+        // Only the first stale lookup revalidates.
+        assert!(matches!(
+            cache.get("foo", 60 * SECOND),
+            Lookup::Stale {
+                revalidate: true,
+                ..
+            }
+        ));
+        assert!(matches!(
+            cache.get("foo", 61 * SECOND),
+            Lookup::Stale {
+                revalidate: false,
+                ..
+            }
+        ));
+
+        // Failed revalidation keeps the stale endpoint.
+        cache.insert("foo", None, 62 * SECOND);
+        assert!(matches!(
+            cache.get("foo", 63 * SECOND),
+            Lookup::Stale {
+                revalidate: true,
+                ..
+            }
+        ));
+
+        let too_old = 60 * SECOND + micros(STALE_WHILE_REVALIDATE);
+        assert!(matches!(cache.get("foo", too_old), Lookup::Miss));
+
+        assert_eq!(
+            cache.stats(),
+            EndpointCacheStats {
+                hits: 1,
+                stale_hits: 3,
+                negative_hits: 0,
+                misses: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn clamp_ttl() {
+        let cache = EndpointCache::default();
+
+        cache.insert("foo", Some((vec![endpoint("https://example.com")], 0)), 0);
+        assert!(matches!(
+            cache.get("foo", micros(MIN_TTL) - 1),
+            Lookup::Fresh(_)
+        ));
+
+        cache.insert(
+            "bar",
+            Some((vec![endpoint("https://example.com")], u32::MAX)),
+            0,
+        );
+        assert!(matches!(
+            cache.get("bar", micros(MAX_TTL)),
+            Lookup::Stale { .. }
+        ));
+    }
+
+    #[test]
+    fn negative_caching() {
+        let cache = EndpointCache::default();
+
+        cache.insert("foo", None, 0);
+
+        assert!(matches!(cache.get("foo", 0), Lookup::NotFound));
+        assert!(matches!(
+            cache.get("foo", micros(NEGATIVE_TTL)),
+            Lookup::Miss
+        ));
+
+        assert_eq!(cache.stats().negative_hits, 1);
+    }
+
+    #[test]
+    fn invalidate() {
+        let cache = EndpointCache::default();
+
+        cache.insert(
+            "foo",
+            Some((vec![endpoint("http://localhost:6287")], 60)),
+            0,
+        );
+        cache.insert(
+            "bar",
+            Some((vec![endpoint("http://localhost:6287")], 60)),
+            0,
+        );
+        cache.insert(
+            "baz",
+            Some((vec![endpoint("http://localhost:6288")], 60)),
+            0,
+        );
+
+        cache.invalidate("foo");
+        assert!(matches!(cache.get("foo", 0), Lookup::Miss));
+
+        let failed = Url::parse("http://localhost:6287/pub/foo").unwrap();
+        assert_eq!(cache.failover("bar", &failed), None);
+        assert!(matches!(cache.get("bar", 0), Lookup::Miss));
+        assert!(matches!(cache.get("baz", 0), Lookup::Fresh(_)));
+    }
+
+    #[test]
+    fn failover() {
+        let cache = EndpointCache::default();
+
+        let candidates = vec![
+            endpoint("http://localhost:6287"),
+            endpoint("http://localhost:6288"),
+        ];
+        cache.insert("foo", Some((candidates.clone(), 60)), 0);
+        cache.insert("bar", Some((candidates, 60)), 0);
+
+        let failed = Url::parse("http://localhost:6287/pub/foo").unwrap();
+        assert_eq!(
+            cache.failover("foo", &failed),
+            Some(Url::parse("http://localhost:6288").unwrap())
+        );
+
+        match cache.get("foo", 0) {
+            Lookup::Fresh(endpoint) => assert_eq!(endpoint.url.port(), Some(6288)),
+            lookup => panic!("unexpected {lookup:?}"),
+        }
+
+        // Other targets keep using the same endpoint until they fail themselves.
+        match cache.get("bar", 0) {
+            Lookup::Fresh(endpoint) => assert_eq!(endpoint.url.port(), Some(6287)),
+            lookup => panic!("unexpected {lookup:?}"),
+        }
+
+        // Failing an origin no longer in use has no effect.
+        assert_eq!(cache.failover("foo", &failed), None);
+        assert!(matches!(cache.get("foo", 0), Lookup::Fresh(_)));
+
+        let failed = Url::parse("http://localhost:6288/pub/foo").unwrap();
+        assert_eq!(cache.failover("foo", &failed), None);
+        assert!(matches!(cache.get("foo", 0), Lookup::Miss));
+    }
+
+    #[test]
+    fn failover_subdomain() {
+        let cache = EndpointCache::default();
+        let pubky = Keypair::random().public_key();
+
+        let subdomains = |url: &str| Endpoint {
+            params: EndpointParams {
+                features: vec![SUBDOMAINS_FEATURE.to_string()],
+                ..Default::default()
+            },
+            ..endpoint(url)
+        };
+
+        let candidates = vec![
+            subdomains("https://a.example.com"),
+            subdomains("https://b.example.com"),
+            endpoint("https://c.example.com"),
+        ];
+        cache.insert("foo", Some((candidates, 60)), 0);
+
+        let failed = Url::parse(&format!("https://{pubky}.a.example.com/pub/foo")).unwrap();
+        assert_eq!(
+            cache.failover("foo", &failed),
+            Some(Url::parse(&format!("https://{pubky}.b.example.com/")).unwrap())
+        );
+
+        // The next candidate doesn't serve subdomains.
+        let failed = Url::parse(&format!("https://{pubky}.b.example.com/pub/foo")).unwrap();
+        assert_eq!(cache.failover("foo", &failed), None);
+
+        match cache.get("foo", 0) {
+            Lookup::Fresh(endpoint) => assert_eq!(endpoint.url.host_str(), Some("c.example.com")),
+            lookup => panic!("unexpected {lookup:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn cache_resolved_endpoints() {
+        let testnet = Testnet::new(10);
+        let server = Homeserver::start_test(&testnet).await.unwrap();
+
+        let client = PubkyClient::test(&testnet);
+
+        let keypair = Keypair::random();
+
+        client.signup(&keypair, &server.public_key()).await.unwrap();
+
+        let url = format!("pubky://{}/pub/foo.txt", keypair.public_key());
+
+        client.put(url.as_str(), &[0]).await.unwrap();
+
+        let before = client.endpoint_cache_stats();
+        client.get(url.as_str()).await.unwrap();
+        assert_eq!(client.endpoint_cache_stats().hits, before.hits + 1);
+
+        // Unknown pubkys are cached too.
+        let unknown = format!("pubky://{}/pub/foo.txt", Keypair::random().public_key());
+        assert!(client.get(unknown.as_str()).await.is_err());
+        assert!(client.get(unknown.as_str()).await.is_err());
+        assert_eq!(client.endpoint_cache_stats().negative_hits, 1);
+
+        // Connection errors invalidate the cached endpoint.
+        let target = format!("_pubky.{}", keypair.public_key());
+        let unreachable = (vec![endpoint("http://localhost:1")], 60);
+        client
+            .endpoint_cache
+            .insert(&target, Some(unreachable), Timestamp::now().as_u64());
+
+        assert!(client.get(url.as_str()).await.is_err());
+        assert!(matches!(
+            client
+                .endpoint_cache
+                .get(&target, Timestamp::now().as_u64()),
+            Lookup::Miss
+        ));
+
+        assert_eq!(client.get(url.as_str()).await.unwrap().unwrap(), vec![0]);
+    }
+}
@@ -1,4 +1,8 @@
-use reqwest::Method;
+use std::collections::VecDeque;
+
+use futures_util::{stream, Stream};
+use pubky_common::list::{ListEntry, ListResponse};
+use reqwest::{header, Method};
 use url::Url;
 
 use crate::{error::Result, PubkyClient};
@@ -12,6 +16,7 @@ pub struct ListBuilder<'a> {
     cursor: Option<&'a str>,
     client: &'a PubkyClient,
     shallow: bool,
+    max_items: Option<usize>,
 }
 
 impl<'a> ListBuilder<'a> {
@@ -24,6 +29,7 @@ impl<'a> ListBuilder<'a> {
             cursor: None,
             reverse: false,
             shallow: false,
+            max_items: None,
         }
     }
 
@@ -53,13 +59,122 @@ impl<'a> ListBuilder<'a> {
         self
     }
 
+    /// Set the maximum number of entries returned by [ListBuilder::stream],
+    /// regardless of how many pages it takes.
+    pub fn max_items(mut self, max_items: usize) -> Self {
+        self.max_items = max_items.into();
+        self
+    }
+
     /// Send the list request.
     ///
     /// Returns a list of Pubky URLs of the files in the path of the `url`
     /// respecting [ListBuilder::reverse], [ListBuilder::limit] and [ListBuilder::cursor]
     /// options.
     pub async fn send(self) -> Result<Vec<String>> {
-        let mut url = self.client.pubky_to_http(self.url).await?;
+        let (target, url) = self.request_url().await?;
+
+        let response = self
+            .client
+            .send(&target, self.client.request(Method::GET, url))
+            .await?;
+
+        response.error_for_status_ref()?;
+
+        // TODO: bail on too large files.
+        let bytes = response.bytes().await?;
+
+        Ok(String::from_utf8_lossy(&bytes)
+            .lines()
+            .map(String::from)
+            .collect())
+    }
+
+    /// Same as [ListBuilder::send] but returns typed [ListEntry]s with
+    /// the metadata of each file, and the cursor of the next page if any.
+    pub async fn send_entries(self) -> Result<ListResponse> {
+        let (target, url) = self.request_url().await?;
+
+        let response = self
+            .client
+            .send(
+                &target,
+                self.client
+                    .request(Method::GET, url)
+                    .header(header::ACCEPT, "application/json"),
+            )
+            .await?;
+
+        response.error_for_status_ref()?;
+
+        // TODO: bail on too large files.
+        let bytes = response.bytes().await?;
+
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    /// Returns a [Stream] of [ListEntry]s in the path of the `url`, following
+    /// cursors until all entries are listed, or [ListBuilder::max_items] is reached.
+    ///
+    /// Pages of [ListBuilder::limit] entries are only requested once the
+    /// previous page is consumed.
+    pub fn stream(self) -> impl Stream<Item = Result<ListEntry>> {
+        let state = StreamState {
+            client: self.client.clone(),
+            url: self.url,
+            reverse: self.reverse,
+            limit: self.limit,
+            shallow: self.shallow,
+            cursor: self.cursor.map(String::from),
+            max_items: self.max_items,
+            buffer: VecDeque::new(),
+            yielded: 0,
+            exhausted: false,
+        };
+
+        stream::try_unfold(state, |mut state| async move {
+            loop {
+                if state.max_items.is_some_and(|max| state.yielded >= max) {
+                    return Ok(None);
+                }
+
+                if let Some(entry) = state.buffer.pop_front() {
+                    state.yielded += 1;
+                    return Ok(Some((entry, state)));
+                }
+
+                if state.exhausted {
+                    return Ok(None);
+                }
+
+                let mut builder = ListBuilder::new(&state.client, state.url.clone())
+                    .reverse(state.reverse)
+                    .shallow(state.shallow);
+
+                if let Some(limit) = state.limit {
+                    builder = builder.limit(limit);
+                }
+                if let Some(cursor) = state.cursor.as_deref() {
+                    builder = builder.cursor(cursor);
+                }
+
+                let page = builder.send_entries().await?;
+
+                // Guard against a cursor that does not advance.
+                state.exhausted = page.entries.is_empty()
+                    || page.next_cursor.is_none()
+                    || page.next_cursor == state.cursor;
+
+                state.cursor = page.next_cursor;
+                state.buffer.extend(page.entries);
+            }
+        })
+    }
+
+    /// Resolve the homeserver url of this list request with its query params,
+    /// and the target it was resolved from.
+    async fn request_url(&self) -> Result<(String, Url)> {
+        let (target, mut url) = self.client.pubky_to_http_target(self.url.clone()).await?;
 
         if !url.path().ends_with('/') {
             let path = url.path().to_string();
@@ -91,16 +206,20 @@ impl<'a> ListBuilder<'a> {
 
         drop(query);
 
-        let response = self.client.request(Method::GET, url).send().await?;
-
-        response.error_for_status_ref()?;
-
-        // TODO: bail on too large files.
-        let bytes = response.bytes().await?;
-
-        Ok(String::from_utf8_lossy(&bytes)
-            .lines()
-            .map(String::from)
-            .collect())
+        Ok((target, url))
     }
 }
+
+/// State of a [ListBuilder::stream] between pages.
+struct StreamState {
+    client: PubkyClient,
+    url: Url,
+    reverse: bool,
+    limit: Option<u16>,
+    shallow: bool,
+    cursor: Option<String>,
+    max_items: Option<usize>,
+    buffer: VecDeque<ListEntry>,
+    yielded: usize,
+    exhausted: bool,
+}
@@ -1,4 +1,6 @@
 pub mod auth;
+pub mod endpoint_cache;
 pub mod list_builder;
 pub mod pkarr;
 pub mod public;
+pub mod upload_builder;
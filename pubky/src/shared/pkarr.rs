@@ -1,3 +1,5 @@
+use std::{fmt::Display, time::Duration};
+
 use url::Url;
 
 use pkarr::{
@@ -5,22 +7,71 @@ use pkarr::{
     Keypair, PublicKey, SignedPacket,
 };
 
+use pubky_common::{
+    crypto::random_bytes,
+    endpoint::{EndpointParams, SUBDOMAINS_FEATURE},
+    timestamp::Timestamp,
+};
+
 use crate::{
     error::{Error, Result},
     PubkyClient,
 };
 
+use super::endpoint_cache::Lookup;
+
 const MAX_ENDPOINT_RESOLUTION_RECURSION: u8 = 3;
 
+/// Default age after which the `_pubky` record is republished,
+/// see [PubkyClient::republish_homeserver].
+pub(crate) const DEFAULT_REPUBLISH_THRESHOLD: Duration = Duration::from_secs(60 * 60);
+
+/// When to publish the `_pubky` record.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum PublishStrategy {
+    /// Always sign and publish a new packet.
+    Force,
+    /// Only publish if the `_pubky` record is missing, points to a different
+    /// host, or its packet is older than this.
+    IfOlderThan(Duration),
+}
+
 impl PubkyClient {
-    /// Publish the SVCB record for `_pubky.<public_key>`.
+    /// Publish the SVCB record for `_pubky.<public_key>`, preserving other records.
+    ///
+    /// If `host` is `None`, the host of the existing `_pubky` record is republished.
+    ///
+    /// Returns `true` if a new packet was published.
     pub(crate) async fn publish_pubky_homeserver(
         &self,
         keypair: &Keypair,
-        host: &str,
-    ) -> Result<()> {
+        host: Option<&str>,
+        strategy: PublishStrategy,
+    ) -> Result<bool> {
         let existing = self.pkarr_resolve(&keypair.public_key()).await?;
 
+        let existing_host = existing.as_ref().and_then(pubky_host);
+
+        let host = match (host, &existing_host) {
+            (Some(host), _) => host.to_string(),
+            (None, Some(existing_host)) => existing_host.clone(),
+            (None, None) => {
+                return Err(Error::Generic(
+                    "No homeserver to republish for this pubky".to_string(),
+                ))
+            }
+        };
+
+        if let (PublishStrategy::IfOlderThan(max_age), Some(existing)) = (strategy, &existing) {
+            let age = Timestamp::now()
+                .as_u64()
+                .saturating_sub(existing.timestamp());
+
+            if existing_host.as_deref() == Some(host.as_str()) && age < max_age.as_micros() as u64 {
+                return Ok(false);
+            }
+        }
+
         let mut packet = Packet::new_reply(0);
 
         if let Some(existing) = existing {
@@ -31,7 +82,7 @@ impl PubkyClient {
             }
         }
 
-        let svcb = SVCB::new(0, host.try_into()?);
+        let svcb = SVCB::new(0, host.as_str().try_into()?);
 
         packet.answers.push(pkarr::dns::ResourceRecord::new(
             "_pubky".try_into().unwrap(),
@@ -44,14 +95,33 @@ impl PubkyClient {
 
         self.pkarr_publish(&signed_packet).await?;
 
-        Ok(())
+        self.endpoint_cache
+            .invalidate(&pubky_target(keypair.public_key()));
+
+        Ok(true)
+    }
+
+    /// Refresh the `_pubky` record of `keypair` pointing to `host`,
+    /// if it is missing, points elsewhere, or is older than `max_age`.
+    ///
+    /// Returns `true` if a new packet was published.
+    pub(crate) async fn inner_republish_homeserver(
+        &self,
+        keypair: &Keypair,
+        host: &PublicKey,
+        max_age: Duration,
+    ) -> Result<bool> {
+        self.publish_pubky_homeserver(
+            keypair,
+            Some(&host.to_string()),
+            PublishStrategy::IfOlderThan(max_age),
+        )
+        .await
     }
 
     /// Resolve the homeserver for a pubky.
     pub(crate) async fn resolve_pubky_homeserver(&self, pubky: &PublicKey) -> Result<Endpoint> {
-        let target = format!("_pubky.{pubky}");
-
-        self.resolve_endpoint(&target)
+        self.resolve_endpoint(&pubky_target(pubky))
             .await
             .map_err(|_| Error::Generic("Could not resolve homeserver".to_string()))
     }
@@ -62,14 +132,54 @@ impl PubkyClient {
     /// usually an IPv4, IPv6 or ICANN domain, but could also be any other unknown hostname.
     ///
     /// Recursively resolve SVCB and HTTPS endpoints, with [MAX_ENDPOINT_RESOLUTION_RECURSION] limit.
+    ///
+    /// Results are cached, see [EndpointCache](super::endpoint_cache::EndpointCache).
     pub(crate) async fn resolve_endpoint(&self, target: &str) -> Result<Endpoint> {
+        match self.endpoint_cache.get(target, Timestamp::now().as_u64()) {
+            Lookup::Fresh(endpoint) => return Ok(endpoint),
+            Lookup::Stale {
+                endpoint,
+                revalidate,
+            } => {
+                if revalidate {
+                    let client = self.clone();
+                    let target = target.to_string();
+
+                    spawn(async move {
+                        let _ = client.resolve_and_cache_endpoint(&target).await;
+                    });
+                }
+
+                return Ok(endpoint);
+            }
+            Lookup::NotFound => return Err(Error::ResolveEndpoint(target.into())),
+            Lookup::Miss => {}
+        };
+
+        self.resolve_and_cache_endpoint(target).await
+    }
+
+    async fn resolve_and_cache_endpoint(&self, target: &str) -> Result<Endpoint> {
+        let result = self.resolve_endpoint_uncached(target).await;
+
+        self.endpoint_cache.insert(
+            target,
+            result.as_ref().ok().cloned(),
+            Timestamp::now().as_u64(),
+        );
+
+        result.map(|(mut candidates, _)| candidates.swap_remove(0))
+    }
+
+    /// Resolve the candidate endpoints of a target, in the order they should be tried,
+    /// and the minimum TTL of the records they were resolved from.
+    async fn resolve_endpoint_uncached(&self, target: &str) -> Result<(Vec<Endpoint>, u32)> {
         let original_target = target;
-        // TODO: cache the result of this function?
 
         let mut target = target.to_string();
 
-        let mut endpoint_public_key = None;
-        let mut origin = target.clone();
+        let mut candidates = vec![];
+        let mut ttl = u32::MAX;
 
         let mut step = 0;
 
@@ -80,87 +190,65 @@ impl PubkyClient {
             };
             step += 1;
 
-            if let Some(signed_packet) = self
+            let Some(signed_packet) = self
                 .pkarr_resolve(&public_key)
                 .await
                 .map_err(|_| Error::ResolveEndpoint(original_target.into()))?
-            {
-                // Choose most prior SVCB record
-                let svcb = signed_packet.resource_records(&target).fold(
-                    None,
-                    |prev: Option<SVCB>, answer| {
-                        if let Some(svcb) = match &answer.rdata {
-                            pkarr::dns::rdata::RData::SVCB(svcb) => Some(svcb),
-                            pkarr::dns::rdata::RData::HTTPS(curr) => Some(&curr.0),
-                            _ => None,
-                        } {
-                            let curr = svcb.clone();
-
-                            if curr.priority == 0 {
-                                return Some(curr);
-                            }
-                            if let Some(prev) = &prev {
-                                // TODO return random if priority is the same
-                                if curr.priority >= prev.priority {
-                                    return Some(curr);
-                                }
-                            } else {
-                                return Some(curr);
-                            }
+            else {
+                break;
+            };
+
+            let records = ordered_svcb_records(
+                signed_packet
+                    .resource_records(&target)
+                    .filter_map(|answer| match &answer.rdata {
+                        pkarr::dns::rdata::RData::SVCB(svcb) => {
+                            Some((svcb.clone().into_owned(), answer.ttl))
+                        }
+                        pkarr::dns::rdata::RData::HTTPS(https) => {
+                            Some((https.0.clone().into_owned(), answer.ttl))
                         }
+                        _ => None,
+                    })
+                    .collect(),
+            );
 
-                        prev
-                    },
-                );
+            let Some((first, _)) = records.first() else {
+                break;
+            };
 
-                if let Some(svcb) = svcb {
-                    endpoint_public_key = Some(public_key.clone());
-                    target = svcb.target.to_string();
+            ttl = records
+                .iter()
+                .fold(ttl, |ttl, (_, record_ttl)| ttl.min(*record_ttl));
 
-                    if let Some(port) = svcb.get_param(pkarr::dns::rdata::SVCB::PORT) {
-                        if port.len() < 2 {
-                            // TODO: debug! Error encoding port!
-                        }
-                        let port = u16::from_be_bytes([port[0], port[1]]);
+            // Follow the most prior record to the next Pkarr domain.
+            if PublicKey::try_from(first.target.to_string()).is_ok() {
+                target = first.target.to_string();
+                continue;
+            }
 
-                        origin = format!("{target}:{port}");
-                    } else {
-                        origin.clone_from(&target);
-                    };
+            candidates = records
+                .iter()
+                .filter(|(svcb, _)| PublicKey::try_from(svcb.target.to_string()).is_err())
+                .map(|(svcb, _)| Endpoint::from_svcb(svcb))
+                .collect::<Result<_>>()?;
 
-                    if step >= MAX_ENDPOINT_RESOLUTION_RECURSION {
-                        continue;
-                    };
-                }
-            } else {
-                break;
-            }
+            break;
         }
 
-        if PublicKey::try_from(origin.as_str()).is_ok() {
+        if candidates.is_empty() {
             return Err(Error::ResolveEndpoint(original_target.into()));
         }
 
-        if endpoint_public_key.is_some() {
-            let url = Url::parse(&format!(
-                "{}://{}",
-                if origin.starts_with("localhost") {
-                    "http"
-                } else {
-                    "https"
-                },
-                origin
-            ))?;
-
-            return Ok(Endpoint { url });
-        }
-
-        Err(Error::ResolveEndpoint(original_target.into()))
+        Ok((candidates, ttl))
     }
 
     pub(crate) async fn resolve_url(&self, url: &mut Url) -> Result<()> {
         if let Some(Ok(pubky)) = url.host_str().map(PublicKey::try_from) {
-            let Endpoint { url: x, .. } = self.resolve_endpoint(&format!("_pubky.{pubky}")).await?;
+            let x = self
+                .resolve_endpoint(&pubky_target(&pubky))
+                .await?
+                .pubky_url(&pubky);
 
             url.set_host(x.host_str())?;
             url.set_port(x.port()).expect("should work!");
@@ -171,9 +259,117 @@ impl PubkyClient {
     }
 }
 
-#[derive(Debug)]
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn spawn(future: impl std::future::Future<Output = ()> + Send + 'static) {
+    tokio::spawn(future);
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn spawn(future: impl std::future::Future<Output = ()> + 'static) {
+    wasm_bindgen_futures::spawn_local(future);
+}
+
+/// The target resolving to the homeserver of `pubky`.
+pub(crate) fn pubky_target(pubky: impl Display) -> String {
+    format!("_pubky.{pubky}")
+}
+
+/// The target of the `_pubky` record in a signed packet, if any.
+fn pubky_host(signed_packet: &SignedPacket) -> Option<String> {
+    signed_packet
+        .resource_records("_pubky")
+        .find_map(|record| match &record.rdata {
+            pkarr::dns::rdata::RData::SVCB(svcb) => Some(svcb.target.to_string()),
+            pkarr::dns::rdata::RData::HTTPS(https) => Some(https.0.target.to_string()),
+            _ => None,
+        })
+}
+
+/// Order SVCB records as specified in [RFC 9460](https://www.rfc-editor.org/rfc/rfc9460#section-2.4.1):
+/// AliasMode records (priority `0`) if any, otherwise ServiceMode records by ascending priority,
+/// in random order within the same priority.
+fn ordered_svcb_records(mut records: Vec<(SVCB<'static>, u32)>) -> Vec<(SVCB<'static>, u32)> {
+    if records.iter().any(|(svcb, _)| svcb.priority == 0) {
+        records.retain(|(svcb, _)| svcb.priority == 0);
+    }
+
+    // Fisher-Yates shuffle, then a stable sort to keep the random order within a priority.
+    for i in (1..records.len()).rev() {
+        let j = (u64::from_be_bytes(random_bytes::<8>()) % (i as u64 + 1)) as usize;
+        records.swap(i, j);
+    }
+    records.sort_by_key(|(svcb, _)| svcb.priority);
+
+    records
+}
+
+#[derive(Debug, Clone)]
 pub(crate) struct Endpoint {
     pub url: Url,
+    /// Parameters announced in the SVCB record of the endpoint.
+    pub params: EndpointParams,
+}
+
+impl Endpoint {
+    /// Endpoint of an SVCB record with a "non-pkarr" target.
+    fn from_svcb(svcb: &SVCB) -> Result<Self> {
+        let target = svcb.target.to_string();
+
+        let origin = match svcb.get_param(SVCB::PORT) {
+            Some(&[a, b]) => format!("{target}:{}", u16::from_be_bytes([a, b])),
+            _ => target,
+        };
+
+        let url = Url::parse(&format!(
+            "{}://{}",
+            if origin.starts_with("localhost") {
+                "http"
+            } else {
+                "https"
+            },
+            origin
+        ))?;
+
+        Ok(Endpoint {
+            url,
+            params: EndpointParams::from_svcb(svcb),
+        })
+    }
+
+    /// Returns true if the homeserver serves users at `<pubky>.<host>`.
+    pub fn uses_subdomains(&self) -> bool {
+        self.url.domain().is_some() && self.params.supports_feature(SUBDOMAINS_FEATURE)
+    }
+
+    /// Url of the root of `pubky` at this endpoint, `<pubky>.<host>/`
+    /// if it [uses subdomains](Self::uses_subdomains), or `<host>/<pubky>/` otherwise.
+    pub fn pubky_url(&self, pubky: &PublicKey) -> Url {
+        let mut url = self.url.clone();
+
+        if self.uses_subdomains() {
+            let host = format!("{pubky}.{}", self.url.host_str().unwrap_or_default());
+            url.set_host(Some(&host)).expect("valid subdomain");
+            url.set_path("/");
+        } else {
+            url.set_path(&format!("/{pubky}/"));
+        }
+
+        url
+    }
+
+    /// The pubky of the subdomain of this endpoint that `url` points to, if any.
+    pub fn subdomain_of(&self, url: &Url) -> Option<PublicKey> {
+        let (label, host) = url.host_str()?.split_once('.')?;
+
+        if url.scheme() != self.url.scheme()
+            || url.port_or_known_default() != self.url.port_or_known_default()
+            || Some(host) != self.url.host_str()
+        {
+            return None;
+        }
+
+        PublicKey::try_from(label).ok()
+    }
 }
 
 #[cfg(test)]
@@ -188,7 +384,11 @@ mod tests {
         mainline::{dht::DhtSettings, Testnet},
         Keypair, PkarrClient, Settings, SignedPacket,
     };
-    use pubky_homeserver::Homeserver;
+    use pubky_common::endpoint::PROTOCOL_VERSION;
+    use pubky_homeserver::{
+        config::{write_secret_key_file, Config, DEFAULT_MAX_ENTRY_SIZE},
+        Homeserver,
+    };
 
     #[tokio::test]
     async fn resolve_endpoint_https() {
@@ -320,7 +520,11 @@ mod tests {
             let pubky = Keypair::random();
 
             client
-                .publish_pubky_homeserver(&pubky, &format!("pubky.{}", &intermediate.public_key()))
+                .publish_pubky_homeserver(
+                    &pubky,
+                    Some(&format!("pubky.{}", &intermediate.public_key())),
+                    PublishStrategy::Force,
+                )
                 .await
                 .unwrap();
 
@@ -333,4 +537,258 @@ mod tests {
             assert_eq!(url.port(), Some(server.port()));
         }
     }
+
+    #[tokio::test]
+    async fn republish_homeserver() {
+        let testnet = Testnet::new(10);
+        let server = Homeserver::start_test(&testnet).await.unwrap();
+
+        let client = PubkyClient::test(&testnet);
+
+        let keypair = Keypair::random();
+
+        client.signup(&keypair, &server.public_key()).await.unwrap();
+
+        // Add an unrelated record to the user's packet.
+        let mut packet = Packet::new_reply(0);
+        let existing = client
+            .pkarr_resolve(&keypair.public_key())
+            .await
+            .unwrap()
+            .unwrap();
+        for answer in existing.packet().answers.iter().cloned() {
+            packet.answers.push(answer.into_owned());
+        }
+        packet.answers.push(pkarr::dns::ResourceRecord::new(
+            "foo".try_into().unwrap(),
+            pkarr::dns::CLASS::IN,
+            60 * 60,
+            pkarr::dns::rdata::RData::TXT("bar".try_into().unwrap()),
+        ));
+        let signed_packet = SignedPacket::from_packet(&keypair, &packet).unwrap();
+        client.pkarr_publish(&signed_packet).await.unwrap();
+
+        // Fresh record pointing to the same host.
+        assert!(!client
+            .republish_homeserver(&keypair, &server.public_key())
+            .await
+            .unwrap());
+
+        assert!(client
+            .inner_republish_homeserver(&keypair, &server.public_key(), Duration::ZERO)
+            .await
+            .unwrap());
+
+        let republished = client
+            .pkarr_resolve(&keypair.public_key())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(republished.more_recent_than(&signed_packet));
+        assert_eq!(republished.resource_records("foo").count(), 1);
+        assert_eq!(
+            pubky_host(&republished),
+            Some(server.public_key().to_string())
+        );
+
+        let Endpoint { url, .. } = client
+            .resolve_pubky_homeserver(&keypair.public_key())
+            .await
+            .unwrap();
+        assert_eq!(url.port(), Some(server.port()));
+
+        // Periodic republishing, moving to another host once, then skipping the fresh record.
+        let other = Homeserver::start_test(&testnet).await.unwrap();
+
+        let handle = client.republish_homeserver_periodically(
+            keypair.clone(),
+            other.public_key(),
+            Duration::from_millis(100),
+        );
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let moved = client
+            .pkarr_resolve(&keypair.public_key())
+            .await
+            .unwrap()
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        handle.abort();
+
+        let latest = client
+            .pkarr_resolve(&keypair.public_key())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(moved.more_recent_than(&republished));
+        assert_eq!(moved.timestamp(), latest.timestamp());
+        assert_eq!(latest.resource_records("foo").count(), 1);
+        assert_eq!(pubky_host(&latest), Some(other.public_key().to_string()));
+    }
+
+    #[tokio::test]
+    async fn follow_rotated_homeserver_key() {
+        let testnet = Testnet::new(10);
+
+        let storage = std::env::temp_dir().join(Timestamp::now().to_string());
+        std::fs::create_dir_all(&storage).unwrap();
+
+        let current = Keypair::random();
+        let previous = Keypair::random();
+        write_secret_key_file(&storage.join("current"), &current).unwrap();
+        write_secret_key_file(&storage.join("previous"), &previous).unwrap();
+
+        let config = Config::builder()
+            .set(&format!("bootstrap = {:?}", testnet.bootstrap))
+            .unwrap()
+            .set(&format!("storage = {:?}", storage))
+            .unwrap()
+            .set(&format!("secret_key_file = {:?}", storage.join("current")))
+            .unwrap()
+            .set(&format!(
+                "previous_secret_key_files = [{:?}]",
+                storage.join("previous")
+            ))
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let server = Homeserver::start(config).await.unwrap();
+        assert_eq!(server.public_key(), current.public_key());
+
+        let keypair = Keypair::random();
+
+        {
+            let client = PubkyClient::test(&testnet);
+            client.signup(&keypair, &server.public_key()).await.unwrap();
+
+            // The user's record still points to the homeserver's previous key.
+            assert!(client
+                .inner_republish_homeserver(&keypair, &previous.public_key(), Duration::ZERO)
+                .await
+                .unwrap());
+        }
+
+        // A client without cached endpoints follows `_pubky` to the previous key,
+        // then its redirect to the current key.
+        let client = PubkyClient::test(&testnet);
+
+        let Endpoint { url, .. } = client
+            .resolve_pubky_homeserver(&keypair.public_key())
+            .await
+            .unwrap();
+        assert_eq!(url.port(), Some(server.port()));
+
+        client.signin(&keypair).await.unwrap();
+
+        let url = format!("pubky://{}/pub/foo.txt", keypair.public_key());
+        client.put(url.as_str(), b"bar").await.unwrap();
+        assert_eq!(
+            client.get(url.as_str()).await.unwrap().as_deref(),
+            Some(b"bar".as_slice())
+        );
+    }
+
+    #[tokio::test]
+    async fn homeserver_params() {
+        let testnet = Testnet::new(10);
+        let server = Homeserver::start_test(&testnet).await.unwrap();
+
+        let client = PubkyClient::test(&testnet);
+
+        let keypair = Keypair::random();
+
+        client.signup(&keypair, &server.public_key()).await.unwrap();
+
+        let params = client
+            .homeserver_params(&keypair.public_key())
+            .await
+            .unwrap();
+
+        assert!(params.supports_version(PROTOCOL_VERSION));
+        assert!(params.supports_feature("uploads"));
+        assert_eq!(params.max_upload_size, Some(DEFAULT_MAX_ENTRY_SIZE));
+    }
+
+    #[test]
+    fn order_svcb_records() {
+        let record = |priority: u16, target: &'static str| {
+            (SVCB::new(priority, target.try_into().unwrap()), 60)
+        };
+
+        let targets = |records: Vec<(SVCB<'static>, u32)>| {
+            records
+                .iter()
+                .map(|(svcb, _)| svcb.target.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let mut orders = std::collections::HashSet::new();
+
+        for _ in 0..100 {
+            let ordered = targets(ordered_svcb_records(vec![
+                record(3, "d.com"),
+                record(1, "a.com"),
+                record(2, "c.com"),
+                record(1, "b.com"),
+            ]));
+
+            assert_eq!(&ordered[2..], ["c.com", "d.com"]);
+
+            orders.insert(ordered);
+        }
+
+        // Random order within the same priority.
+        assert_eq!(orders.len(), 2);
+
+        // AliasMode records take precedence.
+        let ordered = targets(ordered_svcb_records(vec![
+            record(1, "a.com"),
+            record(0, "alias.com"),
+        ]));
+        assert_eq!(ordered, ["alias.com"]);
+    }
+
+    #[tokio::test]
+    async fn failover_to_next_candidate() {
+        let testnet = Testnet::new(10);
+        let server = Homeserver::start_test(&testnet).await.unwrap();
+
+        let client = PubkyClient::test(&testnet);
+
+        let keypair = Keypair::random();
+
+        client.signup(&keypair, &server.public_key()).await.unwrap();
+
+        let url = format!("pubky://{}/pub/foo.txt", keypair.public_key());
+        client.put(url.as_str(), &[0]).await.unwrap();
+
+        // Point the user to an unreachable frontend first, then to the homeserver.
+        let mut packet = Packet::new_reply(0);
+        for (priority, port) in [(1, 1), (2, server.port())] {
+            let mut svcb = SVCB::new(priority, "localhost".try_into().unwrap());
+            svcb.set_port(port);
+
+            packet.answers.push(pkarr::dns::ResourceRecord::new(
+                "_pubky".try_into().unwrap(),
+                pkarr::dns::CLASS::IN,
+                60 * 60,
+                pkarr::dns::rdata::RData::HTTPS(svcb.into()),
+            ));
+        }
+        let signed_packet = SignedPacket::from_packet(&keypair, &packet).unwrap();
+        client.pkarr_publish(&signed_packet).await.unwrap();
+
+        let target = format!("_pubky.{}", keypair.public_key());
+        client.endpoint_cache.invalidate(&target);
+
+        assert_eq!(client.get(url.as_str()).await.unwrap().unwrap(), vec![0]);
+
+        let Endpoint { url, .. } = client.resolve_endpoint(&target).await.unwrap();
+        assert_eq!(url.port(), Some(server.port()));
+    }
 }
@@ -1,35 +1,38 @@
 use bytes::Bytes;
 
+use percent_encoding::percent_decode_str;
 use pkarr::PublicKey;
+use pubky_common::{list::DirectoryStat, path::Path};
 use reqwest::{Method, StatusCode};
 use url::Url;
 
 use crate::{
-    error::{Error, Result},
+    error::{error_for_status, Error, Result},
     PubkyClient,
 };
 
-use super::{list_builder::ListBuilder, pkarr::Endpoint};
+use super::{list_builder::ListBuilder, pkarr::pubky_target, upload_builder::UploadBuilder};
 
 impl PubkyClient {
     pub(crate) async fn inner_put<T: TryInto<Url>>(&self, url: T, content: &[u8]) -> Result<()> {
-        let url = self.pubky_to_http(url).await?;
+        let (target, url) = self.pubky_to_http_target(url).await?;
 
         let response = self
-            .request(Method::PUT, url)
-            .body(content.to_owned())
-            .send()
+            .send(
+                &target,
+                self.request(Method::PUT, url).body(content.to_owned()),
+            )
             .await?;
 
-        response.error_for_status()?;
+        error_for_status(response).await?;
 
         Ok(())
     }
 
     pub(crate) async fn inner_get<T: TryInto<Url>>(&self, url: T) -> Result<Option<Bytes>> {
-        let url = self.pubky_to_http(url).await?;
+        let (target, url) = self.pubky_to_http_target(url).await?;
 
-        let response = self.request(Method::GET, url).send().await?;
+        let response = self.send(&target, self.request(Method::GET, url)).await?;
 
         if response.status() == StatusCode::NOT_FOUND {
             return Ok(None);
@@ -44,22 +47,128 @@ impl PubkyClient {
     }
 
     pub(crate) async fn inner_delete<T: TryInto<Url>>(&self, url: T) -> Result<()> {
-        let url = self.pubky_to_http(url).await?;
+        let (target, url) = self.pubky_to_http_target(url).await?;
 
-        let response = self.request(Method::DELETE, url).send().await?;
+        let response = self
+            .send(&target, self.request(Method::DELETE, url))
+            .await?;
 
         response.error_for_status_ref()?;
 
         Ok(())
     }
 
-    pub(crate) fn inner_list<T: TryInto<Url>>(&self, url: T) -> Result<ListBuilder> {
+    pub(crate) async fn inner_delete_recursive<T: TryInto<Url>>(&self, url: T) -> Result<usize> {
+        let (target, mut url) = self.pubky_to_http_target(url).await?;
+
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+
+        url.query_pairs_mut().append_key_only("recursive");
+
+        let response = self
+            .send(&target, self.request(Method::DELETE, url))
+            .await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(0);
+        }
+
+        response.error_for_status_ref()?;
+
+        let text = response.text().await?;
+
+        text.trim()
+            .parse()
+            .map_err(|_| Error::Generic(format!("Invalid deleted entries count: {text}")))
+    }
+
+    pub(crate) async fn inner_stat<T: TryInto<Url>>(
+        &self,
+        url: T,
+    ) -> Result<Option<DirectoryStat>> {
+        let (target, mut url) = self.pubky_to_http_target(url).await?;
+
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+
+        url.query_pairs_mut().append_key_only("stat");
+
+        let response = self.send(&target, self.request(Method::GET, url)).await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        response.error_for_status_ref()?;
+
+        Ok(Some(serde_json::from_slice(&response.bytes().await?)?))
+    }
+
+    pub(crate) async fn inner_copy<T: TryInto<Url>, U: TryInto<Url>>(
+        &self,
+        source: T,
+        destination: U,
+        remove_source: bool,
+    ) -> Result<()> {
+        let source: Url = source.try_into().map_err(|_| Error::InvalidUrl)?;
+        let destination: Url = destination.try_into().map_err(|_| Error::InvalidUrl)?;
+
+        if source.host_str() != destination.host_str() {
+            return Err(Error::Generic(
+                "Source and destination should belong to the same Pubky".to_string(),
+            ));
+        }
+
+        // Sent as a query parameter, which is encoded again.
+        let destination_path = percent_decode_str(destination.path())
+            .decode_utf8()
+            .map_err(|_| Error::InvalidUrl)?;
+
+        let (target, mut url) = self.pubky_to_http_target(source).await?;
+
+        url.query_pairs_mut().append_pair(
+            if remove_source { "move" } else { "copy" },
+            &destination_path,
+        );
+
+        let response = self.send(&target, self.request(Method::POST, url)).await?;
+
+        response.error_for_status_ref()?;
+
+        Ok(())
+    }
+
+    pub(crate) fn inner_list<T: TryInto<Url>>(&self, url: T) -> Result<ListBuilder<'_>> {
         Ok(ListBuilder::new(
             self,
             url.try_into().map_err(|_| Error::InvalidUrl)?,
         ))
     }
 
+    pub(crate) fn inner_upload<T: TryInto<Url>>(&self, url: T) -> Result<UploadBuilder<'_>> {
+        Ok(UploadBuilder::new(
+            self,
+            url.try_into().map_err(|_| Error::InvalidUrl)?,
+        ))
+    }
+
+    /// Same as [PubkyClient::pubky_to_http], also returning the target of the endpoint
+    /// the url was resolved to, to [send](PubkyClient::send) requests to it.
+    pub(crate) async fn pubky_to_http_target<T: TryInto<Url>>(
+        &self,
+        url: T,
+    ) -> Result<(String, Url)> {
+        let url: Url = url.try_into().map_err(|_| Error::InvalidUrl)?;
+
+        Ok((
+            pubky_target(url.host_str().unwrap_or_default()),
+            self.pubky_to_http(url).await?,
+        ))
+    }
+
     pub(crate) async fn pubky_to_http<T: TryInto<Url>>(&self, url: T) -> Result<Url> {
         let original_url: Url = url.try_into().map_err(|_| Error::InvalidUrl)?;
 
@@ -68,22 +177,27 @@ impl PubkyClient {
             .ok_or(Error::Generic("Missing Pubky Url host".to_string()))?;
 
         if let Ok(public_key) = PublicKey::try_from(pubky) {
-            let Endpoint { mut url, .. } = self.resolve_pubky_homeserver(&public_key).await?;
-
-            // TODO: remove if we move to subdomains instead of paths.
-            if original_url.scheme() == "pubky" {
-                let path = original_url.path_segments();
-
-                let mut split = url.path_segments_mut().unwrap();
-                split.push(pubky);
-                if let Some(segments) = path {
-                    for segment in segments {
-                        split.push(segment);
-                    }
-                }
-                drop(split);
+            let endpoint = self.resolve_pubky_homeserver(&public_key).await?;
+
+            if original_url.scheme() != "pubky" {
+                return Ok(endpoint.url);
             }
 
+            let decoded = percent_decode_str(original_url.path()).decode_utf8_lossy();
+            let path = Path::parse(&decoded)?;
+
+            let mut url = endpoint.pubky_url(&public_key);
+
+            let mut split = url.path_segments_mut().unwrap();
+            split.pop_if_empty();
+            for segment in path.segments() {
+                split.push(segment);
+            }
+            if path.is_directory() {
+                split.push("");
+            }
+            drop(split);
+
             return Ok(url);
         }
 
@@ -99,6 +213,7 @@ mod tests {
     use crate::*;
 
     use bytes::Bytes;
+    use futures_util::{StreamExt, TryStreamExt};
     use pkarr::{mainline::Testnet, Keypair};
     use pubky_homeserver::Homeserver;
     use reqwest::{Method, StatusCode};
@@ -851,4 +966,678 @@ mod tests {
 
         assert_eq!(response, None);
     }
+
+    #[tokio::test]
+    async fn copy_rename() {
+        let testnet = Testnet::new(10);
+        let server = Homeserver::start_test(&testnet).await.unwrap();
+
+        let client = PubkyClient::test(&testnet);
+
+        let keypair = Keypair::random();
+
+        client.signup(&keypair, &server.public_key()).await.unwrap();
+
+        let pubky = keypair.public_key();
+
+        client
+            .put(format!("pubky://{pubky}/pub/a/1.txt").as_str(), &[1])
+            .await
+            .unwrap();
+        client
+            .put(format!("pubky://{pubky}/pub/a/b/2.txt").as_str(), &[2])
+            .await
+            .unwrap();
+
+        // Copy a file, then delete the original without affecting the shared blob.
+        client
+            .copy(
+                format!("pubky://{pubky}/pub/a/1.txt").as_str(),
+                format!("pubky://{pubky}/pub/c/1.txt").as_str(),
+            )
+            .await
+            .unwrap();
+        client
+            .delete(format!("pubky://{pubky}/pub/a/1.txt").as_str())
+            .await
+            .unwrap();
+
+        let blob = client
+            .get(format!("pubky://{pubky}/pub/c/1.txt").as_str())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(blob, Bytes::from(vec![1]));
+
+        // Move a directory.
+        client
+            .rename(
+                format!("pubky://{pubky}/pub/a/").as_str(),
+                format!("pubky://{pubky}/pub/d/").as_str(),
+            )
+            .await
+            .unwrap();
+
+        let list = client
+            .list(format!("pubky://{pubky}/pub/").as_str())
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            list,
+            vec![
+                format!("pubky://{pubky}/pub/c/1.txt"),
+                format!("pubky://{pubky}/pub/d/b/2.txt"),
+            ]
+        );
+
+        let response = client
+            .rename(
+                format!("pubky://{pubky}/pub/a/").as_str(),
+                format!("pubky://{pubky}/pub/e/").as_str(),
+            )
+            .await;
+
+        match response {
+            Err(Error::Reqwest(error)) => assert_eq!(error.status(), Some(StatusCode::NOT_FOUND)),
+            _ => panic!("expected error StatusCode::NOT_FOUND"),
+        }
+
+        let response = client
+            .copy(
+                format!("pubky://{pubky}/pub/d/").as_str(),
+                format!("pubky://{pubky}/pub/d/e/").as_str(),
+            )
+            .await;
+
+        match response {
+            Err(Error::Reqwest(error)) => {
+                assert_eq!(error.status(), Some(StatusCode::BAD_REQUEST))
+            }
+            _ => panic!("expected error StatusCode::BAD_REQUEST"),
+        }
+
+        let feed_url = format!("http://localhost:{}/events/", server.port());
+
+        let response = client
+            .request(Method::GET, feed_url.as_str().try_into().unwrap())
+            .send()
+            .await
+            .unwrap();
+
+        let text = response.text().await.unwrap();
+        let lines = text.split('\n').collect::<Vec<_>>();
+
+        assert_eq!(
+            lines,
+            vec![
+                format!("PUT pubky://{pubky}/pub/a/1.txt"),
+                format!("PUT pubky://{pubky}/pub/a/b/2.txt"),
+                format!("PUT pubky://{pubky}/pub/c/1.txt"),
+                format!("DEL pubky://{pubky}/pub/a/1.txt"),
+                format!("PUT pubky://{pubky}/pub/d/b/2.txt"),
+                format!("DEL pubky://{pubky}/pub/a/b/2.txt"),
+                lines.last().unwrap().to_string()
+            ]
+        );
+
+        // Non-ASCII destination, with a space.
+        client
+            .copy(
+                format!("pubky://{pubky}/pub/c/1.txt").as_str(),
+                format!("pubky://{pubky}/pub/c/é 1.txt").as_str(),
+            )
+            .await
+            .unwrap();
+
+        let list = client
+            .list(format!("pubky://{pubky}/pub/c/").as_str())
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            list,
+            vec![
+                format!("pubky://{pubky}/pub/c/1.txt"),
+                format!("pubky://{pubky}/pub/c/é 1.txt"),
+            ]
+        );
+        assert_eq!(
+            client
+                .get(format!("pubky://{pubky}/pub/c/é 1.txt").as_str())
+                .await
+                .unwrap(),
+            Some(Bytes::from(vec![1]))
+        );
+    }
+
+    #[tokio::test]
+    async fn copy_rename_batches() {
+        let testnet = Testnet::new(10);
+        let server = Homeserver::start_test(&testnet).await.unwrap();
+
+        let client = PubkyClient::test(&testnet);
+
+        let keypair = Keypair::random();
+
+        client.signup(&keypair, &server.public_key()).await.unwrap();
+
+        let pubky = keypair.public_key();
+
+        // More than one batch of copies.
+        for i in 0..120 {
+            client
+                .put(format!("pubky://{pubky}/pub/dir/{i}.txt").as_str(), &[0])
+                .await
+                .unwrap();
+        }
+
+        client
+            .copy(
+                format!("pubky://{pubky}/pub/dir/").as_str(),
+                format!("pubky://{pubky}/pub/copy/").as_str(),
+            )
+            .await
+            .unwrap();
+        client
+            .rename(
+                format!("pubky://{pubky}/pub/copy/").as_str(),
+                format!("pubky://{pubky}/pub/moved/").as_str(),
+            )
+            .await
+            .unwrap();
+
+        let list = client
+            .list(format!("pubky://{pubky}/pub/").as_str())
+            .unwrap()
+            .shallow(true)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            list,
+            vec![
+                format!("pubky://{pubky}/pub/dir/"),
+                format!("pubky://{pubky}/pub/moved/"),
+            ]
+        );
+
+        for dir in ["dir", "moved"] {
+            let count = client
+                .delete_recursive(format!("pubky://{pubky}/pub/{dir}/").as_str())
+                .await
+                .unwrap();
+
+            assert_eq!(count, 120);
+        }
+    }
+
+    #[tokio::test]
+    async fn delete_recursive() {
+        let testnet = Testnet::new(10);
+        let server = Homeserver::start_test(&testnet).await.unwrap();
+
+        let client = PubkyClient::test(&testnet);
+
+        let keypair = Keypair::random();
+
+        client.signup(&keypair, &server.public_key()).await.unwrap();
+
+        let pubky = keypair.public_key();
+
+        // More than one batch of deletions.
+        for i in 0..120 {
+            client
+                .put(format!("pubky://{pubky}/pub/dir/{i}.txt").as_str(), &[0])
+                .await
+                .unwrap();
+        }
+        client
+            .put(format!("pubky://{pubky}/pub/dir.txt").as_str(), &[0])
+            .await
+            .unwrap();
+
+        let response = client
+            .delete(format!("pubky://{pubky}/pub/dir/").as_str())
+            .await;
+
+        match response {
+            Err(Error::Reqwest(error)) => assert_eq!(error.status(), Some(StatusCode::CONFLICT)),
+            _ => panic!("expected error StatusCode::CONFLICT"),
+        }
+
+        let count = client
+            .delete_recursive(format!("pubky://{pubky}/pub/dir/").as_str())
+            .await
+            .unwrap();
+
+        assert_eq!(count, 120);
+
+        let list = client
+            .list(format!("pubky://{pubky}/pub/").as_str())
+            .unwrap()
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(list, vec![format!("pubky://{pubky}/pub/dir.txt")]);
+
+        let count = client
+            .delete_recursive(format!("pubky://{pubky}/pub/dir/").as_str())
+            .await
+            .unwrap();
+
+        assert_eq!(count, 0);
+
+        let feed_url = format!("http://localhost:{}/events/?limit=1000", server.port());
+
+        let response = client
+            .request(Method::GET, feed_url.as_str().try_into().unwrap())
+            .send()
+            .await
+            .unwrap();
+
+        let text = response.text().await.unwrap();
+
+        assert_eq!(
+            text.lines()
+                .filter(|line| line.starts_with(&format!("DEL pubky://{pubky}/pub/dir/")))
+                .count(),
+            120
+        );
+    }
+
+    #[tokio::test]
+    async fn list_entries() {
+        let testnet = Testnet::new(10);
+        let server = Homeserver::start_test(&testnet).await.unwrap();
+
+        let client = PubkyClient::test(&testnet);
+
+        let keypair = Keypair::random();
+
+        client.signup(&keypair, &server.public_key()).await.unwrap();
+
+        let pubky = keypair.public_key();
+
+        client
+            .put(format!("pubky://{pubky}/pub/a.txt").as_str(), &[0, 1, 2])
+            .await
+            .unwrap();
+        client
+            .put(format!("pubky://{pubky}/pub/dir/b.txt").as_str(), &[0])
+            .await
+            .unwrap();
+        client
+            .put(format!("pubky://{pubky}/pub/z.txt").as_str(), &[0])
+            .await
+            .unwrap();
+
+        let url = format!("pubky://{pubky}/pub/");
+
+        let list = client
+            .list(url.as_str())
+            .unwrap()
+            .shallow(true)
+            .limit(2)
+            .send_entries()
+            .await
+            .unwrap();
+
+        assert_eq!(list.entries.len(), 2);
+
+        let file = &list.entries[0];
+        assert_eq!(file.url, format!("pubky://{pubky}/pub/a.txt"));
+        assert!(!file.is_directory);
+        assert_eq!(file.content_length, Some(3));
+        assert!(file.last_modified.is_some());
+
+        let response = client
+            .request(
+                Method::HEAD,
+                client.pubky_to_http(file.url.as_str()).await.unwrap(),
+            )
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            file.etag.as_deref(),
+            response.headers().get("etag").and_then(|h| h.to_str().ok())
+        );
+
+        let directory = &list.entries[1];
+        assert_eq!(directory.url, format!("pubky://{pubky}/pub/dir/"));
+        assert!(directory.is_directory);
+        assert_eq!(directory.content_length, None);
+
+        assert_eq!(list.next_cursor, Some(format!("pubky://{pubky}/pub/dir/")));
+
+        let list = client
+            .list(url.as_str())
+            .unwrap()
+            .shallow(true)
+            .limit(2)
+            .cursor(list.next_cursor.as_deref().unwrap())
+            .send_entries()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            list.entries
+                .iter()
+                .map(|entry| entry.url.as_str())
+                .collect::<Vec<_>>(),
+            vec![format!("pubky://{pubky}/pub/z.txt")]
+        );
+        assert_eq!(list.next_cursor, None);
+    }
+
+    #[tokio::test]
+    async fn list_stream() {
+        let testnet = Testnet::new(10);
+        let server = Homeserver::start_test(&testnet).await.unwrap();
+
+        let client = PubkyClient::test(&testnet);
+
+        let keypair = Keypair::random();
+
+        client.signup(&keypair, &server.public_key()).await.unwrap();
+
+        let pubky = keypair.public_key();
+
+        let urls = (0..5)
+            .map(|i| format!("pubky://{pubky}/pub/example.com/{i}.txt"))
+            .collect::<Vec<_>>();
+
+        for url in &urls {
+            client.put(url.as_str(), &[0]).await.unwrap();
+        }
+
+        let url = format!("pubky://{pubky}/pub/example.com/");
+
+        let list = client
+            .list(url.as_str())
+            .unwrap()
+            .limit(2)
+            .stream()
+            .map(|entry| entry.map(|entry| entry.url))
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        assert_eq!(list, urls, "stream follows cursors across pages");
+
+        let list = client
+            .list(url.as_str())
+            .unwrap()
+            .limit(2)
+            .reverse(true)
+            .max_items(3)
+            .stream()
+            .map(|entry| entry.map(|entry| entry.url))
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            list,
+            urls.iter().rev().take(3).cloned().collect::<Vec<_>>(),
+            "reverse stream with max items"
+        );
+    }
+
+    #[tokio::test]
+    async fn stat() {
+        let testnet = Testnet::new(10);
+        let server = Homeserver::start_test(&testnet).await.unwrap();
+
+        let client = PubkyClient::test(&testnet);
+
+        let keypair = Keypair::random();
+
+        client.signup(&keypair, &server.public_key()).await.unwrap();
+
+        let pubky = keypair.public_key();
+
+        let url = format!("pubky://{pubky}/pub/example.com/");
+
+        assert_eq!(client.stat(url.as_str()).await.unwrap(), None);
+
+        client
+            .put(format!("{url}a.txt").as_str(), &[0; 10])
+            .await
+            .unwrap();
+        client
+            .put(format!("{url}dir/b.txt").as_str(), &[0; 5])
+            .await
+            .unwrap();
+        client
+            .put(
+                format!("pubky://{pubky}/pub/other.com/c.txt").as_str(),
+                &[0; 7],
+            )
+            .await
+            .unwrap();
+
+        let stat = client.stat(url.as_str()).await.unwrap().unwrap();
+        assert_eq!(stat.content_length, 15);
+        assert_eq!(stat.entries_count, 2);
+
+        let stat = client
+            .stat(format!("pubky://{pubky}/pub").as_str())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stat.content_length, 22);
+        assert_eq!(stat.entries_count, 3);
+
+        client
+            .rename(
+                format!("{url}dir/").as_str(),
+                format!("pubky://{pubky}/pub/other.com/dir/").as_str(),
+            )
+            .await
+            .unwrap();
+
+        let stat = client.stat(url.as_str()).await.unwrap().unwrap();
+        assert_eq!(stat.content_length, 10);
+        assert_eq!(stat.entries_count, 1);
+
+        client.delete(format!("{url}a.txt").as_str()).await.unwrap();
+
+        assert_eq!(client.stat(url.as_str()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn upload() {
+        let testnet = Testnet::new(10);
+        let server = Homeserver::start_test(&testnet).await.unwrap();
+
+        let client = PubkyClient::test(&testnet);
+
+        let keypair = Keypair::random();
+
+        client.signup(&keypair, &server.public_key()).await.unwrap();
+
+        let pubky = keypair.public_key();
+
+        let url = format!("pubky://{pubky}/pub/example.com/large");
+
+        let content = (0..100_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+        client
+            .upload(url.as_str())
+            .unwrap()
+            .part_size(16 * 1024)
+            .send(&content)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            client.get(url.as_str()).await.unwrap().unwrap().as_ref(),
+            content
+        );
+
+        // Resume a session that already received a part.
+        let builder = client.upload(url.as_str()).unwrap().part_size(16 * 1024);
+        let id = builder.create_session().await.unwrap();
+
+        let mut part_url = client.pubky_to_http(url.as_str()).await.unwrap();
+        part_url
+            .query_pairs_mut()
+            .append_pair("upload", &id)
+            .append_pair("part", "1");
+        client
+            .request(Method::PUT, part_url.clone())
+            .body(content[16 * 1024..32 * 1024].to_vec())
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+
+        assert_eq!(
+            builder.status(&id).await.unwrap(),
+            Some(UploadStatus {
+                parts: vec![1],
+                offset: 0
+            })
+        );
+
+        let content = content.iter().rev().cloned().collect::<Vec<_>>();
+
+        // Part 1 has stale content, so the hash doesn't match.
+        let error = builder.session(&id).send(&content).await.unwrap_err();
+        assert!(
+            matches!(error, Error::Reqwest(error) if error.status() == Some(StatusCode::BAD_REQUEST))
+        );
+
+        client
+            .request(Method::PUT, part_url)
+            .body(content[16 * 1024..32 * 1024].to_vec())
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+
+        client
+            .upload(url.as_str())
+            .unwrap()
+            .part_size(16 * 1024)
+            .session(&id)
+            .send(&content)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            client.get(url.as_str()).await.unwrap().unwrap().as_ref(),
+            content
+        );
+
+        // Finalized sessions are removed.
+        let builder = client.upload(url.as_str()).unwrap();
+        assert_eq!(builder.status(&id).await.unwrap(), None);
+
+        // Read from a stream of chunks shorter than a part.
+        let reader = futures_util::stream::iter(
+            content
+                .chunks(1000)
+                .map(|chunk| Ok::<_, std::io::Error>(chunk.to_vec()))
+                .collect::<Vec<_>>(),
+        )
+        .into_async_read();
+
+        let url = format!("pubky://{pubky}/pub/example.com/streamed");
+
+        client
+            .upload(url.as_str())
+            .unwrap()
+            .part_size(16 * 1024)
+            .send_reader(reader)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            client.get(url.as_str()).await.unwrap().unwrap().as_ref(),
+            content
+        );
+    }
+
+    #[tokio::test]
+    async fn subdomains() {
+        let testnet = Testnet::new(10);
+
+        let config_path =
+            std::env::temp_dir().join(format!("{}.toml", Keypair::random().public_key()));
+        std::fs::write(
+            &config_path,
+            format!(
+                "bootstrap = {:?}\nstorage = {:?}\nsubdomains = true\n",
+                testnet.bootstrap,
+                pubky_homeserver::config::Config::test(&testnet).storage(),
+            ),
+        )
+        .unwrap();
+
+        let config = pubky_homeserver::config::Config::load(&config_path)
+            .await
+            .unwrap();
+        std::fs::remove_file(&config_path).unwrap();
+
+        let server = Homeserver::start(config).await.unwrap();
+
+        let client = PubkyClient::test(&testnet);
+
+        let keypair = Keypair::random();
+        let pubky = keypair.public_key();
+
+        client.signup(&keypair, &server.public_key()).await.unwrap();
+
+        let url = format!("pubky://{pubky}/pub/foo.txt");
+
+        assert_eq!(
+            client.pubky_to_http(url.as_str()).await.unwrap().as_str(),
+            format!("http://{pubky}.localhost:{}/pub/foo.txt", server.port())
+        );
+
+        assert!(client.session(&pubky).await.unwrap().is_some());
+
+        client.put(url.as_str(), &[0, 1, 2, 3, 4]).await.unwrap();
+
+        assert_eq!(
+            client.get(url.as_str()).await.unwrap().unwrap().as_ref(),
+            &[0, 1, 2, 3, 4]
+        );
+
+        // Redirected from paths to the subdomain.
+        let response = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+            .get(format!(
+                "http://localhost:{}/{pubky}/pub/foo.txt",
+                server.port()
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            response.headers()[reqwest::header::LOCATION],
+            format!("//{pubky}.localhost:{}/pub/foo.txt", server.port()).as_str()
+        );
+
+        client.signout(&pubky).await.unwrap();
+        assert!(client.session(&pubky).await.unwrap().is_none());
+
+        client.signin(&keypair).await.unwrap();
+        assert!(client.session(&pubky).await.unwrap().is_some());
+    }
 }
@@ -0,0 +1,240 @@
+use std::{collections::HashSet, time::Duration};
+
+use futures_util::{AsyncRead, AsyncReadExt};
+use pubky_common::{crypto::Hasher, upload::UploadStatus};
+use reqwest::{Method, StatusCode};
+use url::Url;
+
+use crate::{
+    error::{error_for_status, Error, Result},
+    PubkyClient,
+};
+
+/// Default size of each uploaded part (8 MiB).
+pub const DEFAULT_PART_SIZE: usize = 8 * 1024 * 1024;
+/// Default number of retries of a failed part, before giving up.
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// Delay before the first retry of a failed part, doubled for every following retry.
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+/// Maximum delay between two retries of a failed part.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Helper struct to upload large payloads in parts, resuming
+/// and retrying individual parts instead of the whole upload.
+#[derive(Debug)]
+pub struct UploadBuilder<'a> {
+    url: Url,
+    client: &'a PubkyClient,
+    part_size: usize,
+    max_retries: u32,
+    session: Option<String>,
+}
+
+impl<'a> UploadBuilder<'a> {
+    /// Create a new Upload builder
+    pub(crate) fn new(client: &'a PubkyClient, url: Url) -> Self {
+        Self {
+            client,
+            url,
+            part_size: DEFAULT_PART_SIZE,
+            max_retries: DEFAULT_MAX_RETRIES,
+            session: None,
+        }
+    }
+
+    /// Set the size of each part in bytes, defaults to [DEFAULT_PART_SIZE].
+    pub fn part_size(mut self, part_size: usize) -> Self {
+        self.part_size = part_size.max(1);
+        self
+    }
+
+    /// Set how many times a failed part is retried, defaults to [DEFAULT_MAX_RETRIES].
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Resume an existing upload session, created by [UploadBuilder::create_session],
+    /// only uploading the parts the homeserver didn't receive yet.
+    pub fn session(mut self, id: &str) -> Self {
+        self.session = Some(id.to_string());
+        self
+    }
+
+    /// Create an upload session and return its id.
+    ///
+    /// Useful to persist the id before calling [UploadBuilder::send],
+    /// to resume the upload with [UploadBuilder::session] after a crash.
+    pub async fn create_session(&self) -> Result<String> {
+        let (target, mut url) = self.client.pubky_to_http_target(self.url.clone()).await?;
+        url.query_pairs_mut().append_key_only("uploads");
+
+        let response = self
+            .client
+            .send(&target, self.client.request(Method::POST, url))
+            .await?;
+
+        response.error_for_status_ref()?;
+
+        Ok(response.text().await?.trim().to_string())
+    }
+
+    /// Returns the [UploadStatus] of the upload session `id`,
+    /// or `None` if the session doesn't exist (anymore).
+    pub async fn status(&self, id: &str) -> Result<Option<UploadStatus>> {
+        let (target, mut url) = self.client.pubky_to_http_target(self.url.clone()).await?;
+        url.query_pairs_mut().append_pair("upload", id);
+
+        let response = self
+            .client
+            .send(&target, self.client.request(Method::GET, url))
+            .await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        response.error_for_status_ref()?;
+
+        Ok(Some(serde_json::from_slice(&response.bytes().await?)?))
+    }
+
+    /// Upload the `content` in parts of [UploadBuilder::part_size],
+    /// and write it to the `url` once all parts are received.
+    pub async fn send(self, content: &[u8]) -> Result<()> {
+        self.send_reader(content).await
+    }
+
+    /// Same as [UploadBuilder::send], reading the content from `reader` one part at a time,
+    /// so only a single part is held in memory.
+    ///
+    /// Parts already received by a resumed [session](UploadBuilder::session) are read, to hash
+    /// the whole content, but not uploaded again.
+    pub async fn send_reader<R: AsyncRead + Unpin>(self, mut reader: R) -> Result<()> {
+        let (target, url) = self.client.pubky_to_http_target(self.url.clone()).await?;
+
+        let (id, received) = match &self.session {
+            Some(id) => {
+                let status = self
+                    .status(id)
+                    .await?
+                    .ok_or(Error::Generic(format!("Upload session {id} not found")))?;
+
+                (id.clone(), status.parts.into_iter().collect())
+            }
+            None => (self.create_session().await?, HashSet::new()),
+        };
+
+        let mut hasher = Hasher::new();
+        let mut chunk = Vec::with_capacity(self.part_size);
+
+        for part in 0.. {
+            chunk.clear();
+            (&mut reader)
+                .take(self.part_size as u64)
+                .read_to_end(&mut chunk)
+                .await?;
+
+            if chunk.is_empty() {
+                break;
+            }
+
+            hasher.update(&chunk);
+
+            if !received.contains(&part) {
+                self.upload_part(&target, &url, &id, part, &chunk).await?;
+            }
+        }
+
+        let mut url = url;
+        url.query_pairs_mut()
+            .append_pair("upload", &id)
+            .append_pair("hash", &hasher.finalize().to_hex());
+
+        let response = self
+            .client
+            .send(&target, self.client.request(Method::POST, url))
+            .await?;
+
+        error_for_status(response).await?;
+
+        Ok(())
+    }
+
+    /// Upload a single part, retrying up to [UploadBuilder::max_retries]
+    /// times on network and server errors.
+    async fn upload_part(
+        &self,
+        target: &str,
+        url: &Url,
+        id: &str,
+        part: u32,
+        chunk: &[u8],
+    ) -> Result<()> {
+        let mut url = url.clone();
+        url.query_pairs_mut()
+            .append_pair("upload", id)
+            .append_pair("part", &part.to_string());
+
+        let mut retries = 0;
+
+        loop {
+            let result = match self
+                .client
+                .send(
+                    target,
+                    self.client
+                        .request(Method::PUT, url.clone())
+                        .body(chunk.to_vec()),
+                )
+                .await
+            {
+                Ok(response) => error_for_status(response).await,
+                Err(error) => Err(error),
+            };
+
+            match result {
+                Ok(_) => return Ok(()),
+                Err(Error::Reqwest(error))
+                    if retries < self.max_retries
+                        && error.status().is_none_or(|s| s.is_server_error()) =>
+                {
+                    sleep(retry_delay(retries)).await;
+                    retries += 1;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+/// Exponential backoff before retrying a part that already failed `retries + 1` times.
+fn retry_delay(retries: u32) -> Duration {
+    RETRY_BASE_DELAY
+        .saturating_mul(2_u32.saturating_pow(retries.min(16)))
+        .min(MAX_RETRY_DELAY)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await;
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn sleep(duration: Duration) {
+    gloo_timers::future::sleep(duration).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delay_is_capped() {
+        assert_eq!(retry_delay(0), RETRY_BASE_DELAY);
+        assert_eq!(retry_delay(1), RETRY_BASE_DELAY * 2);
+        assert_eq!(retry_delay(32), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(u32::MAX), MAX_RETRY_DELAY);
+    }
+}
@@ -11,15 +11,18 @@ use url::Url;
 use pubky_common::capabilities::Capabilities;
 
 use crate::error::Error;
+use crate::shared::pkarr::DEFAULT_REPUBLISH_THRESHOLD;
 use crate::PubkyClient;
 
 mod http;
 mod keys;
+mod list_stream;
 mod pkarr;
 mod recovery_file;
 mod session;
 
 use keys::{Keypair, PublicKey};
+use list_stream::ListStream;
 use session::Session;
 
 impl Default for PubkyClient {
@@ -37,6 +40,7 @@ impl PubkyClient {
     pub fn new() -> Self {
         Self {
             http: reqwest::Client::builder().build().unwrap(),
+            endpoint_cache: Default::default(),
             session_cookies: Arc::new(RwLock::new(HashSet::new())),
             pkarr_relays: DEFAULT_RELAYS.into_iter().map(|s| s.to_string()).collect(),
         }
@@ -48,6 +52,7 @@ impl PubkyClient {
     pub fn testnet() -> Self {
         Self {
             http: reqwest::Client::builder().build().unwrap(),
+            endpoint_cache: Default::default(),
             session_cookies: Arc::new(RwLock::new(HashSet::new())),
             pkarr_relays: TESTNET_RELAYS.into_iter().map(|s| s.to_string()).collect(),
         }
@@ -106,6 +111,8 @@ impl PubkyClient {
     }
 
     /// Signin to a homeserver using the root Keypair.
+    ///
+    /// Also republishes the `_pubky` record in the background if it is older than an hour.
     #[wasm_bindgen]
     pub async fn signin(&self, keypair: &Keypair) -> Result<(), JsValue> {
         self.inner_signin(keypair.as_inner())
@@ -114,6 +121,60 @@ impl PubkyClient {
             .map_err(|e| e.into())
     }
 
+    /// Republish the `_pubky` record pointing to the `host` homeserver,
+    /// to keep it alive in the DHT, while preserving other records in the Pkarr packet.
+    ///
+    /// Skipped if the current record already points to `host` and was published
+    /// less than an hour ago.
+    ///
+    /// Returns `true` if a new packet was published.
+    #[wasm_bindgen(js_name = "republishHomeserver")]
+    pub async fn republish_homeserver(
+        &self,
+        keypair: &Keypair,
+        host: &PublicKey,
+    ) -> Result<bool, JsValue> {
+        self.inner_republish_homeserver(
+            keypair.as_inner(),
+            host.as_inner(),
+            DEFAULT_REPUBLISH_THRESHOLD,
+        )
+        .await
+        .map_err(|e| e.into())
+    }
+
+    /// Returns the hits and misses of the cache of resolved homeserver endpoints,
+    /// as an object with the fields `hits`, `stale_hits`, `negative_hits` and `misses`.
+    #[wasm_bindgen(js_name = "endpointCacheStats")]
+    pub fn endpoint_cache_stats(&self) -> Result<JsValue, JsValue> {
+        let stats = self.endpoint_cache.stats();
+
+        let json = serde_json::json!({
+            "hits": stats.hits,
+            "stale_hits": stats.stale_hits,
+            "negative_hits": stats.negative_hits,
+            "misses": stats.misses,
+        });
+
+        js_sys::JSON::parse(&json.to_string())
+    }
+
+    /// Resolve the parameters announced by the homeserver of `pubky`.
+    ///
+    /// Returns an object with the fields `alpn`, `ipv4_hints`, `ipv6_hints`,
+    /// `versions`, `max_upload_size` and `features`.
+    #[wasm_bindgen(js_name = "homeserverParams")]
+    pub async fn homeserver_params(&self, pubky: &PublicKey) -> Result<JsValue, JsValue> {
+        let params = self
+            .resolve_pubky_homeserver(pubky.as_inner())
+            .await?
+            .params;
+
+        let json = serde_json::to_string(&params).map_err(Error::from)?;
+
+        js_sys::JSON::parse(&json)
+    }
+
     /// Return `pubkyauth://` url and wait for the incoming [AuthToken]
     /// verifying that AuthToken, and if capabilities were requested, signing in to
     /// the Pubky's homeserver and returning the [Session] information.
@@ -190,13 +251,57 @@ impl PubkyClient {
         self.inner_delete(url).await.map_err(|e| e.into())
     }
 
+    /// Delete every file under a directory relative to a pubky author.
+    ///
+    /// Returns the number of deleted files.
+    #[wasm_bindgen(js_name = "deleteRecursive")]
+    pub async fn delete_recursive(&self, url: &str) -> Result<usize, JsValue> {
+        self.inner_delete_recursive(url).await.map_err(|e| e.into())
+    }
+
+    /// Get the total size, files count and last modification time
+    /// of a directory relative to a pubky author, recursively.
+    ///
+    /// Returns an object with the fields `content_length`, `entries_count` and `last_modified`,
+    /// or `undefined` if the directory is empty or doesn't exist.
+    #[wasm_bindgen]
+    pub async fn stat(&self, url: &str) -> Result<JsValue, JsValue> {
+        match self.inner_stat(url).await? {
+            Some(stat) => {
+                let json = serde_json::to_string(&stat).map_err(Error::from)?;
+
+                js_sys::JSON::parse(&json)
+            }
+            None => Ok(JsValue::UNDEFINED),
+        }
+    }
+
+    /// Copy a file, or a directory (url ending with `/`) recursively,
+    /// to another path of the same pubky, without re-uploading its content.
+    #[wasm_bindgen]
+    pub async fn copy(&self, source: &str, destination: &str) -> Result<(), JsValue> {
+        self.inner_copy(source, destination, false)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// Move a file, or a directory (url ending with `/`) recursively,
+    /// to another path of the same pubky, without re-uploading its content.
+    #[wasm_bindgen]
+    pub async fn rename(&self, source: &str, destination: &str) -> Result<(), JsValue> {
+        self.inner_copy(source, destination, true)
+            .await
+            .map_err(|e| e.into())
+    }
+
     /// Returns a list of Pubky urls (as strings).
     ///
     /// - `url`:     The Pubky url (string) to the directory you want to list its content.
     /// - `cursor`:  Either a full `pubky://` Url (from previous list response),
     ///                 or a path (to a file or directory) relative to the `url`
     /// - `reverse`: List in reverse order
-    /// - `limit`    Limit the number of urls in the response
+    /// - `limit`    Limit the number of urls in the response,
+    ///                 defaults to the maximum allowed by the homeserver
     /// - `shallow`: List directories and files, instead of flat list of files.
     #[wasm_bindgen]
     pub async fn list(
@@ -209,31 +314,17 @@ impl PubkyClient {
     ) -> Result<Array, JsValue> {
         // TODO: try later to return Vec<String> from async function.
 
-        if let Some(cursor) = cursor {
-            return self
-                .inner_list(url)?
-                .reverse(reverse.unwrap_or(false))
-                .limit(limit.unwrap_or(u16::MAX))
-                .cursor(&cursor)
-                .shallow(shallow.unwrap_or(false))
-                .send()
-                .await
-                .map(|urls| {
-                    let js_array = Array::new();
-
-                    for url in urls {
-                        js_array.push(&JsValue::from_str(&url));
-                    }
+        let mut builder = self
+            .inner_list(url)?
+            .reverse(reverse.unwrap_or(false))
+            .limit(limit.unwrap_or(u16::MAX))
+            .shallow(shallow.unwrap_or(false));
 
-                    js_array
-                })
-                .map_err(|e| e.into());
+        if let Some(cursor) = &cursor {
+            builder = builder.cursor(cursor);
         }
 
-        self.inner_list(url)?
-            .reverse(reverse.unwrap_or(false))
-            .limit(limit.unwrap_or(u16::MAX))
-            .shallow(shallow.unwrap_or(false))
+        builder
             .send()
             .await
             .map(|urls| {
@@ -247,4 +338,71 @@ impl PubkyClient {
             })
             .map_err(|e| e.into())
     }
+
+    /// Returns a [ListStream] of all the entries in a directory,
+    /// transparently following cursors, one page at a time.
+    ///
+    /// - `url`:      The Pubky url (string) to the directory you want to list its content.
+    /// - `reverse`:  List in reverse order
+    /// - `shallow`:  List directories and files, instead of flat list of files.
+    /// - `maxItems`: Stop after returning that many entries.
+    #[wasm_bindgen(js_name = "listStream")]
+    pub fn list_stream(
+        &self,
+        url: &str,
+        reverse: Option<bool>,
+        shallow: Option<bool>,
+        max_items: Option<usize>,
+    ) -> Result<ListStream, JsValue> {
+        let mut builder = self
+            .inner_list(url)?
+            .reverse(reverse.unwrap_or(false))
+            .shallow(shallow.unwrap_or(false));
+
+        if let Some(max_items) = max_items {
+            builder = builder.max_items(max_items);
+        }
+
+        Ok(ListStream(Box::pin(builder.stream())))
+    }
+
+    /// Create an upload session for [PubkyClient::upload], and return its id.
+    #[wasm_bindgen(js_name = "createUploadSession")]
+    pub async fn create_upload_session(&self, url: &str) -> Result<String, JsValue> {
+        self.inner_upload(url)?
+            .create_session()
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// Upload a large payload in parts, retrying failed parts.
+    ///
+    /// - `partSize`: Size of each part in bytes, defaults to 8 MiB.
+    /// - `maxRetries`: How many times a failed part is retried, defaults to 3.
+    /// - `session`: Id of a session created with `createUploadSession` to resume,
+    ///   only uploading the parts the homeserver didn't receive yet.
+    #[wasm_bindgen]
+    pub async fn upload(
+        &self,
+        url: &str,
+        content: &[u8],
+        part_size: Option<usize>,
+        max_retries: Option<u32>,
+        session: Option<String>,
+    ) -> Result<(), JsValue> {
+        let mut builder = self.inner_upload(url)?;
+
+        if let Some(session) = &session {
+            builder = builder.session(session);
+        }
+
+        if let Some(part_size) = part_size {
+            builder = builder.part_size(part_size);
+        }
+        if let Some(max_retries) = max_retries {
+            builder = builder.max_retries(max_retries);
+        }
+
+        builder.send(content).await.map_err(|e| e.into())
+    }
 }
@@ -0,0 +1,29 @@
+use std::pin::Pin;
+
+use futures_util::{Stream, StreamExt};
+use pubky_common::list::ListEntry;
+use wasm_bindgen::prelude::*;
+
+use crate::error::{Error, Result};
+
+/// An auto-paginating stream of directory entries, returned by `PubkyClient.listStream()`.
+#[wasm_bindgen]
+pub struct ListStream(pub(crate) Pin<Box<dyn Stream<Item = Result<ListEntry>>>>);
+
+#[wasm_bindgen]
+impl ListStream {
+    /// Returns the next entry as an object with the fields
+    /// `url`, `is_directory`, `content_length`, `content_type`, `last_modified` and `etag`,
+    /// or `undefined` once all entries are listed.
+    #[wasm_bindgen]
+    pub async fn next(&mut self) -> Result<JsValue, JsValue> {
+        match self.0.next().await {
+            Some(entry) => {
+                let json = serde_json::to_string(&entry?).map_err(Error::from)?;
+
+                js_sys::JSON::parse(&json)
+            }
+            None => Ok(JsValue::UNDEFINED),
+        }
+    }
+}